@@ -0,0 +1,836 @@
+/// Translation module between DAWPL arrangements and ABC notation, the plain
+/// text format most folk tune collections are written in. Every voice of a
+/// tune becomes a track. Clip names are written as "^name" annotations above
+/// the first note of each clip; tunes without them are read as one clip per
+/// voice, named after the title of the tune. Repeats (with first and second
+/// endings), broken rhythms and tuplets are expanded when reading, while
+/// decorations, grace notes, chord symbols and lyrics are ignored. Tuplets
+/// are written as ABC tuplets e.g. (3 for a triplet.
+use arrangement::*;
+use chord::*;
+use duration::*;
+use notation::*;
+use note::*;
+use scale::*;
+use track::*;
+use synth::*;
+use std::collections::HashMap;
+
+const BARS_PER_LINE: usize = 4;
+// written note lengths are multiples of an eighth note (L:1/8)
+const UNIT_LENGTH: u32 = 8;
+
+macro_rules! k_abc_header_template {
+    () => ("X:{index}
+T:{title}
+M:{beats}/{beat_value}
+L:1/{unit_length}
+K:{key}
+")
+}
+
+pub fn arrangement_to_abc(arrangement: &Arrangement, title: &str)
+    -> Result<String, String> {
+    let key = notation_key(arrangement);
+    let (beats, beat_value) = arrangement.get_time_signature();
+    let tracks = arrangement.get_tracks_ref();
+    let tracks_items: Vec<Vec<NotationItem>> = tracks.iter()
+        .map(|track| notate_track(arrangement, track))
+        .collect::<Result<_, _>>()?;
+    let bass_clef = |items: &[NotationItem]| if needs_bass_clef(items) {
+        " clef=bass" } else { "" };
+    // a single voice is written without voice fields
+    let key_field = match tracks_items.len() {
+        1 => format!("{}{}", key_to_abc(&key), bass_clef(&tracks_items[0])),
+        _ => key_to_abc(&key),
+    };
+    let mut abc = format!(k_abc_header_template!(), index=1, title=title,
+        beats=beats, beat_value=beat_value, unit_length=UNIT_LENGTH,
+        key=key_field);
+    for (track, items) in tracks.iter().zip(tracks_items.iter()) {
+        if tracks.len() > 1 {
+            abc += &format!("V:{}{}\n", voice_id(track.get_name()),
+                bass_clef(items));
+        }
+        abc += &items_to_abc(items, &key);
+    }
+    Ok(abc)
+}
+
+/// Voice ids cannot contain whitespace
+fn voice_id(track_name: &str) -> String {
+    track_name.split_whitespace().collect::<Vec<&str>>().join("_")
+}
+
+/// Returns the ABC key field of a scale e.g. "D", "Bbmix" or "F#m"
+fn key_to_abc(key: &Scale) -> String {
+    let mode = match key.get_scale_type() {
+        ScaleType::Minor => "m",
+        ScaleType::Dorian => "dor",
+        ScaleType::Mixolydian => "mix",
+        ScaleType::Lydian => "lyd",
+        ScaleType::Phrygian => "phr",
+        ScaleType::Locrian => "loc",
+        _ => "",
+    };
+    let tonic = key.get_base_note();
+    format!("{:?}{}{}", tonic.get_letter(), tonic.get_accidental(), mode)
+}
+
+fn chord_to_abc(chord: &Chord) -> String {
+    let suffix = match chord.get_chord_type() {
+        ChordType::Maj7 => "maj7",
+        ChordType::Min7 => "m7",
+        ChordType::Dom7 => "7",
+        ChordType::Dim => "dim",
+        ChordType::Aug => "aug",
+        ChordType::Maj6 => "6",
+    };
+    let root = chord.get_root();
+    format!("{:?}{}{}", root.get_letter(), root.get_accidental(), suffix)
+}
+
+/// Returns the accidental of every letter (C to B) in a key signature with
+/// the given number of sharps (positive) or flats (negative)
+fn signature_accidentals(fifths: i8) -> [Accidental; NUM_LETTERS as usize] {
+    // sharps are added in this order, flats in the reverse order
+    let order = [Letter::F, Letter::C, Letter::G, Letter::D, Letter::A,
+        Letter::E, Letter::B];
+    let mut accidentals = [Accidental::Natural; NUM_LETTERS as usize];
+    for i in 0..fifths.unsigned_abs().min(NUM_LETTERS) as usize {
+        if fifths > 0 {
+            accidentals[order[i].index() as usize] = Accidental::Sharp;
+        } else {
+            accidentals[order[order.len() - 1 - i].index() as usize] =
+                Accidental::Flat;
+        }
+    }
+    accidentals
+}
+
+/// Returns the ABC name of a note e.g. ^F for F#4, c for C5 and _B, for Bb3.
+/// Accidentals are only written where the key signature and the accidentals
+/// earlier in the bar do not already imply them.
+fn note_to_abc(note: &Note, signature: &[Accidental; NUM_LETTERS as usize],
+    bar_accidentals: &mut HashMap<(Letter, i8), Accidental>) -> String {
+    let letter = note.get_letter();
+    let octave = note.get_octave();
+    let implied = bar_accidentals.get(&(letter, octave)).cloned()
+        .unwrap_or(signature[letter.index() as usize]);
+    let accidental = if note.get_accidental() == implied {
+        ""
+    } else {
+        bar_accidentals.insert((letter, octave), note.get_accidental());
+        match note.get_accidental() {
+            Accidental::DoubleFlat => "__",
+            Accidental::Flat => "_",
+            Accidental::Natural => "=",
+            Accidental::Sharp => "^",
+            Accidental::DoubleSharp => "^^",
+        }
+    };
+    // upper case letters are the octave from middle C, lower case the next
+    let name = format!("{:?}", letter);
+    if octave >= 5 {
+        format!("{}{}{}", accidental, name.to_lowercase(),
+            "'".repeat((octave - 5) as usize))
+    } else {
+        format!("{}{}{}", accidental, name, ",".repeat((4 - octave) as usize))
+    }
+}
+
+/// Returns the ABC length (in eighth notes) of a note value length e.g. ""
+/// for an eighth note, "3" for a dotted quarter and "/" for a sixteenth
+fn length_to_abc(length: Duration) -> String {
+    let units = length * UNIT_LENGTH as u64;
+    let (numerator, denominator) = (units.get_numerator(),
+        units.get_denominator());
+    match (numerator, denominator) {
+        (1, 1) => "".into(),
+        (_, 1) => format!("{}", numerator),
+        (1, 2) => "/".into(),
+        (1, _) => format!("/{}", denominator),
+        _ => format!("{}/{}", numerator, denominator),
+    }
+}
+
+/// Returns the ABC tuplet of the given number of events, each a note of a
+/// tuplet of the given number of notes in the time of the given space e.g.
+/// "(3" for a triplet and "(5:4:3" for three notes of a quintuplet. The
+/// space is only left out where every meter implies it.
+fn tuplet_to_abc(notes: u32, space: u32, events: usize) -> String {
+    let implied = match notes {
+        3 | 6 => Some(2),
+        2 | 4 | 8 => Some(3),
+        _ => None,
+    };
+    if implied == Some(space) && events == notes as usize {
+        format!("({}", notes)
+    } else {
+        format!("({}:{}:{}", notes, space, events)
+    }
+}
+
+fn items_to_abc(items: &[NotationItem], key: &Scale) -> String {
+    let signature = signature_accidentals(key.key_signature().unwrap_or(0));
+    let mut bar_accidentals: HashMap<(Letter, i8), Accidental> =
+        HashMap::new();
+    let mut music: String = "".into();
+    let mut num_bars = 0;
+    for item in items.iter() {
+        match *item {
+            NotationItem::ClipStart(ref name, _) => {
+                music += &format!("\"^{}\"", name.replace('"', "'"));
+            },
+            NotationItem::Event {ref notes, ref value, tie_start, ref chord,
+                ..} => {
+                if let Some(ref chord) = *chord {
+                    music += &format!("\"{}\"", chord_to_abc(chord));
+                }
+                let pitch = match *notes {
+                    Some(ref notes) => {
+                        let pitches: Vec<String> = notes.iter()
+                            .map(|note| note_to_abc(note, &signature,
+                                &mut bar_accidentals)).collect();
+                        if pitches.len() == 1 {
+                            pitches[0].clone()
+                        } else {
+                            format!("[{}]", pitches.concat())
+                        }
+                    },
+                    None => "z".into(),
+                };
+                let tie = if tie_start { "-" } else { "" };
+                music += &format!("{}{}{} ", pitch,
+                    length_to_abc(value.written_length()), tie);
+            },
+            NotationItem::Omitted(ref name) => {
+                music += &format!("\n% clip {} omitted\n", name);
+            },
+            NotationItem::BarLine => {
+                bar_accidentals.clear();
+                num_bars += 1;
+                music += if num_bars % BARS_PER_LINE == 0 { "|\n" }
+                    else { "| " };
+            },
+            NotationItem::TupletStart(notes, space, events) => {
+                music += &tuplet_to_abc(notes, space, events);
+            },
+            NotationItem::TupletEnd => {},
+        }
+    }
+    let music = music.trim_end();
+    if music.ends_with('|') {
+        format!("{}]\n", music)
+    } else {
+        format!("{} |]\n", music)
+    }
+}
+
+/// A symbol of a voice in the order written, before repeats are expanded
+#[derive(Clone)]
+enum Symbol {
+    /// Notes (none for a rest), duration, and whether the notes are tied to
+    /// the next event
+    Event(Option<Vec<Note>>, Duration, bool),
+    ClipStart(String),
+    RepeatStart,
+    RepeatEnd,
+    Ending(u32),
+}
+
+/// Reads the tunes of an ABC file into arrangements, one per tune. Tunes start
+/// with an X: field, text without any X: field is read as a single tune. The
+/// notes of every clip are played by the given instrument.
+pub fn abc_to_arrangements(abc: &str, instrument: &InstrumentHandle)
+    -> Result<Vec<Arrangement>, String> {
+    let mut tunes: Vec<Vec<&str>> = Vec::new();
+    let mut tune: Option<Vec<&str>> = None;
+    let has_index = abc.lines().any(|line| line.starts_with("X:"));
+    for line in abc.lines() {
+        if line.starts_with("X:") {
+            tunes.extend(tune.take());
+            tune = Some(vec![line]);
+        } else if line.trim().is_empty() && has_index {
+            // tunes are separated by blank lines
+            tunes.extend(tune.take());
+        } else if let Some(ref mut tune) = tune {
+            tune.push(line);
+        } else if !has_index {
+            tune = Some(vec![line]);
+        }
+    }
+    tunes.extend(tune.take());
+    tunes.iter().enumerate().map(|(i, lines)| {
+        parse_tune(lines, i + 1, instrument)
+            .map_err(|error| format!("Tune {}: {}", i + 1, error))
+    }).collect()
+}
+
+/// Reads the first tune of an ABC file into an arrangement
+pub fn abc_to_arrangement(abc: &str, instrument: &InstrumentHandle)
+    -> Result<Arrangement, String> {
+    abc_to_arrangements(abc, instrument)?.into_iter().next()
+        .ok_or_else(|| "No tune found".to_string())
+}
+
+fn parse_tune(lines: &[&str], index: usize,
+    instrument: &InstrumentHandle)
+    -> Result<Arrangement, String> {
+    let mut parser = TuneParser::new();
+    let mut in_header = true;
+    for line in lines.iter() {
+        let line = match line.find('%') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut chars = line.chars();
+        if let (Some(field), Some(':')) = (chars.next(), chars.next()) {
+            if field.is_ascii_alphabetic() {
+                parser.field(field, line[2..].trim(), in_header)?;
+                in_header = in_header && field != 'K';
+                continue;
+            }
+        }
+        // music without a key field starts the body in C major
+        in_header = false;
+        parser.parse_music(line)
+            .map_err(|error| format!("{} in \"{}\"", error, line))?;
+    }
+    Ok(parser.into_arrangement(index, instrument))
+}
+
+/// Reads the fields and music of a tune, keeping the symbols of each voice
+struct TuneParser {
+    title: Option<String>,
+    meter: Option<(u8, u8)>,
+    unit_length: Option<Duration>,
+    key: Option<Scale>,
+    signature: [Accidental; NUM_LETTERS as usize],
+    voices: Vec<(String, Vec<Symbol>)>,
+    voice: usize,
+    // accidentals written earlier in the current bar
+    bar_accidentals: HashMap<(Letter, i8), Accidental>,
+    // length factor of the note after a broken rhythm (> or <)
+    broken_rhythm: Duration,
+    // notes left in the current tuplet and their length factor
+    tuplet: Option<(u32, Duration)>,
+}
+
+impl TuneParser {
+    fn new() -> TuneParser {
+        TuneParser {title: None, meter: None, unit_length: None, key: None,
+            signature: [Accidental::Natural; NUM_LETTERS as usize],
+            voices: Vec::new(), voice: 0, bar_accidentals: HashMap::new(),
+            broken_rhythm: Duration::new(1, 1), tuplet: None}
+    }
+
+    /// The unit note length, which defaults to a sixteenth note in meters
+    /// shorter than 3/4 and to an eighth note otherwise
+    fn unit_length(&self) -> Duration {
+        self.unit_length.unwrap_or(if self.bar_length() < Duration::new(3, 4) {
+            Duration::new(1, 16)
+        } else {
+            Duration::new(1, 8)
+        })
+    }
+
+    fn bar_length(&self) -> Duration {
+        let (beats, beat_value) = self.meter.unwrap_or((4, 4));
+        Duration::new(beats as u64, beat_value as u64)
+    }
+
+    fn symbols(&mut self) -> &mut Vec<Symbol> {
+        if self.voices.is_empty() {
+            self.voices.push(("1".into(), Vec::new()));
+        }
+        &mut self.voices[self.voice].1
+    }
+
+    fn field(&mut self, field: char, value: &str, in_header: bool)
+        -> Result<(), String> {
+        match field {
+            'T' => {
+                self.title = self.title.take().or_else(|| Some(value.into()));
+            },
+            'L' => {
+                let unit_length = parse_fraction(value).ok_or_else(||
+                    format!("Invalid unit note length: {}", value))?;
+                self.unit_length = Some(unit_length);
+            },
+            'M' => {
+                let meter = parse_meter(value)
+                    .ok_or_else(|| format!("Invalid meter: {}", value))?;
+                self.meter = meter.or(self.meter);
+            },
+            'K' => {
+                let key = parse_key(value);
+                self.signature = signature_accidentals(key.map_or(0, |key|
+                    key.key_signature().unwrap_or(0)));
+                self.key = self.key.or(key);
+                self.bar_accidentals.clear();
+            },
+            'V' => {
+                let id = value.split_whitespace().next()
+                    .ok_or_else(|| "Missing voice id".to_string())?;
+                let existing = self.voices.iter()
+                    .position(|voice| voice.0 == id);
+                let position = existing.unwrap_or_else(|| {
+                    self.voices.push((id.into(), Vec::new()));
+                    self.voices.len() - 1
+                });
+                // voices defined in the header are only switched to in the body
+                if !in_header {
+                    self.voice = position;
+                    self.bar_accidentals.clear();
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn parse_music(&mut self, line: &str) -> Result<(), String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '"' => {
+                    let end = find(&chars, i + 1, '"')
+                        .ok_or_else(|| "Unterminated annotation".to_string())?;
+                    let text: String = chars[i + 1..end].iter().collect();
+                    // "^text" annotations start clips, chord symbols are
+                    // ignored
+                    if text.starts_with('^') && !text[1..].trim().is_empty() {
+                        self.symbols().push(Symbol::ClipStart(
+                            text[1..].trim().into()));
+                    }
+                    i = end + 1;
+                },
+                '!' | '+' => {
+                    // decorations e.g. !trill!
+                    i = find(&chars, i + 1, chars[i]).unwrap_or(i) + 1;
+                },
+                '{' => {
+                    // grace notes
+                    i = find(&chars, i + 1, '}')
+                        .ok_or_else(|| "Unterminated grace notes".to_string())?
+                        + 1;
+                },
+                '[' => i = self.parse_bracket(&chars, i)?,
+                '|' | ':' => i = self.parse_bar_line(&chars, i)?,
+                '(' => {
+                    i += 1;
+                    if i < chars.len() && chars[i].is_ascii_digit() {
+                        i = self.parse_tuplet(&chars, i)?;
+                    }
+                },
+                '>' | '<' => {
+                    let symbol = chars[i];
+                    let mut num = 0;
+                    while i < chars.len() && chars[i] == symbol {
+                        num += 1;
+                        i += 1;
+                    }
+                    let short = Duration::new(1, 1 << num.min(32));
+                    let long = Duration::new(2, 1) - short;
+                    let (before, after) = if symbol == '>' {
+                        (long, short)
+                    } else {
+                        (short, long)
+                    };
+                    if let Some(&mut Symbol::Event(_, ref mut duration, _)) =
+                        self.last_event() {
+                        *duration = *duration * before;
+                    }
+                    self.broken_rhythm = after;
+                },
+                '-' => {
+                    if let Some(&mut Symbol::Event(_, _, ref mut tie)) =
+                        self.last_event() {
+                        *tie = true;
+                    }
+                    i += 1;
+                },
+                'z' | 'x' => {
+                    i += 1;
+                    let length = parse_length(&chars, &mut i)?;
+                    self.push_event(None, length);
+                },
+                'Z' => {
+                    // multi-measure rest
+                    i += 1;
+                    let num_bars = parse_number(&chars, &mut i)?
+                        .unwrap_or(1);
+                    let duration = self.bar_length() * num_bars as u64;
+                    self.symbols().push(Symbol::Event(None, duration, false));
+                },
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    let (note, length) = self.parse_note(&chars, &mut i)?;
+                    self.push_event(Some(vec![note]), length);
+                },
+                // spaces, slur ends, decorations such as ~ and . etc.
+                _ => i += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn last_event(&mut self) -> Option<&mut Symbol> {
+        self.symbols().iter_mut().rev()
+            .find(|symbol| matches!(**symbol, Symbol::Event(..)))
+    }
+
+    /// Adds an event given its length in unit note lengths
+    fn push_event(&mut self, notes: Option<Vec<Note>>, length: Duration) {
+        let mut duration = length * self.unit_length() * self.broken_rhythm;
+        self.broken_rhythm = Duration::new(1, 1);
+        if let Some((num_notes, factor)) = self.tuplet {
+            duration = duration * factor;
+            self.tuplet = if num_notes > 1 {
+                Some((num_notes - 1, factor))
+            } else {
+                None
+            };
+        }
+        self.symbols().push(Symbol::Event(notes, duration, false));
+    }
+
+    /// Parses a note e.g. ^f'3/2, returning the note as spelled and its
+    /// length in unit note lengths
+    fn parse_note(&mut self, chars: &[char], i: &mut usize)
+        -> Result<(Note, Duration), String> {
+        let mut alteration: Option<i8> = None;
+        while *i < chars.len() && "^_=".contains(chars[*i]) {
+            let step = match chars[*i] {
+                '^' => 1,
+                '_' => -1,
+                _ => 0,
+            };
+            alteration = Some(alteration.unwrap_or(0) + step);
+            *i += 1;
+        }
+        let letter_char = match chars.get(*i) {
+            Some(c) if "ABCDEFGabcdefg".contains(*c) => *c,
+            _ => return Err("Accidental without a note".into()),
+        };
+        *i += 1;
+        let letter: Letter = letter_char.to_ascii_uppercase().to_string()
+            .parse().unwrap();
+        let mut octave: i8 = if letter_char.is_ascii_uppercase() { 4 }
+            else { 5 };
+        while *i < chars.len() && (chars[*i] == '\'' || chars[*i] == ',') {
+            octave = octave.checked_add(if chars[*i] == '\'' { 1 } else { -1 })
+                .ok_or_else(|| format!("Note outside of the MIDI range: {}",
+                    letter_char))?;
+            *i += 1;
+        }
+        let accidental = match alteration {
+            Some(alteration) => {
+                let accidental = Accidental::from_half_steps(alteration)
+                    .ok_or_else(|| "Too many accidentals".to_string())?;
+                self.bar_accidentals.insert((letter, octave), accidental);
+                accidental
+            },
+            None => self.bar_accidentals.get(&(letter, octave)).cloned()
+                .unwrap_or(self.signature[letter.index() as usize]),
+        };
+        let midi = (octave as i32 + 1) * NUM_TONES as i32
+            + letter.half_steps() as i32 + accidental.half_steps() as i32;
+        if midi < 0 || midi >= MIDI_NUM as i32 {
+            return Err(format!("Note outside of the MIDI range: {}",
+                letter_char));
+        }
+        let length = parse_length(chars, i)?;
+        Ok((Note::spelled(letter, accidental, octave), length))
+    }
+
+    /// Parses what follows a [ : a chord, an inline field, an ending or a bar
+    /// line. Returns the position after it.
+    fn parse_bracket(&mut self, chars: &[char], start: usize)
+        -> Result<usize, String> {
+        let mut i = start + 1;
+        match (chars.get(i), chars.get(i + 1)) {
+            (Some(field), Some(':')) if field.is_ascii_alphabetic() => {
+                let end = find(chars, i, ']')
+                    .ok_or_else(|| "Unterminated inline field".to_string())?;
+                let value: String = chars[i + 2..end].iter().collect();
+                self.field(*field, value.trim(), false)?;
+                Ok(end + 1)
+            },
+            (Some(c), _) if c.is_ascii_digit() => {
+                let ending = parse_number(chars, &mut i)?
+                    .ok_or_else(|| "Invalid ending".to_string())?;
+                self.symbols().push(Symbol::Ending(ending));
+                Ok(i)
+            },
+            (Some('|'), _) => self.parse_bar_line(chars, i),
+            _ => {
+                let mut notes: Vec<Note> = Vec::new();
+                let mut note_length: Option<Duration> = None;
+                while i < chars.len() && chars[i] != ']' {
+                    if "^_=ABCDEFGabcdefg".contains(chars[i]) {
+                        let (note, length) = self.parse_note(chars, &mut i)?;
+                        if !notes.contains(&note) {
+                            notes.push(note);
+                        }
+                        note_length = note_length.or(Some(length));
+                    } else {
+                        i += 1;
+                    }
+                }
+                if i == chars.len() {
+                    return Err("Unterminated chord".into());
+                }
+                i += 1;
+                // the chord length multiplies the length of its first note
+                let length = note_length.unwrap_or_else(||
+                    Duration::new(1, 1)) * parse_length(chars, &mut i)?;
+                let notes = if notes.is_empty() { None } else { Some(notes) };
+                self.push_event(notes, length);
+                Ok(i)
+            },
+        }
+    }
+
+    /// Parses bar lines and repeat signs e.g. |, ||, |], |:, :|, :: and :|2
+    fn parse_bar_line(&mut self, chars: &[char], start: usize)
+        -> Result<usize, String> {
+        let mut i = start;
+        while i < chars.len() && "|:]".contains(chars[i]) {
+            i += 1;
+        }
+        let bar_line: String = chars[start..i].iter().collect();
+        if bar_line.starts_with(':') && bar_line.len() > 1 {
+            self.symbols().push(Symbol::RepeatEnd);
+        }
+        if bar_line.ends_with(':') && bar_line.len() > 1 {
+            self.symbols().push(Symbol::RepeatStart);
+        }
+        if let Some(ending) = parse_number(chars, &mut i)? {
+            self.symbols().push(Symbol::Ending(ending));
+        }
+        self.bar_accidentals.clear();
+        Ok(i)
+    }
+
+    /// Parses a tuplet (p:q:r, in which the next r notes (p by default) are
+    /// played in the time of q
+    fn parse_tuplet(&mut self, chars: &[char], start: usize)
+        -> Result<usize, String> {
+        let mut i = start;
+        let p = parse_number(chars, &mut i)?
+            .ok_or_else(|| "Invalid tuplet".to_string())?;
+        let mut q: Option<u32> = None;
+        let mut r: Option<u32> = None;
+        if chars.get(i) == Some(&':') {
+            i += 1;
+            q = parse_number(chars, &mut i)?;
+            if chars.get(i) == Some(&':') {
+                i += 1;
+                r = parse_number(chars, &mut i)?;
+            }
+        }
+        let (beats, _) = self.meter.unwrap_or((4, 4));
+        let compound = beats % 3 == 0 && beats > 3;
+        let q = q.unwrap_or(match p {
+            3 | 6 => 2,
+            2 | 4 | 8 => 3,
+            _ if compound => 3,
+            _ => 2,
+        });
+        if p > 0 {
+            self.tuplet = Some((r.unwrap_or(p), Duration::new(q as u64,
+                p as u64)));
+        }
+        Ok(i)
+    }
+
+    fn into_arrangement(self, index: usize, instrument: &InstrumentHandle)
+        -> Arrangement {
+        let title: String = self.title.unwrap_or_default().to_lowercase()
+            .chars().map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let title = match title.trim_matches('_') {
+            "" => format!("tune{}", index),
+            title => title.into(),
+        };
+        let multiple_voices = self.voices.len() > 1;
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut clips = Vec::new();
+        for (id, symbols) in self.voices.into_iter() {
+            let default_name = if multiple_voices {
+                format!("{}_{}", title, id)
+            } else {
+                title.clone()
+            };
+            let mut builders: Vec<ClipBuilder> = Vec::new();
+            let mut tied = false;
+            for symbol in expand_repeats(&symbols).into_iter() {
+                match symbol {
+                    Symbol::ClipStart(name) => {
+                        builders.push(ClipBuilder::new(name));
+                        tied = false;
+                    },
+                    Symbol::Event(notes, duration, tie) => {
+                        if builders.is_empty() {
+                            builders.push(ClipBuilder::new(
+                                default_name.clone()));
+                        }
+                        let builder = builders.last_mut().unwrap();
+                        if tied && notes.is_some() &&
+                            builder.melody.last() == Some(&notes) {
+                            *builder.durations.last_mut().unwrap() += duration;
+                        } else {
+                            builder.melody.push(notes);
+                            builder.durations.push(duration);
+                        }
+                        tied = tie;
+                    },
+                    _ => {},
+                }
+            }
+            let mut track = Track::new(id, Vec::new());
+            for builder in builders.into_iter() {
+                track.append_clip(add_clip(&mut clips,
+                    builder.build(instrument)));
+            }
+            tracks.push(track);
+        }
+        let mut arrangement = Arrangement::new(tracks, clips);
+        if let Some(key) = self.key {
+            arrangement.set_key(key);
+        }
+        if let Some((beats, beat_value)) = self.meter {
+            arrangement.set_time_signature(beats, beat_value);
+        }
+        arrangement
+    }
+}
+
+/// Plays repeated sections twice, skipping first endings on the way back
+fn expand_repeats(symbols: &[Symbol]) -> Vec<Symbol> {
+    let mut expanded: Vec<Symbol> = Vec::new();
+    let mut section_start = 0;
+    let mut repeating = false;
+    let mut skipping = false;
+    let mut i = 0;
+    while i < symbols.len() {
+        match symbols[i] {
+            Symbol::RepeatStart => section_start = i + 1,
+            Symbol::RepeatEnd if !repeating => {
+                repeating = true;
+                i = section_start;
+                continue;
+            },
+            Symbol::RepeatEnd => {
+                repeating = false;
+                skipping = false;
+                section_start = i + 1;
+            },
+            Symbol::Ending(ending) => skipping = repeating && ending == 1,
+            ref symbol => if !skipping {
+                expanded.push(symbol.clone());
+            },
+        }
+        i += 1;
+    }
+    expanded
+}
+
+fn find(chars: &[char], start: usize, c: char) -> Option<usize> {
+    chars[start.min(chars.len())..].iter().position(|other| *other == c)
+        .map(|position| start + position)
+}
+
+/// Parses the number at a position, if there is one, moving past it. Numbers
+/// too large to read give an error.
+fn parse_number(chars: &[char], i: &mut usize) -> Result<Option<u32>, String> {
+    let start = *i;
+    while *i < chars.len() && chars[*i].is_ascii_digit() {
+        *i += 1;
+    }
+    if start == *i {
+        return Ok(None);
+    }
+    let digits: String = chars[start..*i].iter().collect();
+    digits.parse().map(Some)
+        .map_err(|_| format!("Number too large: {}", digits))
+}
+
+/// Parses a note length multiplier e.g. 3, /2, 3/2, / or //
+fn parse_length(chars: &[char], i: &mut usize)
+    -> Result<Duration, String> {
+    let numerator = parse_number(chars, i)?.unwrap_or(1);
+    let mut denominator: u32 = 1;
+    while chars.get(*i) == Some(&'/') {
+        *i += 1;
+        denominator = denominator.checked_mul(parse_number(chars, i)?
+            .unwrap_or(2)).ok_or_else(|| "Invalid note length".to_string())?;
+    }
+    if numerator == 0 || denominator == 0 {
+        return Err("Invalid note length".into());
+    }
+    Ok(Duration::new(numerator as u64, denominator as u64))
+}
+
+fn parse_fraction(value: &str) -> Option<Duration> {
+    value.parse::<Duration>().ok().filter(|fraction| !fraction.is_zero())
+}
+
+/// Parses a meter e.g. 6/8, C (common time), C| (cut time), 2+3/8 or none
+fn parse_meter(value: &str) -> Option<Option<(u8, u8)>> {
+    match value {
+        "C" => return Some(Some((4, 4))),
+        "C|" => return Some(Some((2, 2))),
+        "none" | "" => return Some(None),
+        _ => {},
+    }
+    let mut parts = value.split('/');
+    let beats = parts.next()?.split('+')
+        .map(|beats| beats.trim().parse::<u8>().ok())
+        .sum::<Option<u8>>()?;
+    let beat_value: u8 = parts.next()?.trim().parse().ok()?;
+    if parts.next().is_some() || beats == 0 || beat_value == 0 {
+        return None;
+    }
+    Some(Some((beats, beat_value)))
+}
+
+/// Parses a key e.g. G, Bb, F#m, Ador or "D mix", returning None for tunes
+/// without a key (K:none)
+fn parse_key(value: &str) -> Option<Scale> {
+    let mut chars = value.chars().peekable();
+    let letter: Letter = match chars.next() {
+        Some(letter) if "ABCDEFG".contains(letter) =>
+            letter.to_string().parse().unwrap(),
+        // highland pipe keys and key signatures without a tonic
+        _ => return None,
+    };
+    let accidental = match chars.peek() {
+        Some('#') => Accidental::Sharp,
+        Some('b') => Accidental::Flat,
+        _ => Accidental::Natural,
+    };
+    if accidental != Accidental::Natural {
+        chars.next();
+    }
+    let mode: String = chars.skip_while(|c| c.is_whitespace())
+        .take_while(|c| c.is_ascii_alphabetic()).collect::<String>()
+        .to_lowercase();
+    let scale_type = match &mode[..mode.len().min(3)] {
+        "" | "maj" | "ion" => ScaleType::Major,
+        "m" | "min" | "aeo" => ScaleType::Minor,
+        "mix" => ScaleType::Mixolydian,
+        "dor" => ScaleType::Dorian,
+        "phr" => ScaleType::Phrygian,
+        "lyd" => ScaleType::Lydian,
+        "loc" => ScaleType::Locrian,
+        // other words such as clef=bass follow a major key
+        _ => ScaleType::Major,
+    };
+    Some(Scale::new(Note::spelled(letter, accidental, 4), scale_type))
+}
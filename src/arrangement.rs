@@ -1,30 +1,260 @@
 /// This file defines the arrangement interface. An arrangement is determined by
-/// a vector of tracks (which are all currently mixed together) as well as a 
-/// vector of clips whose names correspond to the symbolic names used within the
-/// tracks. Future improvements may include allowing for individual volume
-/// control (e.g. via a multiplier or limit of some sort) across different
-/// tracks.
+/// a vector of tracks (which are all mixed together) as well as a vector of
+/// clips whose names correspond to the symbolic names used within the tracks.
+/// The volume and pan of each track, and the parameters of its effects and
+/// synths, can change over time with automation lanes (see automation.rs),
+/// and instrument clips can be swung, grooved and humanized (see groove.rs).
+use automation::*;
 use chord::*;
 use clip::*;
+use duration::*;
+use effects::*;
+use groove::*;
 use note::*;
 use scale::*;
 use syntax::*;
 use track::*;
 use translate::*;
+use tuning::*;
 
+/// Tempo of arrangements that do not set one, in quarter notes per minute
+pub const DEFAULT_TEMPO: f64 = 120.0;
+/// Velocity of the notes of instrument clips, and of drum hits that do not
+/// set one
+pub const DEFAULT_VELOCITY: u8 = 100;
 
+/// A note of an arrangement placed in time, with its start from the start of
+/// the arrangement
+#[derive(Debug, PartialEq, Clone)]
+pub struct TimedNote {
+    /// Index of the track that plays the note
+    pub track: usize,
+    /// Name of the clip the note is in
+    pub clip: String,
+    pub instrument: String,
+    /// The note as it is spelled and detuned in the clip, or the key of a
+    /// drum hit spelled the default way
+    pub pitch: Note,
+    pub start: Time,
+    pub duration: Duration,
+    /// 1 to 127
+    pub velocity: u8,
+}
 
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize),
+    serde(default))]
 pub struct Arrangement {
     /// All tracks that comprise the arrangement
     tracks: Vec<Track>,
     /// All clips used within a track
     clips: Vec<Clip>,
-    // TODO: add volumes/combination effects
+    /// Tuning that decides the frequencies of all notes in the arrangement
+    #[cfg_attr(feature = "serialize",
+        serde(deserialize_with = "deserialize_valid"))]
+    tuning: Tuning,
+    /// Frequency of A4 in hertz, the reference pitch of the tuning
+    concert_pitch: f64,
+    /// Key of the arrangement, used to spell notes in notation
+    key: Option<Scale>,
+    /// Beats per bar and the note value of a beat e.g. (6, 8)
+    time_signature: (u8, u8),
+    /// Quarter notes per minute
+    tempo: f64,
+    /// Effects on clips, tracks, groups, returns and the master, at most one
+    /// chain each
+    effects: Vec<EffectChain>,
+    /// Groups of tracks mixed together before the master
+    groups: Vec<TrackGroup>,
+    /// Names of the returns tracks can send to
+    returns: Vec<String>,
+    sends: Vec<AuxSend>,
+    /// Automation lanes, at most one for each target
+    automation: Vec<AutomationLane>,
+    /// Transforms of instrument clips, at most one list for each clip
+    transforms: Vec<ClipTransforms>,
+}
+
+/// An arrangement without tracks or clips, with the default tuning, concert
+/// pitch and time signature
+impl Default for Arrangement {
+    fn default() -> Arrangement {
+        Arrangement::new(Vec::new(), Vec::new())
+    }
 }
 
 impl Arrangement {
     pub fn new(tracks: Vec<Track>, clips: Vec<Clip>) -> Arrangement {
-        Arrangement {tracks, clips}
+        Arrangement {tracks, clips, tuning: Tuning::default(),
+            concert_pitch: CONCERT_A, key: None, time_signature: (4, 4),
+            tempo: DEFAULT_TEMPO, effects: Vec::new(), groups: Vec::new(),
+            returns: Vec::new(), sends: Vec::new(), automation: Vec::new(),
+            transforms: Vec::new()}
+    }
+
+    pub fn set_key(&mut self, key: Scale) {
+        self.key = Some(key);
+    }
+
+    pub fn get_key(&self) -> Option<Scale> {
+        self.key
+    }
+
+    pub fn set_time_signature(&mut self, beats: u8, beat_value: u8) {
+        self.time_signature = (beats, beat_value);
+    }
+
+    pub fn get_time_signature(&self) -> (u8, u8) {
+        self.time_signature
+    }
+
+    pub fn get_bar_length(&self) -> Duration {
+        Duration::new(self.time_signature.0 as u64,
+            self.time_signature.1.max(1) as u64)
+    }
+
+    /// Finds the clip with the given name
+    pub fn get_clip(&self, name: &str) -> Option<&Clip> {
+        self.clips.iter().find(|clip| clip.get_name() == name)
+    }
+
+    /// Sets the frequency of A4 e.g. 440 (the default), 432 or 415 (baroque)
+    pub fn set_concert_pitch(&mut self, a4_hz: f64) {
+        self.concert_pitch = a4_hz;
+    }
+
+    pub fn get_concert_pitch(&self) -> f64 {
+        self.concert_pitch
+    }
+
+    /// Sets the tempo in quarter notes per minute
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = tempo;
+    }
+
+    pub fn get_tempo(&self) -> f64 {
+        self.tempo
+    }
+
+    /// Length of a whole note in seconds at the tempo of the arrangement
+    pub fn get_whole_note_seconds(&self) -> f64 {
+        240.0 / self.tempo
+    }
+
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.tuning = tuning;
+    }
+
+    pub fn get_tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    /// Puts effects on a clip, track or the master, replacing any effects it
+    /// had. No effects removes them.
+    pub fn set_effects(&mut self, target: EffectTarget, effects: Vec<Effect>) {
+        self.effects.retain(|chain| chain.target != target);
+        if !effects.is_empty() {
+            self.effects.push(EffectChain {target, effects});
+        }
+    }
+
+    /// Effects on a clip, track or the master, in the order they are applied
+    pub fn get_effects(&self, target: &EffectTarget) -> &[Effect] {
+        self.effects.iter().find(|chain| &chain.target == target)
+            .map_or(&[], |chain| &chain.effects[..])
+    }
+
+    pub fn get_effect_chains(&self) -> &Vec<EffectChain> {
+        &self.effects
+    }
+
+    /// Puts tracks in a group, replacing any group with the same name. Tracks
+    /// are taken out of any other group they were in.
+    pub fn set_group(&mut self, name: &str, tracks: Vec<String>) {
+        for group in self.groups.iter_mut() {
+            group.tracks.retain(|track| !tracks.contains(track));
+        }
+        match self.groups.iter().position(|group| group.name == name) {
+            Some(index) => self.groups[index].tracks = tracks,
+            None => self.groups.push(TrackGroup {name: name.into(), tracks}),
+        }
+    }
+
+    pub fn get_groups(&self) -> &Vec<TrackGroup> {
+        &self.groups
+    }
+
+    /// Finds the group a track is in
+    pub fn get_group_of(&self, track: &str) -> Option<&TrackGroup> {
+        self.groups.iter().find(|group|
+            group.tracks.iter().any(|name| name == track))
+    }
+
+    /// Adds a return with the given effects, replacing the effects of any
+    /// return with the same name
+    pub fn set_return(&mut self, name: &str, effects: Vec<Effect>) {
+        if !self.returns.iter().any(|other| other == name) {
+            self.returns.push(name.into());
+        }
+        self.set_effects(EffectTarget::Return(name.into()), effects);
+    }
+
+    pub fn get_returns(&self) -> &Vec<String> {
+        &self.returns
+    }
+
+    /// Sends a track to a return at a level from 0 to 1, replacing any send
+    /// from the track to the return. A level of 0 removes the send.
+    pub fn set_send(&mut self, track: &str, destination: &str, level: f64) {
+        self.sends.retain(|send|
+            send.track != track || send.destination != destination);
+        if level != 0.0 {
+            self.sends.push(AuxSend {track: track.into(),
+                destination: destination.into(), level});
+        }
+    }
+
+    /// The sends of a track, in the order they were made
+    pub fn get_sends(&self, track: &str) -> Vec<&AuxSend> {
+        self.sends.iter().filter(|send| send.track == track).collect()
+    }
+
+    pub fn get_all_sends(&self) -> &Vec<AuxSend> {
+        &self.sends
+    }
+
+    /// Automates a target with breakpoints, which are put in order of time,
+    /// replacing any lane of the target. No breakpoints removes the lane.
+    pub fn set_automation(&mut self, target: AutomationTarget,
+        mut breakpoints: Vec<Breakpoint>) {
+        self.automation.retain(|lane| lane.target != target);
+        if !breakpoints.is_empty() {
+            breakpoints.sort_by_key(|breakpoint| breakpoint.time);
+            self.automation.push(AutomationLane {target, breakpoints});
+        }
+    }
+
+    pub fn get_automation(&self, target: &AutomationTarget)
+        -> Option<&AutomationLane> {
+        self.automation.iter().find(|lane| &lane.target == target)
+    }
+
+    pub fn get_automation_lanes(&self) -> &Vec<AutomationLane> {
+        &self.automation
+    }
+
+    /// Puts transforms on an instrument clip, in the order they apply,
+    /// replacing any transforms it had. No transforms removes them.
+    pub fn set_transforms(&mut self, clip: &str, transforms: Vec<Transform>) {
+        self.transforms.retain(|other| other.clip != clip);
+        if !transforms.is_empty() {
+            self.transforms.push(ClipTransforms {clip: clip.into(),
+                transforms});
+        }
+    }
+
+    pub fn get_transforms(&self, clip: &str) -> &[Transform] {
+        self.transforms.iter().find(|other| other.clip == clip)
+            .map_or(&[], |other| &other.transforms[..])
     }
 
     // Obtain a vector of all the names of tracks and clips
@@ -46,5 +276,258 @@ impl Arrangement {
     pub fn get_tracks_ref(&self) -> &Vec<Track> {
         &self.tracks
     }
+
+    /// Adds a clip, replacing any clip with the same name
+    pub fn set_clip(&mut self, clip: Clip) {
+        match self.clips.iter().position(|other|
+            other.get_name() == clip.get_name()) {
+            Some(index) => self.clips[index] = clip,
+            None => self.clips.push(clip),
+        }
+    }
+
+    /// Adds a track, replacing any track with the same name
+    pub fn set_track(&mut self, track: Track) {
+        match self.tracks.iter().position(|other|
+            other.get_name() == track.get_name()) {
+            Some(index) => self.tracks[index] = track,
+            None => self.tracks.push(track),
+        }
+    }
+
+    /// Removes the clip or track with the given name. Clips that are still
+    /// used by a track are not removed.
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        if let Some(track) = self.tracks.iter().find(|track|
+            track.get_names_ref().iter().any(|clip_name| clip_name == name)) {
+            return Err(format!("Clip \"{}\" is used by track \"{}\"", name,
+                track.get_name()));
+        }
+        let (clips, tracks) = (self.clips.len(), self.tracks.len());
+        self.clips.retain(|clip| clip.get_name() != name);
+        self.tracks.retain(|track| track.get_name() != name);
+        if self.clips.len() == clips && self.tracks.len() == tracks {
+            return Err(format!("Nothing is named \"{}\"", name));
+        }
+        self.effects.retain(|chain| match chain.target {
+            EffectTarget::Clip(ref target) | EffectTarget::Track(ref target) =>
+                target != name,
+            _ => true,
+        });
+        self.sends.retain(|send| send.track != name);
+        self.transforms.retain(|other| other.clip != name);
+        self.automation.retain(|lane| match lane.target {
+            AutomationTarget::EffectParameter(EffectTarget::Clip(ref target),
+                _, _) | AutomationTarget::EffectParameter(
+                EffectTarget::Track(ref target), _, _) => target != name,
+            ref target => target.get_track()
+                .map_or(true, |track| track != name),
+        });
+        for group in self.groups.iter_mut() {
+            group.tracks.retain(|track| track != name);
+        }
+        Ok(())
+    }
+
+    /// Puts the tracks in the given order, leaving out tracks not named
+    pub fn set_track_order(&mut self, names: &[String]) -> Result<(), String> {
+        let mut tracks: Vec<Track> = Vec::new();
+        for name in names.iter() {
+            let track = self.tracks.iter()
+                .find(|track| track.get_name() == name)
+                .ok_or_else(|| format!("Unknown track \"{}\"", name))?;
+            tracks.push(track.clone());
+        }
+        self.tracks = tracks;
+        Ok(())
+    }
+
+    /// Checks that every clip used by a track exists, that every instrument
+    /// clip has as many durations as notes, that the beat value of the time
+    /// signature is a power of two (as MIDI files can only hold those), that
+    /// the tempo and concert pitch are positive numbers, that the tuning is
+    /// valid, that effects, groups and sends only name clips, tracks, groups
+    /// and returns that exist, that automation lanes automate tracks and
+    /// parameters of effects that exist with breakpoints in order of time,
+    /// and that transforms are valid and only put on instrument clips
+    pub fn validate(&self) -> Result<(), String> {
+        let has_track = |name: &str| self.tracks.iter()
+            .any(|track| track.get_name() == name);
+        let has_return = |name: &str| self.returns.iter()
+            .any(|other| other == name);
+        if !self.time_signature.1.is_power_of_two() {
+            return Err(format!("The time signature has a beat value of {}, \
+                which is not a power of two", self.time_signature.1));
+        }
+        if !(self.tempo.is_finite() && self.tempo > 0.0) {
+            return Err(format!("Invalid tempo {}", self.tempo));
+        }
+        if !(self.concert_pitch.is_finite() && self.concert_pitch > 0.0) {
+            return Err(format!("Invalid concert pitch {}",
+                self.concert_pitch));
+        }
+        self.tuning.validate()?;
+        for chain in self.effects.iter() {
+            let (kind, name) = match chain.target {
+                EffectTarget::Clip(ref name) if self.get_clip(name).is_none() =>
+                    ("clip", name),
+                EffectTarget::Track(ref name) if !has_track(name) =>
+                    ("track", name),
+                EffectTarget::Group(ref name) if !self.groups.iter()
+                    .any(|group| &group.name == name) => ("group", name),
+                EffectTarget::Return(ref name) if !has_return(name) =>
+                    ("return", name),
+                _ => continue,
+            };
+            return Err(format!("Effects are put on an unknown {} \"{}\"",
+                kind, name));
+        }
+        for group in self.groups.iter() {
+            if let Some(track) = group.tracks.iter()
+                .find(|track| !has_track(track)) {
+                return Err(format!("Group \"{}\" has an unknown track \
+                    \"{}\"", group.name, track));
+            }
+        }
+        for send in self.sends.iter() {
+            if !has_track(&send.track) {
+                return Err(format!("Unknown track \"{}\" sends to \"{}\"",
+                    send.track, send.destination));
+            }
+            if !has_return(&send.destination) {
+                return Err(format!("Track \"{}\" sends to an unknown return \
+                    \"{}\"", send.track, send.destination));
+            }
+        }
+        for lane in self.automation.iter() {
+            if let Some(track) = lane.target.get_track() {
+                if !has_track(track) {
+                    return Err(format!("Automation of an unknown track \
+                        \"{}\"", track));
+                }
+            }
+            if let AutomationTarget::EffectParameter(ref target, effect,
+                parameter) = lane.target {
+                let exists = self.get_effects(target).get(effect)
+                    .is_some_and(|effect|
+                        parameter < effect.get_parameters().len());
+                if !exists {
+                    return Err(format!("Automation of parameter {} of \
+                        unknown effect {} of {:?}", parameter, effect,
+                        target));
+                }
+            }
+            if lane.breakpoints.iter().any(|breakpoint|
+                !breakpoint.value.is_finite()) {
+                return Err(format!("Automation of {:?} has an invalid \
+                    breakpoint", lane.target));
+            }
+            if lane.breakpoints.windows(2).any(|pair|
+                pair[0].time > pair[1].time) {
+                return Err(format!("The breakpoints of the automation of \
+                    {:?} are out of order", lane.target));
+            }
+        }
+        for other in self.transforms.iter() {
+            if !matches!(self.get_clip(&other.clip),
+                Some(Clip::Instrument(_, _, _, _))) {
+                return Err(format!("Transforms are put on \"{}\", which is \
+                    not an instrument clip", other.clip));
+            }
+            for transform in other.transforms.iter() {
+                transform.validate().map_err(|error|
+                    format!("Clip \"{}\": {}", other.clip, error))?;
+            }
+        }
+        for track in self.tracks.iter() {
+            for clip_name in track.get_names_ref().iter() {
+                if self.get_clip(clip_name).is_none() {
+                    return Err(format!("Track \"{}\" uses an unknown clip \
+                        \"{}\"", track.get_name(), clip_name));
+                }
+            }
+        }
+        for clip in self.clips.iter() {
+            match *clip {
+                Clip::Instrument(_, _, ref melody, ref durations)
+                    if melody.len() != durations.len() => {
+                    return Err(format!("Clip \"{}\" has {} notes but {} \
+                        durations", clip.get_name(), melody.len(),
+                        durations.len()));
+                },
+                Clip::Drums(ref name, ref pattern) => {
+                    pattern.validate().map_err(|error|
+                        format!("Clip \"{}\": {}", name, error))?;
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists every note played by the arrangement, ordered by track and then
+    /// by start, with the notes of instrument clips where their transforms
+    /// play them and the hits of drum clips as notes one step long on their
+    /// MIDI keys. Tracks play their clips one after another and all tracks
+    /// start together. Audio file clips are not included and take no time,
+    /// since their length is not known until they are read.
+    pub fn get_timed_notes(&self) -> Vec<TimedNote> {
+        let mut notes: Vec<TimedNote> = Vec::new();
+        for (track_index, track) in self.tracks.iter().enumerate() {
+            let mut time = Time::zero();
+            for clip_name in track.get_names_ref().iter() {
+                match self.get_clip(clip_name) {
+                    Some(Clip::Instrument(_, instrument, melody,
+                        durations)) => {
+                        let events = perform_clip(durations,
+                            self.get_transforms(clip_name));
+                        for (pitches, event) in melody.iter()
+                            .zip(events.iter()) {
+                            for pitch in pitches.iter().flatten() {
+                                notes.push(TimedNote {track: track_index,
+                                    clip: clip_name.clone(),
+                                    instrument: instrument.get_name()
+                                        .clone(),
+                                    pitch: *pitch,
+                                    start: time + event.start,
+                                    duration: event.duration,
+                                    velocity: event.velocity});
+                            }
+                        }
+                        time += durations.iter().cloned().sum();
+                    },
+                    Some(&Clip::Empty(_, duration)) => time += duration,
+                    Some(Clip::Drums(_, pattern)) => {
+                        for hit in pattern.get_hits().iter() {
+                            let lane = &pattern.lanes[hit.lane];
+                            notes.push(TimedNote {track: track_index,
+                                clip: clip_name.clone(),
+                                instrument: lane.instrument.get_name()
+                                    .clone(),
+                                pitch: Note::from_midi(lane.key),
+                                start: time + hit.start,
+                                duration: pattern.step,
+                                velocity: hit.velocity});
+                        }
+                        time += pattern.get_length();
+                    },
+                    _ => (),
+                }
+            }
+        }
+        notes
+    }
+
+    /// Length of the arrangement, the length of its longest track
+    pub fn get_length(&self) -> Duration {
+        self.tracks.iter().map(|track| track.get_names_ref().iter()
+            .map(|clip_name| match self.get_clip(clip_name) {
+                Some(Clip::Instrument(_, _, _, durations)) =>
+                    durations.iter().cloned().sum(),
+                Some(&Clip::Empty(_, duration)) => duration,
+                Some(Clip::Drums(_, pattern)) => pattern.get_length(),
+                _ => Duration::zero(),
+            }).sum()).max().unwrap_or_else(Duration::zero)
+    }
 }
 
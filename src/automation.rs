@@ -0,0 +1,159 @@
+/// Automation of an arrangement: lanes of breakpoints that move a track's
+/// volume or pan, a parameter of an effect or a parameter of the synths of a
+/// track over the course of the arrangement, e.g. a filter sweep or a fade.
+/// Breakpoints are placed at exact times from the start of the arrangement,
+/// and the value of a lane is held before its first breakpoint and after its
+/// last.
+///
+/// In SuperCollider every lane plays its envelope (Env) into a control bus,
+/// which the effect synths and the notes of a track read (see translate.rs).
+/// Volume and pan are also written as MIDI controllers (see midi.rs), and the
+/// offline renderer (render.rs) follows every lane but pan.
+use duration::*;
+use effects::*;
+
+/// Controller numbers that volume, pan and the amp parameter of synths are
+/// written as in MIDI files
+const VOLUME_CONTROLLER: u8 = 7;
+const PAN_CONTROLLER: u8 = 10;
+const EXPRESSION_CONTROLLER: u8 = 11;
+
+/// How a lane moves from a breakpoint to the next
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Curve {
+    Linear,
+    /// Even steps in ratio, which sounds even for frequencies. Segments
+    /// between values of different signs or 0 are linear instead.
+    Exponential,
+    /// Holds the value until the next breakpoint, then jumps to it
+    Step,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Breakpoint {
+    /// Time from the start of the arrangement
+    pub time: Time,
+    pub value: f64,
+    /// Curve from this breakpoint to the next
+    pub curve: Curve,
+}
+
+impl Breakpoint {
+    pub fn new(time: Time, value: f64, curve: Curve) -> Breakpoint {
+        Breakpoint {time, value, curve}
+    }
+}
+
+/// What a lane automates
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum AutomationTarget {
+    /// Level of a track, by name, after its effects (1 leaves it unchanged)
+    Volume(String),
+    /// Position of a track, by name, from -1 (left) to 1 (right)
+    Pan(String),
+    /// A parameter of an effect, by where the effect is, its index in the
+    /// chain and the index of the parameter (see Effect::get_parameters)
+    EffectParameter(EffectTarget, usize, usize),
+    /// A parameter of the synths playing the notes of a track, by the name
+    /// of the track and of the parameter e.g. cutoff
+    SynthParameter(String, String),
+}
+
+impl AutomationTarget {
+    /// Name of the track the target belongs to, if any
+    pub fn get_track(&self) -> Option<&String> {
+        match *self {
+            AutomationTarget::Volume(ref track) |
+            AutomationTarget::Pan(ref track) |
+            AutomationTarget::SynthParameter(ref track, _) => Some(track),
+            AutomationTarget::EffectParameter(_, _, _) => None,
+        }
+    }
+
+    /// The MIDI controller the target is written as, with the value of the
+    /// controller for a value of the target. Only volume (0 to 1), pan and
+    /// the amp parameter of synths (0 to 1) are written.
+    pub fn midi_controller(&self, value: f64) -> Option<(u8, u8)> {
+        let (controller, level) = match *self {
+            AutomationTarget::Volume(_) => (VOLUME_CONTROLLER, value),
+            AutomationTarget::Pan(_) => (PAN_CONTROLLER, (value + 1.0) / 2.0),
+            AutomationTarget::SynthParameter(_, ref parameter)
+                if parameter == "amp" => (EXPRESSION_CONTROLLER, value),
+            _ => return None,
+        };
+        Some((controller, (level * 127.0).round().clamp(0.0, 127.0) as u8))
+    }
+}
+
+/// Breakpoints of a target, ordered by time
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct AutomationLane {
+    pub target: AutomationTarget,
+    pub breakpoints: Vec<Breakpoint>,
+}
+
+impl AutomationLane {
+    /// Value of the lane at a time, 0 if it has no breakpoints
+    pub fn value_at(&self, time: Time) -> f64 {
+        let next = match self.breakpoints.iter()
+            .position(|breakpoint| breakpoint.time > time) {
+            Some(0) => return self.breakpoints[0].value,
+            Some(next) => next,
+            None => return self.breakpoints.last()
+                .map_or(0.0, |breakpoint| breakpoint.value),
+        };
+        let (from, to) = (self.breakpoints[next - 1], self.breakpoints[next]);
+        let position = ((time - from.time) / (to.time - from.time)).to_f64();
+        match effective_curve(&from, &to) {
+            Curve::Linear => from.value + (to.value - from.value) * position,
+            Curve::Exponential =>
+                from.value * (to.value / from.value).powf(position),
+            Curve::Step => from.value,
+        }
+    }
+}
+
+/// The curve a segment is played with: exponential segments that cannot be
+/// are linear
+fn effective_curve(from: &Breakpoint, to: &Breakpoint) -> Curve {
+    if from.curve == Curve::Exponential && from.value * to.value <= 0.0 {
+        Curve::Linear
+    } else {
+        from.curve
+    }
+}
+
+/// Writes a lane as a SynthDef that plays it into the control bus given by
+/// its out parameter from the moment it starts, at the given length of a
+/// whole note in seconds
+pub fn automation_to_synth_def(name: &str, lane: &AutomationLane,
+    whole_note_seconds: f64) -> String {
+    // the envelope starts at the value of the first breakpoint, and holds it
+    // until the breakpoint's time
+    let first = lane.breakpoints.first().map_or(0.0, |first| first.value);
+    let mut levels: Vec<String> = vec![first.to_string()];
+    let mut times: Vec<String> = Vec::new();
+    let mut curves: Vec<String> = Vec::new();
+    let mut last_time = Time::zero();
+    let mut last_curve = "\\lin";
+    for (i, breakpoint) in lane.breakpoints.iter().enumerate() {
+        levels.push(breakpoint.value.to_string());
+        times.push(((breakpoint.time - last_time).to_f64() *
+            whole_note_seconds).to_string());
+        curves.push(last_curve.into());
+        last_time = breakpoint.time;
+        last_curve = match lane.breakpoints.get(i + 1)
+            .map(|next| effective_curve(breakpoint, next)) {
+            Some(Curve::Exponential) => "\\exp",
+            Some(Curve::Step) => "\\hold",
+            _ => "\\lin",
+        };
+    }
+    format!(k_automation_def_template!(), name=name,
+        envelope=format!("Env.new([{}], [{}], [{}])", levels.join(", "),
+            times.join(", "), curves.join(", ")))
+}
@@ -0,0 +1,129 @@
+/// Reading arrangements from score files and writing them with any of the
+/// backends, as done by the dawpl and dawpl-repl binaries. Files are read and
+/// written in the format given by their extension: .abc, .musicxml or .xml,
+/// .json or .toml (with the "serialize" feature), and the DAWPL score language
+/// for any other extension. Output may also be SuperCollider (.scd), MIDI
+/// (.mid), audio (.wav) or LilyPond (.ly).
+use abc::*;
+use arrangement::*;
+use instruments::*;
+use lilypond::*;
+use midi::*;
+use musicxml::*;
+#[cfg(feature = "serialize")]
+use project::*;
+use render::*;
+use score::*;
+use translate::*;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Names of the backends, as given to compile()
+pub const BACKENDS: [&str; 8] = ["sc", "midi", "wav", "lilypond", "musicxml",
+    "abc", "json", "toml"];
+
+/// Reads an arrangement from a file, or from standard input given "-"
+pub fn read_arrangement(input: &str) -> Result<Arrangement, String> {
+    let path = Path::new(input);
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str()).unwrap_or("");
+    if extension == "json" || extension == "toml" {
+        return load_serialized(path);
+    }
+    let mut contents = String::new();
+    if input == "-" {
+        io::stdin().read_to_string(&mut contents)
+            .map_err(|error| format!("Could not read the score: {}", error))?;
+    } else {
+        contents = fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", input, error))?;
+    }
+    let arrangement = match extension {
+        // ABC does not name instruments
+        "abc" => abc_to_arrangement(&contents, &sine()),
+        "musicxml" | "xml" => musicxml_to_arrangement(&contents),
+        _ => parse_score(&contents).map_err(|error| error.to_string()),
+    };
+    arrangement.map_err(|message| format!("{}: {}", input, message))
+}
+
+#[cfg(feature = "serialize")]
+fn load_serialized(path: &Path) -> Result<Arrangement, String> {
+    load_project(path)
+}
+
+#[cfg(not(feature = "serialize"))]
+fn load_serialized(path: &Path) -> Result<Arrangement, String> {
+    Err(format!("{}: reading projects needs dawpl to be built with the \
+        \"serialize\" feature", path.display()))
+}
+
+/// Chooses a backend from the extension of an output file
+pub fn backend_for_extension(output: &str) -> Option<&'static str> {
+    let extension = Path::new(output).extension()?.to_str()?;
+    match extension {
+        "scd" => Some("sc"),
+        "mid" | "midi" => Some("midi"),
+        "wav" => Some("wav"),
+        "ly" => Some("lilypond"),
+        "musicxml" | "xml" => Some("musicxml"),
+        "abc" => Some("abc"),
+        "json" => Some("json"),
+        "toml" => Some("toml"),
+        _ => None,
+    }
+}
+
+/// Compiles an arrangement with the named backend. The title is used by
+/// formats that have one.
+pub fn compile(arrangement: &Arrangement, backend: &str, title: &str)
+    -> Result<Vec<u8>, String> {
+    Ok(match backend {
+        "sc" | "supercollider" =>
+            arrangement_to_super_collider(arrangement).into_bytes(),
+        "midi" => arrangement_to_midi(arrangement),
+        "wav" => arrangement_to_wav(arrangement, SAMPLE_RATE),
+        "lilypond" | "ly" => arrangement_to_lilypond(arrangement)?.into_bytes(),
+        "musicxml" => arrangement_to_musicxml(arrangement)?.into_bytes(),
+        "abc" => arrangement_to_abc(arrangement, title)?.into_bytes(),
+        "json" | "toml" => serialize(arrangement, backend)?,
+        _ => return Err(format!("Unknown backend \"{}\"", backend)),
+    })
+}
+
+/// Writes an arrangement to a file with the given backend, or with the
+/// backend chosen by the extension of the file
+pub fn export(arrangement: &Arrangement, path: &str, backend: Option<&str>)
+    -> Result<(), String> {
+    let backend = backend.or_else(|| backend_for_extension(path))
+        .ok_or_else(|| format!("No backend is known for {}", path))?;
+    let output = compile(arrangement, backend, title_of(path))?;
+    fs::write(path, output)
+        .map_err(|error| format!("Could not write {}: {}", path, error))
+}
+
+/// Title of the arrangement read from or written to a file
+pub fn title_of(path: &str) -> &str {
+    Path::new(path).file_stem().and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.is_empty() && *stem != "-")
+        .unwrap_or("Untitled")
+}
+
+#[cfg(feature = "serialize")]
+fn serialize(arrangement: &Arrangement, format: &str)
+    -> Result<Vec<u8>, String> {
+    let contents = if format == "json" {
+        arrangement_to_json(arrangement)?
+    } else {
+        arrangement_to_toml(arrangement)?
+    };
+    Ok(contents.into_bytes())
+}
+
+#[cfg(not(feature = "serialize"))]
+fn serialize(_arrangement: &Arrangement, format: &str)
+    -> Result<Vec<u8>, String> {
+    Err(format!("Writing {} needs dawpl to be built with the \"serialize\" \
+        feature", format))
+}
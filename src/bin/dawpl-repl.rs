@@ -0,0 +1,45 @@
+/// Interactive prompt for composing with DAWPL. Every line is a session
+/// command (see session.rs); definitions with open braces or brackets carry
+/// on over the following lines. A score file given as an argument is loaded
+/// first.
+extern crate dawpl;
+
+use dawpl::session::*;
+use std::env;
+use std::io::{self, BufRead, Write};
+
+fn main() {
+    let mut session = Session::new();
+    if let Some(path) = env::args().nth(1) {
+        report(session.execute(&format!("load {}", path)));
+    }
+    println!("DAWPL (type \"help\" for commands, \"quit\" to leave)");
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "dawpl> " } else { "...> " });
+        io::stdout().flush().ok();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        if input.is_empty() && ["quit", "exit"].contains(&line.trim()) {
+            break;
+        }
+        input += &line;
+        input += "\n";
+        if is_complete(&input) {
+            report(session.execute(&input));
+            input.clear();
+        }
+    }
+}
+
+fn report(result: Result<String, String>) {
+    match result {
+        Ok(ref output) if output.is_empty() => (),
+        Ok(output) => println!("{}", output),
+        Err(message) => println!("error: {}", message),
+    }
+}
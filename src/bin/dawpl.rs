@@ -0,0 +1,106 @@
+/// Command-line compiler for DAWPL scores. Reads a score (in the DAWPL score
+/// language, ABC, MusicXML, or a JSON/TOML project with the "serialize"
+/// feature) and writes it with any of the backends.
+extern crate dawpl;
+
+use dawpl::backend::*;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+const USAGE: &str = "Usage: dawpl [options] <score>
+
+Compiles a score to SuperCollider, MIDI, WAV or notation. Scores are read
+in the DAWPL score language unless they end in .abc, .musicxml or .xml
+(or .json or .toml for projects).
+
+Options:
+  -b, --backend <name>  sc, midi, wav, lilypond, musicxml, abc, json or toml
+                        (default: chosen from the output extension, or sc)
+  -o, --output <path>   file to write (default: standard output)
+  -t, --tempo <bpm>     play at this many quarter notes per minute
+  -c, --check           only check the score, writing nothing
+  -h, --help            show this message";
+
+struct Options {
+    input: String,
+    backend: Option<String>,
+    output: Option<String>,
+    tempo: Option<f64>,
+    check: bool,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(message) => {
+            eprintln!("dawpl: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        },
+    };
+    if let Err(message) = run(&options) {
+        eprintln!("dawpl: {}", message);
+        process::exit(1);
+    }
+}
+
+/// Parses the command-line arguments, returning None when help is requested
+fn parse_args(args: Vec<String>) -> Result<Option<Options>, String> {
+    let mut options = Options {input: "".into(), backend: None, output: None,
+        tempo: None, check: false};
+    let mut input: Option<String> = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next()
+            .ok_or_else(|| format!("{} needs a value", name));
+        match &arg[..] {
+            "-h" | "--help" => return Ok(None),
+            "-b" | "--backend" => options.backend = Some(value(&arg)?),
+            "-o" | "--output" => options.output = Some(value(&arg)?),
+            "-t" | "--tempo" => {
+                let tempo = value(&arg)?;
+                options.tempo = Some(tempo.parse().ok()
+                    .filter(|tempo: &f64| *tempo > 0.0)
+                    .ok_or_else(|| format!("Invalid tempo \"{}\"", tempo))?);
+            },
+            "-c" | "--check" => options.check = true,
+            _ if arg.starts_with('-') && arg != "-" =>
+                return Err(format!("Unknown option {}", arg)),
+            _ if input.is_some() => return Err("Only one score can be \
+                compiled at a time".into()),
+            _ => input = Some(arg),
+        }
+    }
+    options.input = input.ok_or("No score given")?;
+    Ok(Some(options))
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut arrangement = read_arrangement(&options.input)?;
+    arrangement.validate().map_err(|message|
+        format!("{}: {}", options.input, message))?;
+    if let Some(tempo) = options.tempo {
+        arrangement.set_tempo(tempo);
+    }
+    let backend = match options.backend {
+        Some(ref backend) => backend.clone(),
+        None => options.output.as_ref()
+            .and_then(|output| backend_for_extension(output))
+            .unwrap_or("sc").into(),
+    };
+    let output = compile(&arrangement, &backend, title_of(&options.input))?;
+    if options.check {
+        return Ok(());
+    }
+    match options.output {
+        Some(ref path) => fs::write(path, output)
+            .map_err(|error| format!("Could not write {}: {}", path, error)),
+        None => io::stdout().write_all(&output)
+            .map_err(|error| format!("Could not write output: {}", error)),
+    }
+}
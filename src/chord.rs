@@ -8,6 +8,7 @@ use std::collections::HashMap;
 
 custom_derive! {
     #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, EnumFromStr)]
+    #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
     pub enum ChordType {Maj7, Min7, Dom7, Dim, Aug, Maj6}
 }
 
@@ -30,8 +31,12 @@ lazy_static! {
     };
 }
 
+pub const CHORD_TYPES: [ChordType; 6] = [ChordType::Maj7, ChordType::Min7,
+    ChordType::Dom7, ChordType::Dim, ChordType::Aug, ChordType::Maj6];
+
 // TODO: add chord! macro, e.g. chord!("C4maj7", 3, 7, 9)
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Chord {
     root: Note,
     chord_type: ChordType,
@@ -39,7 +44,7 @@ pub struct Chord {
 }
 
 impl Playable for Chord {
-    fn play(&self) -> Vec<i8> {
+    fn play(&self) -> Vec<Note> {
         self.get_voicing(0).play()
     }
 }
@@ -50,6 +55,40 @@ impl Chord {
         Chord {root, chord_type, ref_scale: Scale::new(root, ScaleType::Major)}
     }
 
+    pub fn get_root(&self) -> Note {
+        self.root
+    }
+
+    pub fn get_chord_type(&self) -> ChordType {
+        self.chord_type
+    }
+
+    /// Names the chord formed by a set of notes regardless of voicing or
+    /// doubled notes, e.g. E4 G4 B4 C5 is a Cmaj7. Only the chord types in
+    /// CHORD_TYPES are recognized. The root is placed at its lowest
+    /// occurrence among the notes, and spelled as it is there.
+    pub fn identify(notes: &[Note]) -> Option<Chord> {
+        let pitch_classes = |notes: &[Note]| {
+            let mut classes: Vec<i8> = notes.iter().map(|note|
+                note.get_midi_value().rem_euclid(NUM_TONES as i8)).collect();
+            classes.sort();
+            classes.dedup();
+            classes
+        };
+        let classes = pitch_classes(notes);
+        let mut candidates: Vec<Note> = notes.to_vec();
+        candidates.sort();
+        for root in candidates.iter() {
+            for chord_type in CHORD_TYPES.iter() {
+                let chord = Chord::new(root.with_cents(0), *chord_type);
+                if pitch_classes(&chord.play()) == classes {
+                    return Some(chord);
+                }
+            }
+        }
+        None
+    }
+
     // TODO: rootless voicings in A/B positions?
 
     /// Given a starting note for the inversion of the chord (first_note) and 
@@ -70,6 +109,18 @@ impl Chord {
         self.ref_scale.get_voicing(&voicing)
 
     }
+
+    /// Spells a note as a tone of this chord (e.g. the b7 of C7 is Bb and the
+    /// #5 of Caug is G#). Notes outside of the chord are spelled according to
+    /// the major scale of the root.
+    pub fn spell(&self, note: &Note) -> Note {
+        for chord_tone in self.get_voicing(0).iter() {
+            if chord_tone.get_name() == note.get_name() {
+                return note.respell(chord_tone.get_letter()).unwrap_or(*note);
+            }
+        }
+        self.ref_scale.spell(note)
+    }
 }
 
 // Given a starting note (as the root of the ii of a ii-V-I chord progression),
@@ -1,27 +1,228 @@
 /// This file defines DAWPL clips and the various methods they support for 
-/// editing and translation. Clips can also be extended to support effects
-/// on the signals they contain.
+/// editing and translation. Effects on the signals clips contain are kept
+/// by the arrangement (see effects.rs).
 /// Rest syntax: http://doc.sccode.org/Classes/Rest.html
 use chord::*;
+use drums::*;
+use duration::*;
+#[cfg(feature = "serialize")]
+use instruments::*;
 use note::*;
 use scale::*;
+use synth::*;
+#[cfg(feature = "serialize")]
+use project::*;
+#[cfg(feature = "serialize")]
+use std::convert::TryFrom;
 
+#[cfg(feature = "serialize")]
+const REST: &str = "rest";
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize),
+    serde(into = "ClipRecord", try_from = "ClipRecord"))]
 pub enum Clip {
     // Box is used for dynamic dispatch purposes
-    /// Clip name, instrument name, notes, durations
-    Instrument(String, String, Vec<Option<Vec<i8>>>, Vec<f64>),
+    /// Clip name, instrument, notes (spelled, see note.rs), durations
+    Instrument(String, InstrumentHandle, Vec<Option<Vec<Note>>>,
+        Vec<Duration>),
     /// Clip name, full path to audio file
     File(String, String),
     /// Clip name, duration of the rest
-    Empty(String, f64),
+    Empty(String, Duration),
+    /// Clip name, drum pattern (see drums.rs)
+    Drums(String, DrumPattern),
 }
 
 impl Clip {
     pub fn get_name(&self) -> String {
-        match self {
-            &Clip::Instrument(ref name, _, _, _) => name.clone(),
-            &Clip::File(ref name, _) => name.clone(),
-            &Clip::Empty(ref name, _) => name.clone(),
+        match *self {
+            Clip::Instrument(ref name, _, _, _) => name.clone(),
+            Clip::File(ref name, _) => name.clone(),
+            Clip::Empty(ref name, _) => name.clone(),
+            Clip::Drums(ref name, _) => name.clone(),
+        }
+    }
+}
+
+/// The form clips are saved in within project files, with a named field for
+/// each part of the clip so that project files are easy to read and diff
+#[cfg(feature = "serialize")]
+#[derive(Serialize, Deserialize)]
+struct ClipRecord {
+    name: String,
+    /// "instrument", "file", "empty" or "drums"
+    #[serde(rename = "type")]
+    clip_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instrument: Option<InstrumentRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<Vec<ListItem<Vec<NoteRecord>>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    durations: Option<Vec<Duration>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<Duration>,
+    /// Number of steps, length of a step, lanes and seed of a drum pattern
+    #[serde(skip_serializing_if = "Option::is_none")]
+    steps: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    step: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lanes: Option<Vec<DrumLaneRecord>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+/// A lane of a drum pattern within a project file
+#[cfg(feature = "serialize")]
+#[derive(Serialize, Deserialize)]
+struct DrumLaneRecord {
+    instrument: InstrumentRecord,
+    key: i8,
+    steps: Vec<DrumStep>,
+}
+
+/// Notes are saved by name e.g. "C#4", and read either by name or as the MIDI
+/// values of older project files, which are spelled the default way
+#[cfg(feature = "serialize")]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum NoteRecord {
+    Named(Note),
+    Key(i8),
+}
+
+#[cfg(feature = "serialize")]
+impl TryFrom<NoteRecord> for Note {
+    type Error = String;
+    fn try_from(record: NoteRecord) -> Result<Note, String> {
+        match record {
+            NoteRecord::Named(note) => Ok(note),
+            NoteRecord::Key(key) if key >= 0 => Ok(Note::from_midi(key)),
+            NoteRecord::Key(key) => Err(format!("Invalid MIDI value: {}",
+                key)),
+        }
+    }
+}
+
+/// Instruments of the instrument library are saved by name, other
+/// instruments are saved in full
+#[cfg(feature = "serialize")]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum InstrumentRecord {
+    Named(String),
+    Defined(Instrument),
+}
+
+#[cfg(feature = "serialize")]
+impl From<InstrumentHandle> for InstrumentRecord {
+    fn from(instrument: InstrumentHandle) -> InstrumentRecord {
+        if builtin_instrument(instrument.get_name()).as_ref() ==
+            Some(&instrument) {
+            InstrumentRecord::Named(instrument.get_name().clone())
+        } else {
+            InstrumentRecord::Defined(instrument.get_instrument().clone())
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl TryFrom<InstrumentRecord> for InstrumentHandle {
+    type Error = String;
+    fn try_from(record: InstrumentRecord) -> Result<InstrumentHandle, String> {
+        match record {
+            InstrumentRecord::Named(name) => builtin_instrument(&name)
+                .ok_or_else(|| format!("Unknown instrument: {}", name)),
+            InstrumentRecord::Defined(instrument) => Ok(instrument.handle()),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "serialize")]
+impl From<Clip> for ClipRecord {
+    fn from(clip: Clip) -> ClipRecord {
+        let mut record = ClipRecord {name: clip.get_name(),
+            clip_type: "".into(), instrument: None, notes: None,
+            durations: None, path: None, duration: None, steps: None,
+            step: None, lanes: None, seed: None};
+        match clip {
+            Clip::Instrument(_, instrument, melody, durations) => {
+                record.clip_type = "instrument".into();
+                record.instrument = Some(instrument.into());
+                let melody: Vec<Option<Vec<NoteRecord>>> = melody.into_iter()
+                    .map(|notes| notes.map(|notes| notes.into_iter()
+                    .map(NoteRecord::Named).collect())).collect();
+                record.notes = Some(write_list(&melody, REST));
+                record.durations = Some(durations);
+            },
+            Clip::File(_, path) => {
+                record.clip_type = "file".into();
+                record.path = Some(path);
+            },
+            Clip::Empty(_, duration) => {
+                record.clip_type = "empty".into();
+                record.duration = Some(duration);
+            },
+            Clip::Drums(_, pattern) => {
+                record.clip_type = "drums".into();
+                record.steps = Some(pattern.steps);
+                record.step = Some(pattern.step);
+                record.lanes = Some(pattern.lanes.into_iter().map(|lane|
+                    DrumLaneRecord {instrument: lane.instrument.into(),
+                    key: lane.key, steps: lane.steps}).collect());
+                record.seed = Some(pattern.seed);
+            },
+        }
+        record
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl TryFrom<ClipRecord> for Clip {
+    type Error = String;
+    fn try_from(record: ClipRecord) -> Result<Clip, String> {
+        let name = record.name.clone();
+        let missing = |field: &str| format!("Clip \"{}\" has no {}", name,
+            field);
+        match &record.clip_type[..] {
+            "instrument" => {
+                let melody = read_list(record.notes
+                    .ok_or_else(|| missing("notes"))?, REST)?.into_iter()
+                    .map(|notes| notes.map(|notes| notes.into_iter()
+                    .map(Note::try_from).collect()).transpose())
+                    .collect::<Result<Vec<Option<Vec<Note>>>, String>>()?;
+                let durations = record.durations
+                    .ok_or_else(|| missing("durations"))?;
+                if melody.len() != durations.len() {
+                    return Err(format!("Clip \"{}\" has {} notes but {} \
+                        durations", name, melody.len(), durations.len()));
+                }
+                let instrument = InstrumentHandle::try_from(record.instrument
+                    .ok_or_else(|| missing("instrument"))?)?;
+                Ok(Clip::Instrument(record.name, instrument, melody,
+                    durations))
+            },
+            "file" => Ok(Clip::File(record.name, record.path
+                .ok_or_else(|| missing("path"))?)),
+            "empty" => Ok(Clip::Empty(record.name, record.duration
+                .ok_or_else(|| missing("duration"))?)),
+            "drums" => {
+                let mut pattern = DrumPattern::new(record.steps
+                    .ok_or_else(|| missing("steps"))?, record.step
+                    .ok_or_else(|| missing("step"))?)
+                    .with_seed(record.seed.unwrap_or(0));
+                for lane in record.lanes.ok_or_else(|| missing("lanes"))? {
+                    pattern.lanes.push(DrumLane {instrument:
+                        InstrumentHandle::try_from(lane.instrument)?,
+                        key: lane.key, steps: lane.steps});
+                }
+                Ok(Clip::Drums(record.name, pattern))
+            },
+            clip_type => Err(format!("Clip \"{}\" has an unknown type: {}",
+                name, clip_type)),
+        }
+    }
+}
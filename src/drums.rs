@@ -0,0 +1,205 @@
+/// Drum patterns for step-sequencer clips (Clip::Drums). A pattern is a grid
+/// of steps of equal length, with a lane of steps for each drum voice. Every
+/// step of a lane is a hit at a velocity (0 to 127, 0 is silent) that plays
+/// with a probability, so that patterns can vary from bar to bar.
+///
+/// Lanes are written as strings with a character per step:
+///
+/// ```text
+/// x  hit at the default velocity (100)
+/// X  accent (127)
+/// o  ghost note (50)
+/// ?  hit at the default velocity half of the time
+/// .  rest, also -
+/// ```
+///
+/// Spaces and bar lines (|) are ignored, e.g. "x... x... x... x...". Lanes
+/// shorter than the pattern are filled with rests.
+///
+/// SuperCollider picks the steps that play every time the clip plays. Other
+/// backends pick them once with the pattern's seed, so that MIDI files and
+/// rendered audio are the same every time they are written. Hits are General
+/// MIDI drum keys, and tracks that play drums are written on MIDI channel 10.
+use arrangement::*;
+use duration::*;
+use instruments::*;
+use random::*;
+use synth::*;
+
+/// Velocities of the characters of a lane
+const ACCENT_VELOCITY: u8 = 127;
+const GHOST_VELOCITY: u8 = 50;
+
+/// Drum voices, each played with an instrument of the library on its General
+/// MIDI key
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DrumVoice {
+    Kick,
+    Snare,
+    HiHat,
+}
+
+impl DrumVoice {
+    pub fn get_instrument(&self) -> InstrumentHandle {
+        match *self {
+            DrumVoice::Kick => kick(),
+            DrumVoice::Snare => snare(),
+            DrumVoice::HiHat => hihat(),
+        }
+    }
+
+    /// Key of the voice in the General MIDI percussion map
+    pub fn get_key(&self) -> i8 {
+        match *self {
+            DrumVoice::Kick => 36,
+            DrumVoice::Snare => 38,
+            DrumVoice::HiHat => 42,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DrumStep {
+    /// 0 to 127, 0 for no hit
+    pub velocity: u8,
+    /// Probability of the hit playing, from 0 to 1
+    pub probability: f64,
+}
+
+impl DrumStep {
+    pub fn new(velocity: u8, probability: f64) -> DrumStep {
+        DrumStep {velocity, probability}
+    }
+
+    pub fn rest() -> DrumStep {
+        DrumStep::new(0, 1.0)
+    }
+
+    pub fn is_rest(&self) -> bool {
+        self.velocity == 0 || self.probability <= 0.0
+    }
+}
+
+/// The steps of a drum voice, played with an instrument on a MIDI key
+#[derive(Debug, PartialEq, Clone)]
+pub struct DrumLane {
+    pub instrument: InstrumentHandle,
+    pub key: i8,
+    pub steps: Vec<DrumStep>,
+}
+
+/// A hit of a pattern that plays, from DrumPattern::get_hits
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DrumHit {
+    /// Index of the lane of the hit
+    pub lane: usize,
+    /// Start from the start of the clip
+    pub start: Time,
+    pub velocity: u8,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DrumPattern {
+    /// Number of steps of every lane
+    pub steps: usize,
+    /// Length of a step e.g. 1/16 for sixteenth notes
+    pub step: Duration,
+    pub lanes: Vec<DrumLane>,
+    /// Seed of the choice of the steps that play, where it is made once
+    pub seed: u64,
+}
+
+impl DrumPattern {
+    /// A pattern without lanes of the given number of steps, each the given
+    /// length, e.g. DrumPattern::new(16, Duration::new(1, 16)) for a bar of
+    /// sixteenth notes in 4/4
+    pub fn new(steps: usize, step: Duration) -> DrumPattern {
+        DrumPattern {steps, step, lanes: Vec::new(), seed: 0}
+    }
+
+    /// Adds a lane for a voice, written as a string of steps
+    pub fn lane(self, voice: DrumVoice, steps: &str)
+        -> Result<DrumPattern, String> {
+        self.instrument_lane(voice.get_instrument(), voice.get_key(), steps)
+    }
+
+    /// Adds a lane played with any instrument on the given MIDI key, written
+    /// as a string of steps
+    pub fn instrument_lane(mut self, instrument: InstrumentHandle, key: i8,
+        steps: &str) -> Result<DrumPattern, String> {
+        let mut lane_steps = parse_steps(steps)?;
+        if lane_steps.len() > self.steps {
+            return Err(format!("\"{}\" has {} steps but the pattern has {}",
+                steps, lane_steps.len(), self.steps));
+        }
+        lane_steps.resize(self.steps, DrumStep::rest());
+        self.lanes.push(DrumLane {instrument, key, steps: lane_steps});
+        Ok(self)
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> DrumPattern {
+        self.seed = seed;
+        self
+    }
+
+    pub fn get_length(&self) -> Duration {
+        self.step * self.steps as u64
+    }
+
+    /// The hits that play, ordered by start, with the steps whose
+    /// probability is below 1 chosen with the pattern's seed
+    pub fn get_hits(&self) -> Vec<DrumHit> {
+        let mut random = Random::new(self.seed);
+        let mut hits: Vec<DrumHit> = Vec::new();
+        for index in 0..self.steps {
+            for (lane_index, lane) in self.lanes.iter().enumerate() {
+                let step = match lane.steps.get(index) {
+                    Some(step) if !step.is_rest() => step,
+                    _ => continue,
+                };
+                if step.probability >= 1.0 || random.chance(step.probability) {
+                    hits.push(DrumHit {lane: lane_index,
+                        start: self.step * index as u64,
+                        velocity: step.velocity});
+                }
+            }
+        }
+        hits
+    }
+
+    /// Checks that the steps have a length and that every step of every lane
+    /// has a velocity up to 127 and a probability from 0 to 1
+    pub fn validate(&self) -> Result<(), String> {
+        if self.step.is_zero() {
+            return Err("Steps of no length".to_string());
+        }
+        for lane in self.lanes.iter() {
+            if lane.steps.iter().any(|step| step.velocity > 127 ||
+                !(0.0..=1.0).contains(&step.probability)) {
+                return Err(format!("The {} lane has an invalid step",
+                    lane.instrument.get_name()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a lane written as a string of steps
+pub fn parse_steps(steps: &str) -> Result<Vec<DrumStep>, String> {
+    let mut parsed: Vec<DrumStep> = Vec::new();
+    for character in steps.chars() {
+        parsed.push(match character {
+            'x' => DrumStep::new(DEFAULT_VELOCITY, 1.0),
+            'X' => DrumStep::new(ACCENT_VELOCITY, 1.0),
+            'o' => DrumStep::new(GHOST_VELOCITY, 1.0),
+            '?' => DrumStep::new(DEFAULT_VELOCITY, 0.5),
+            '.' | '-' => DrumStep::rest(),
+            '|' => continue,
+            character if character.is_whitespace() => continue,
+            character => return Err(format!("Unknown step '{}' in \"{}\"",
+                character, steps)),
+        });
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,231 @@
+/// Offline versions of the effects of effects.rs, used by the renderer. Each
+/// processes a whole buffer of mono samples at once, in the same way as the
+/// SuperCollider UGen it stands in for: FreeVerb, CombL, a modulated DelayL,
+/// tanh, MidEQ, Compander and RLPF, RHPF and BPF. Parameters are read at
+/// every sample, so that they can be automated.
+use effects::*;
+use std::f64::consts::PI;
+
+/// Delays of the comb and allpass filters of the reverb, in samples at
+/// 44100 Hz (those of Freeverb, on which FreeVerb is based)
+const REVERB_COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557,
+    1617];
+const REVERB_ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+/// Level of the input into the reverb and of the reverb's output
+const REVERB_INPUT_GAIN: f64 = 0.015;
+const REVERB_OUTPUT_GAIN: f64 = 3.0;
+/// Shortest delay of the chorus in seconds, which the modulation moves
+/// around
+const CHORUS_DELAY: f64 = 0.02;
+
+/// Value of a parameter of an effect at a sample, by the index of the
+/// parameter (see Effect::get_parameters) and of the sample
+pub type Parameters<'a> = &'a dyn Fn(usize, usize) -> f64;
+
+/// Applies an effect to samples in place
+pub fn apply_effect(effect: &Effect, samples: &mut [f64], sample_rate: f64) {
+    let values = effect.get_parameters();
+    apply_automated_effect(effect, samples, sample_rate,
+        &|parameter, _| values[parameter]);
+}
+
+/// Applies an effect whose parameters may change from sample to sample
+pub fn apply_automated_effect(effect: &Effect, samples: &mut [f64],
+    sample_rate: f64, parameters: Parameters) {
+    match *effect {
+        Effect::Reverb(_, _, _) => reverb(samples, sample_rate, parameters),
+        Effect::Delay(_, _, _) => delay(samples, sample_rate, parameters),
+        Effect::Chorus(_, _, _) => chorus(samples, sample_rate, parameters),
+        Effect::Distortion(_, _) => {
+            for (i, sample) in samples.iter_mut().enumerate() {
+                let (drive, mix) = (parameters(0, i), parameters(1, i));
+                *sample = *sample * (1.0 - mix) + (*sample * drive).tanh() *
+                    mix;
+            }
+        },
+        Effect::Eq(_, _, _) => Biquad::process(samples, 3, parameters,
+            |parameters, i| Biquad::peak(sample_rate, parameters(0, i),
+                parameters(1, i), parameters(2, i))),
+        Effect::Compressor(_, _, _, _) =>
+            compress(samples, sample_rate, parameters),
+        Effect::Filter(filter_type, _, _) => Biquad::process(samples, 2,
+            parameters, |parameters, i| Biquad::filter(sample_rate,
+                filter_type, parameters(0, i), parameters(1, i))),
+    }
+}
+
+/// Parameters: mix, room, damping
+fn reverb(samples: &mut [f64], sample_rate: f64, parameters: Parameters) {
+    let scale = sample_rate / 44100.0;
+    let mut combs: Vec<(Vec<f64>, usize, f64)> = REVERB_COMBS.iter()
+        .map(|length| (vec![0.0; ((*length as f64 * scale) as usize).max(1)],
+            0, 0.0)).collect();
+    let mut allpasses: Vec<(Vec<f64>, usize)> = REVERB_ALLPASSES.iter()
+        .map(|length| (vec![0.0; ((*length as f64 * scale) as usize).max(1)],
+            0)).collect();
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let mix = parameters(0, i);
+        let feedback = parameters(1, i) * 0.28 + 0.7;
+        let damp = parameters(2, i) * 0.4;
+        let input = *sample * REVERB_INPUT_GAIN;
+        let mut wet = 0.0;
+        // parallel low passed combs
+        for &mut (ref mut buffer, ref mut index, ref mut low_pass) in
+            combs.iter_mut() {
+            let output = buffer[*index];
+            *low_pass = output * (1.0 - damp) + *low_pass * damp;
+            buffer[*index] = input + *low_pass * feedback;
+            *index = (*index + 1) % buffer.len();
+            wet += output;
+        }
+        // allpasses in series
+        for &mut (ref mut buffer, ref mut index) in allpasses.iter_mut() {
+            let delayed = buffer[*index];
+            buffer[*index] = wet + delayed * 0.5;
+            *index = (*index + 1) % buffer.len();
+            wet = delayed - wet;
+        }
+        *sample = *sample * (1.0 - mix) + wet * REVERB_OUTPUT_GAIN * mix;
+    }
+}
+
+/// Echoes that fade by 60 dB over the decay time, added to the input.
+/// Parameters: delay time, decay time, level.
+fn delay(samples: &mut [f64], sample_rate: f64, parameters: Parameters) {
+    let mut echoes = vec![0.0; samples.len()];
+    for i in 0..samples.len() {
+        let (time, decay) = (parameters(0, i), parameters(1, i));
+        let length = (time * sample_rate).round() as usize;
+        if length == 0 || length > i {
+            continue;
+        }
+        let feedback = if decay > 0.0 { 0.001f64.powf(time / decay) }
+            else { 0.0 };
+        echoes[i] = samples[i - length] + feedback * echoes[i - length];
+    }
+    for (i, (sample, echo)) in samples.iter_mut().zip(echoes.iter())
+        .enumerate() {
+        *sample += echo * parameters(2, i);
+    }
+}
+
+/// Mixes in a copy delayed by a time that moves with a sine wave.
+/// Parameters: rate, depth, mix.
+fn chorus(samples: &mut [f64], sample_rate: f64, parameters: Parameters) {
+    let dry = samples.to_vec();
+    let mut phase = 0.0f64;
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let delay = (CHORUS_DELAY + parameters(1, i) * phase.sin()) *
+            sample_rate;
+        phase += 2.0 * PI * parameters(0, i) / sample_rate;
+        let position = i as f64 - delay;
+        let wet = if position < 0.0 {
+            0.0
+        } else {
+            // linear interpolation between the neighbouring samples
+            let index = position.floor() as usize;
+            let fraction = position - index as f64;
+            let next = dry.get(index + 1).cloned().unwrap_or(0.0);
+            dry[index] * (1.0 - fraction) + next * fraction
+        };
+        let mix = parameters(2, i);
+        *sample = *sample * (1.0 - mix) + wet * mix;
+    }
+}
+
+/// Lowers the level above the threshold by the ratio, following the level
+/// of the input with the attack and release times. Parameters: threshold,
+/// ratio, attack, release.
+fn compress(samples: &mut [f64], sample_rate: f64, parameters: Parameters) {
+    let coefficient = |time: f64| if time > 0.0 {
+        (-1.0 / (time * sample_rate)).exp()
+    } else {
+        0.0
+    };
+    let mut level = 0.0;
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let (threshold, ratio) = (parameters(0, i), parameters(1, i));
+        let input = sample.abs();
+        let coefficient = coefficient(if input > level { parameters(2, i) }
+            else { parameters(3, i) });
+        level = coefficient * level + (1.0 - coefficient) * input;
+        if level > threshold && threshold > 0.0 {
+            *sample *= (level / threshold).powf(1.0 / ratio - 1.0);
+        }
+    }
+}
+
+/// A second order filter, with coefficients from the Audio EQ Cookbook
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    /// Angular frequency and alpha of a filter, from its frequency and the
+    /// reciprocal of its Q. Frequencies are kept below the Nyquist frequency.
+    fn parameters(sample_rate: f64, freq: f64, rq: f64) -> (f64, f64) {
+        let freq = freq.clamp(1.0, sample_rate * 0.49);
+        let w0 = 2.0 * PI * freq / sample_rate;
+        (w0, w0.sin() * rq / 2.0)
+    }
+
+    fn new(b: [f64; 3], a: [f64; 3]) -> Biquad {
+        Biquad {b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]]}
+    }
+
+    fn filter(sample_rate: f64, filter_type: FilterType, freq: f64, rq: f64)
+        -> Biquad {
+        let (w0, alpha) = Biquad::parameters(sample_rate, freq, rq);
+        let cos = w0.cos();
+        let a = [1.0 + alpha, -2.0 * cos, 1.0 - alpha];
+        match filter_type {
+            FilterType::LowPass => Biquad::new([(1.0 - cos) / 2.0, 1.0 - cos,
+                (1.0 - cos) / 2.0], a),
+            FilterType::HighPass => Biquad::new([(1.0 + cos) / 2.0,
+                -(1.0 + cos), (1.0 + cos) / 2.0], a),
+            FilterType::BandPass => Biquad::new([alpha, 0.0, -alpha], a),
+        }
+    }
+
+    /// Boosts or cuts a band around the frequency by the gain in dB
+    fn peak(sample_rate: f64, freq: f64, gain: f64, rq: f64) -> Biquad {
+        let (w0, alpha) = Biquad::parameters(sample_rate, freq, rq);
+        let amplitude = 10f64.powf(gain / 40.0);
+        let cos = w0.cos();
+        Biquad::new([1.0 + alpha * amplitude, -2.0 * cos,
+            1.0 - alpha * amplitude], [1.0 + alpha / amplitude, -2.0 * cos,
+            1.0 - alpha / amplitude])
+    }
+
+    /// Filters samples with the filter made from the given number of
+    /// parameters at each sample, which is only made again when the
+    /// parameters change
+    fn process<F>(samples: &mut [f64], count: usize, parameters: Parameters,
+        make: F) where F: Fn(Parameters, usize) -> Biquad {
+        // NaN differs from every value, so the first filter is always made
+        let mut values = [f64::NAN; 3];
+        let mut filter = Biquad {b: [0.0; 3], a: [0.0; 2]};
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let mut current = [0.0; 3];
+            for (parameter, value) in current.iter_mut().take(count)
+                .enumerate() {
+                *value = parameters(parameter, i);
+            }
+            if current != values {
+                filter = make(parameters, i);
+                values = current;
+            }
+            let x = *sample;
+            let y = filter.b[0] * x + filter.b[1] * x1 + filter.b[2] * x2 -
+                filter.a[0] * y1 - filter.a[1] * y2;
+            x2 = x1;
+            x1 = x;
+            y2 = y1;
+            y1 = y;
+            *sample = y;
+        }
+    }
+}
@@ -0,0 +1,301 @@
+/// Exact note lengths. A Duration is a fraction of a whole note kept in lowest
+/// terms, so that tuplets, dotted values and ties add up to whole bars without
+/// the rounding error of floats, e.g. twelve eighth note triplets last exactly
+/// a bar of 4/4. Clips, tracks and arrangements keep their durations and
+/// times exactly, and the backends only turn them into floats (with to_f64)
+/// or ticks when they write them out.
+use std::cmp::Ordering;
+#[cfg(feature = "serialize")]
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+use std::str::FromStr;
+
+/// Largest denominator of a duration read from a float
+const MAX_DENOMINATOR: u64 = 1 << 32;
+/// Most dots a note value can have
+pub const MAX_DOTS: u32 = 3;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize),
+    serde(into = "String", try_from = "DurationRecord"))]
+pub struct Duration {
+    numerator: u64,
+    denominator: u64,
+}
+
+/// A point in time, as the duration since the start of an arrangement or clip
+pub type Time = Duration;
+
+impl Duration {
+    /// numerator / denominator whole notes, e.g. Duration::new(3, 8) for a
+    /// dotted quarter note
+    pub fn new(numerator: u64, denominator: u64) -> Duration {
+        assert!(denominator != 0, "Duration with a denominator of 0");
+        let divisor = gcd(numerator, denominator);
+        Duration {numerator: numerator / divisor,
+            denominator: denominator / divisor}
+    }
+
+    pub fn zero() -> Duration {
+        Duration::new(0, 1)
+    }
+
+    pub fn get_numerator(&self) -> u64 {
+        self.numerator
+    }
+
+    pub fn get_denominator(&self) -> u64 {
+        self.denominator
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator == 0
+    }
+
+    /// The duration with the given number of dots, each adding half of the
+    /// length added by the one before it e.g. a dotted half note lasts 3/4.
+    /// Panics if there are more than MAX_DOTS dots.
+    pub fn dotted(&self, dots: u32) -> Duration {
+        assert!(dots <= MAX_DOTS, "A note value with {} dots", dots);
+        let added = (1u64 << dots) - 1;
+        *self + *self * Duration::new(added, 1 << dots)
+    }
+
+    /// The duration of a note of an n-tuplet played in the time of m notes of
+    /// the same value e.g. an eighth note of a triplet (3 in the time of 2)
+    /// lasts 1/12
+    pub fn tuplet(&self, n: u64, m: u64) -> Duration {
+        *self * Duration::new(m, n)
+    }
+
+    /// Length in whole notes as a float
+    pub fn to_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    /// The simplest fraction that is as close to the given number of whole
+    /// notes as a float can tell e.g. 1/3 for 0.333..., None for negative
+    /// and infinite lengths and NaN
+    pub fn from_f64(length: f64) -> Option<Duration> {
+        if !(0.0..MAX_DENOMINATOR as f64).contains(&length) {
+            return None;
+        }
+        // convergents of the continued fraction of the length, until one is
+        // close enough
+        let (mut numerator, mut previous_numerator) = (1u64, 0u64);
+        let (mut denominator, mut previous_denominator) = (0u64, 1u64);
+        let mut rest = length;
+        loop {
+            let whole = rest.floor() as u64;
+            let next = |term: u64, previous: u64| whole.checked_mul(term)
+                .and_then(|product| product.checked_add(previous));
+            let (next_numerator, next_denominator) = match (
+                next(numerator, previous_numerator),
+                next(denominator, previous_denominator)) {
+                (Some(numerator), Some(denominator))
+                    if denominator <= MAX_DENOMINATOR =>
+                    (numerator, denominator),
+                _ => break,
+            };
+            previous_numerator = numerator;
+            previous_denominator = denominator;
+            numerator = next_numerator;
+            denominator = next_denominator;
+            let error = numerator as f64 / denominator as f64 - length;
+            if error.abs() <= length.max(1.0) * 1e-12 || rest == whole as f64 {
+                break;
+            }
+            rest = 1.0 / (rest - whole as f64);
+        }
+        Some(Duration::new(numerator, denominator))
+    }
+}
+
+/// The number of notes whose time an n-tuplet is played in when it is not
+/// given: the largest power of two below n, e.g. 2 for triplets and 4 for
+/// quintuplets
+pub fn tuplet_space(n: u64) -> u64 {
+    let mut space = 1;
+    while space * 2 < n {
+        space *= 2;
+    }
+    space
+}
+
+/// Adds a duration to a list, tied to the last one if there is one
+pub fn push_duration(durations: &mut Vec<Duration>, tied: bool,
+    duration: Duration) {
+    match durations.last_mut() {
+        Some(last) if tied => *last += duration,
+        _ => durations.push(duration),
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    a.max(1)
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        let divisor = gcd(self.denominator, other.denominator);
+        let denominator = self.denominator / divisor * other.denominator;
+        Duration::new(self.numerator * (denominator / self.denominator) +
+            other.numerator * (denominator / other.denominator), denominator)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+/// Subtraction panics if the result would be negative
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, other: Duration) -> Duration {
+        let divisor = gcd(self.denominator, other.denominator);
+        let denominator = self.denominator / divisor * other.denominator;
+        Duration::new(self.numerator * (denominator / self.denominator) -
+            other.numerator * (denominator / other.denominator), denominator)
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+impl Mul for Duration {
+    type Output = Duration;
+
+    fn mul(self, other: Duration) -> Duration {
+        // reduced crosswise first to keep the terms small
+        let left = gcd(self.numerator, other.denominator);
+        let right = gcd(other.numerator, self.denominator);
+        Duration::new((self.numerator / left) * (other.numerator / right),
+            (self.denominator / right) * (other.denominator / left))
+    }
+}
+
+impl Mul<u64> for Duration {
+    type Output = Duration;
+
+    fn mul(self, times: u64) -> Duration {
+        self * Duration::new(times, 1)
+    }
+}
+
+/// The ratio of two durations, which panics if the second is zero
+impl Div for Duration {
+    type Output = Duration;
+
+    fn div(self, other: Duration) -> Duration {
+        self.tuplet(other.numerator, other.denominator)
+    }
+}
+
+impl Div<u64> for Duration {
+    type Output = Duration;
+
+    fn div(self, parts: u64) -> Duration {
+        self.tuplet(parts, 1)
+    }
+}
+
+impl Sum for Duration {
+    fn sum<I: Iterator<Item = Duration>>(durations: I) -> Duration {
+        durations.fold(Duration::zero(), |total, duration| total + duration)
+    }
+}
+
+impl Ord for Duration {
+    fn cmp(&self, other: &Duration) -> Ordering {
+        (u128::from(self.numerator) * u128::from(other.denominator))
+            .cmp(&(u128::from(other.numerator) * u128::from(self.denominator)))
+    }
+}
+
+impl PartialOrd for Duration {
+    fn partial_cmp(&self, other: &Duration) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Reads a duration written as a fraction e.g. "3/8", a whole number e.g. "2"
+/// or a decimal e.g. "0.375", in whole notes
+impl FromStr for Duration {
+    type Err = String;
+    fn from_str(duration: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid duration \"{}\"", duration);
+        let mut parts = duration.trim().splitn(2, '/');
+        let numerator = parts.next().unwrap_or("").trim();
+        match parts.next().map(|denominator| denominator.trim()) {
+            Some(denominator) => match (numerator.parse::<u64>(),
+                denominator.parse::<u64>()) {
+                (Ok(_), Ok(0)) => Err(invalid()),
+                (Ok(numerator), Ok(denominator)) =>
+                    Ok(Duration::new(numerator, denominator)),
+                _ => Err(invalid()),
+            },
+            None => match numerator.parse::<u64>() {
+                Ok(numerator) => Ok(Duration::new(numerator, 1)),
+                Err(_) => numerator.parse::<f64>().ok()
+                    .and_then(Duration::from_f64).ok_or_else(invalid),
+            },
+        }
+    }
+}
+
+/// Durations are saved in project files as they are displayed e.g. "3/8",
+/// and can also be read from numbers of whole notes e.g. 0.375
+#[cfg(feature = "serialize")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DurationRecord {
+    Number(f64),
+    Text(String),
+}
+
+#[cfg(feature = "serialize")]
+impl From<Duration> for String {
+    fn from(duration: Duration) -> String {
+        duration.to_string()
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl TryFrom<DurationRecord> for Duration {
+    type Error = String;
+    fn try_from(record: DurationRecord) -> Result<Duration, String> {
+        match record {
+            DurationRecord::Number(length) => Duration::from_f64(length)
+                .ok_or_else(|| format!("Invalid duration {}", length)),
+            DurationRecord::Text(duration) => duration.parse(),
+        }
+    }
+}
+
+/// Durations are displayed as fractions of a whole note e.g. 3/8, or as
+/// whole numbers e.g. 2
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // padded, so that durations line up in tables
+        if self.denominator == 1 {
+            f.pad(&self.numerator.to_string())
+        } else {
+            f.pad(&format!("{}/{}", self.numerator, self.denominator))
+        }
+    }
+}
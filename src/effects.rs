@@ -0,0 +1,215 @@
+/// Audio effects and mixing. Effects can be put on clips, tracks, groups,
+/// returns or the master output of an arrangement. Every clip, track and
+/// master with effects, and every group and return, gets a private stereo
+/// bus in SuperCollider: what plays on it is sent to the bus, and an effect
+/// synth running after every other synth reads the bus, applies its effects
+/// in order and sends the result on to the next stage. Clips go to their
+/// track, tracks go to their group if they are in one or else to the master,
+/// groups and returns go to the master, and the master goes to the speakers
+/// (bus 0). Clips, tracks and the master without effects are skipped.
+///
+/// Tracks can also send their output, after their effects, to returns at a
+/// level of their own, so that several tracks share one effect, e.g. a
+/// reverb for the whole mix. The offline renderer (render.rs) mixes in the
+/// same way.
+use synth::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    BandPass,
+}
+
+/// An effect and its parameters. Mixes go from 0 (only the dry signal) to 1
+/// (only the effect).
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Effect {
+    /// Mix, room size (0 to 1), damping of high frequencies (0 to 1)
+    Reverb(f64, f64, f64),
+    /// Delay time in seconds, time in seconds for the echoes to fade away,
+    /// level of the echoes
+    Delay(f64, f64, f64),
+    /// Rate of the modulation in Hz, depth of the modulation in seconds (up
+    /// to 0.02), mix
+    Chorus(f64, f64, f64),
+    /// Drive (gain into the saturation, 1 is nearly clean), mix
+    Distortion(f64, f64),
+    /// Centre frequency in Hz, gain in dB (negative to cut), reciprocal of
+    /// the Q of the band
+    Eq(f64, f64, f64),
+    /// Threshold (amplitude from 0 to 1), ratio (e.g. 4 for 4:1), attack and
+    /// release times in seconds
+    Compressor(f64, f64, f64, f64),
+    /// Type of filter, cutoff frequency in Hz, reciprocal of the filter's Q
+    Filter(FilterType, f64, f64),
+}
+
+/// Where effects are put
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum EffectTarget {
+    /// A clip, by name
+    Clip(String),
+    /// A track, by name
+    Track(String),
+    /// A group of tracks, by name
+    Group(String),
+    /// A return, by name
+    Return(String),
+    /// The mix of every track
+    Master,
+}
+
+/// Effects put on a target, applied in order
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct EffectChain {
+    pub target: EffectTarget,
+    pub effects: Vec<Effect>,
+}
+
+/// Tracks mixed together before they reach the master (a sub-mix), which can
+/// have effects of their own. A track is in at most one group.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct TrackGroup {
+    pub name: String,
+    pub tracks: Vec<String>,
+}
+
+/// A track's send to a return, at a level from 0 to 1
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct AuxSend {
+    pub track: String,
+    pub destination: String,
+    pub level: f64,
+}
+
+/// Longest delay time in seconds of a delay whose time is automated
+const MAX_DELAY: f64 = 2.0;
+
+/// Mixes the dry signal with the effect
+fn mix(dry: Signal, wet: Signal, mix: Signal) -> Signal {
+    match mix {
+        Signal::Constant(mix) => dry * (1.0 - mix) + wet * mix,
+        mix => dry * (1.0 - mix.clone()) + wet * mix,
+    }
+}
+
+impl Effect {
+    /// Values of the parameters of the effect, in the order they are given
+    /// in, leaving out the type of a filter
+    pub fn get_parameters(&self) -> Vec<f64> {
+        match *self {
+            Effect::Reverb(a, b, c) | Effect::Delay(a, b, c) |
+            Effect::Chorus(a, b, c) | Effect::Eq(a, b, c) => vec![a, b, c],
+            Effect::Distortion(a, b) | Effect::Filter(_, a, b) => vec![a, b],
+            Effect::Compressor(a, b, c, d) => vec![a, b, c, d],
+        }
+    }
+
+    /// Applies the effect to a stereo signal
+    pub fn apply(&self, input: Signal) -> Signal {
+        self.apply_parameters(input, self.get_parameters().into_iter()
+            .map(Signal::from).collect())
+    }
+
+    /// Applies the effect to a stereo signal with parameters given as
+    /// signals, in the order of get_parameters, so that they can change
+    pub fn apply_parameters(&self, input: Signal, parameters: Vec<Signal>)
+        -> Signal {
+        let parameter = |index: usize| parameters[index].clone();
+        match *self {
+            Effect::Reverb(_, _, _) => ugen("FreeVerb",
+                vec![input, parameter(0), parameter(1), parameter(2)]),
+            Effect::Delay(_, _, _) => {
+                let max_delay = match parameter(0) {
+                    Signal::Constant(time) => time,
+                    _ => MAX_DELAY,
+                };
+                // CombL.ar(in, maxdelaytime, delaytime, decaytime)
+                let echoes = ugen("CombL", vec![input.clone(),
+                    max_delay.into(), parameter(0), parameter(1)]);
+                input + echoes * parameter(2)
+            },
+            Effect::Chorus(_, _, _) => {
+                let delay = sin_osc(parameter(0)).kr() * parameter(1) + 0.02;
+                let wet = ugen("DelayL", vec![input.clone(), 0.05.into(),
+                    delay]);
+                mix(input, wet, parameter(2))
+            },
+            Effect::Distortion(_, _) => mix(input.clone(),
+                (input * parameter(0)).unary("tanh"), parameter(1)),
+            Effect::Eq(_, _, _) => ugen("MidEQ",
+                vec![input, parameter(0), parameter(2), parameter(1)]),
+            Effect::Compressor(_, _, _, _) => {
+                let slope = match parameter(1) {
+                    Signal::Constant(ratio) => (1.0 / ratio).into(),
+                    ratio => 1.0 / ratio,
+                };
+                // Compander.ar(in, control, thresh, slopeBelow, slopeAbove,
+                // clampTime, relaxTime)
+                ugen("Compander", vec![input.clone(), input, parameter(0),
+                    1.0.into(), slope, parameter(2), parameter(3)])
+            },
+            Effect::Filter(filter_type, _, _) => match filter_type {
+                FilterType::LowPass => rlpf(input, parameter(0), parameter(1)),
+                FilterType::HighPass => ugen("RHPF",
+                    vec![input, parameter(0), parameter(1)]),
+                FilterType::BandPass => bpf(input, parameter(0), parameter(1)),
+            },
+        }
+    }
+}
+
+/// Name of the argument of an effects SynthDef that sets a parameter of one
+/// of its effects, by the index of the effect and of the parameter
+pub fn effect_argument(effect: usize, parameter: usize) -> String {
+    format!("fx{}_{}", effect, parameter)
+}
+
+/// Writes an effect chain as a SynthDef that reads the bus given by its in
+/// parameter and writes to the bus given by its out parameter. The result
+/// is also sent at the given levels to the buses given by the parameters
+/// send0, send1 and so on.
+///
+/// The parameters of effects listed in automated, by the index of the effect
+/// and of the parameter, are arguments named by effect_argument instead of
+/// fixed. With fader, the volume and pan arguments set the level and
+/// position of the result.
+pub fn effects_to_synth_def(name: &str, effects: &[Effect],
+    automated: &[(usize, usize)], fader: bool, send_levels: &[f64])
+    -> String {
+    let mut lines: String = "".into();
+    let mut arguments: String = "".into();
+    for (i, effect) in effects.iter().enumerate() {
+        let mut parameters: Vec<Signal> = Vec::new();
+        for (j, value) in effect.get_parameters().into_iter().enumerate() {
+            if automated.contains(&(i, j)) {
+                let argument = effect_argument(i, j);
+                arguments += &format!(", {}={}", argument, value);
+                parameters.push(param(&argument));
+            } else {
+                parameters.push(value.into());
+            }
+        }
+        // each effect is applied to the result of the last, held in sig
+        lines += &format!("    sig = {};\n", effect.apply_parameters(
+            param("sig"), parameters).to_super_collider());
+    }
+    if fader {
+        arguments += ", volume=1, pan=0";
+        lines += "    sig = Balance2.ar(sig[0], sig[1], pan, volume);\n";
+    }
+    let mut sends: String = "".into();
+    for (i, level) in send_levels.iter().enumerate() {
+        arguments += &format!(", send{}=0", i);
+        sends += &format!("    Out.ar(send{}, sig * {});\n", i, level);
+    }
+    format!(k_effects_def_template!(), name=name, arguments=arguments,
+        effects=lines, sends=sends)
+}
@@ -0,0 +1,285 @@
+/// Transforms that make instrument clips sound played rather than quantized:
+/// swing, groove templates and humanization. They move the notes of a clip in
+/// time and change how hard they are played, but not what is written in the
+/// clip, so notation still shows the clip as written and the clip keeps its
+/// length. The arrangement keeps the transforms of each clip, in the order
+/// they apply, and every backend that plays notes follows them.
+///
+/// Timing offsets are rounded to MIDI ticks, and no note is moved before the
+/// start of its clip or before the note played before it. Every note lasts
+/// at least a tick, so that it ends after it starts.
+use arrangement::*;
+use clip::*;
+use duration::*;
+use midi::*;
+use random::*;
+
+/// A transform of the timing and velocity of the notes of a clip
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Transform {
+    /// Percentage and grid e.g. (66.7, 1/8): every second position of the
+    /// grid is delayed to the percentage of the pair it is in, and the time
+    /// around it stretched to match. 50 is straight, 66.7 is a triplet feel.
+    Swing(f64, Duration),
+    /// Timing and velocity offsets of a groove template
+    Groove(Groove),
+    /// Largest timing offset in whole notes, largest velocity offset and
+    /// seed of random offsets for every note
+    Humanize(f64, u8, u64),
+}
+
+/// A groove template: a timing and velocity offset for every position of a
+/// grid, repeated over the clip. Notes that start on the grid take the
+/// offsets of their position and other notes are left alone.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Groove {
+    /// Length of a position e.g. 1/16
+    pub grid: Duration,
+    /// Timing offset of each position in whole notes, late if positive
+    pub timing: Vec<f64>,
+    /// Velocity offset of each position
+    pub velocity: Vec<i16>,
+}
+
+/// Transforms put on a clip
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ClipTransforms {
+    /// Name of the clip
+    pub clip: String,
+    pub transforms: Vec<Transform>,
+}
+
+/// An event of a clip (a note, chord or rest) as it is played
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PlayedEvent {
+    /// Start from the start of the clip
+    pub start: Time,
+    pub duration: Duration,
+    /// 1 to 127
+    pub velocity: u8,
+}
+
+/// An event while it is transformed: where it is written, and how far its
+/// start and end have moved from there
+struct Event {
+    start: Time,
+    end: Time,
+    start_offset: f64,
+    end_offset: f64,
+    velocity: i32,
+}
+
+impl Transform {
+    /// Checks that swing is between 0 and 100 percent, that grids have a
+    /// length, that a groove has both offsets for every position and that
+    /// timing offsets are numbers
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Transform::Swing(percent, grid) => {
+                if !(percent > 0.0 && percent < 100.0) {
+                    return Err(format!("Swing of {}% is not between 0 and \
+                        100", percent));
+                }
+                if grid.is_zero() {
+                    return Err("A swing grid of no length".into());
+                }
+            },
+            Transform::Groove(ref groove) => {
+                if groove.grid.is_zero() {
+                    return Err("A groove grid of no length".into());
+                }
+                if groove.timing.is_empty() ||
+                    groove.timing.len() != groove.velocity.len() ||
+                    groove.timing.iter().any(|offset| !offset.is_finite()) {
+                    return Err("A groove needs a timing and a velocity \
+                        offset for every position".into());
+                }
+            },
+            Transform::Humanize(timing, _, _) => {
+                if !(timing >= 0.0 && timing.is_finite()) {
+                    return Err(format!("Invalid humanize timing {}", timing));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn apply(&self, events: &mut [Event]) {
+        match *self {
+            Transform::Swing(percent, grid) => {
+                let amount = percent / 100.0;
+                for event in events.iter_mut() {
+                    event.start_offset += swing_offset(event.start, grid,
+                        amount);
+                    event.end_offset += swing_offset(event.end, grid, amount);
+                }
+            },
+            Transform::Groove(ref groove) => {
+                for event in events.iter_mut() {
+                    let (index, rest) = grid_position(event.start,
+                        groove.grid);
+                    if !rest.is_zero() {
+                        continue;
+                    }
+                    let position = (index % groove.timing.len() as u64)
+                        as usize;
+                    event.start_offset += groove.timing[position];
+                    event.end_offset += groove.timing[position];
+                    event.velocity += groove.velocity[position] as i32;
+                }
+            },
+            Transform::Humanize(timing, velocity, seed) => {
+                let mut random = Random::new(seed);
+                for event in events.iter_mut() {
+                    let offset = (random.next_f64() * 2.0 - 1.0) * timing;
+                    event.start_offset += offset;
+                    event.end_offset += offset;
+                    event.velocity += ((random.next_f64() * 2.0 - 1.0) *
+                        velocity as f64).round() as i32;
+                }
+            },
+        }
+    }
+}
+
+impl Groove {
+    /// Takes a groove from notes given by their starts and velocities: each
+    /// position of the grid over the given length gets the average offset of
+    /// the notes nearest to it, and the average velocity of those notes less
+    /// the average velocity of all notes
+    pub fn from_notes(notes: &[(Time, u8)], length: Duration, grid: Duration)
+        -> Result<Groove, String> {
+        if grid.is_zero() {
+            return Err("A groove grid of no length".into());
+        }
+        let slots = length / grid;
+        let positions = ((slots.get_numerator() + slots.get_denominator() -
+            1) / slots.get_denominator()).max(1) as usize;
+        let mut timing = vec![0.0; positions];
+        let mut velocity = vec![0.0; positions];
+        let mut counts = vec![0; positions];
+        for &(start, note_velocity) in notes.iter() {
+            let (mut index, rest) = grid_position(start, grid);
+            if rest + rest > grid {
+                index += 1;
+            }
+            let position = (index % positions as u64) as usize;
+            timing[position] += start.to_f64() - (grid * index).to_f64();
+            velocity[position] += note_velocity as f64;
+            counts[position] += 1;
+        }
+        let average = notes.iter().map(|note| note.1 as f64).sum::<f64>() /
+            notes.len().max(1) as f64;
+        let mut groove = Groove {grid, timing: vec![0.0; positions],
+            velocity: vec![0; positions]};
+        for position in 0..positions {
+            if counts[position] > 0 {
+                let count = counts[position] as f64;
+                groove.timing[position] = timing[position] / count;
+                groove.velocity[position] = (velocity[position] / count -
+                    average).round() as i16;
+            }
+        }
+        Ok(groove)
+    }
+
+    /// Takes a groove from the notes of an instrument clip, which are all
+    /// played at the default velocity, or from the hits of a drum clip
+    pub fn from_clip(clip: &Clip, grid: Duration) -> Result<Groove, String> {
+        match *clip {
+            Clip::Instrument(_, _, ref melody, ref durations) => {
+                let mut notes: Vec<(Time, u8)> = Vec::new();
+                let mut time = Time::zero();
+                for (keys, duration) in melody.iter().zip(durations.iter()) {
+                    if keys.as_ref().is_some_and(|keys| !keys.is_empty()) {
+                        notes.push((time, DEFAULT_VELOCITY));
+                    }
+                    time += *duration;
+                }
+                Groove::from_notes(&notes, time, grid)
+            },
+            Clip::Drums(_, ref pattern) => {
+                let notes: Vec<(Time, u8)> = pattern.get_hits().iter()
+                    .map(|hit| (hit.start, hit.velocity)).collect();
+                Groove::from_notes(&notes, pattern.get_length(), grid)
+            },
+            _ => Err(format!("Clip \"{}\" has no notes to take a groove \
+                from", clip.get_name())),
+        }
+    }
+
+    /// Takes a groove from the notes of a Standard MIDI File, over the whole
+    /// notes (bars of 4/4) they start in
+    pub fn from_midi(data: &[u8], grid: Duration) -> Result<Groove, String> {
+        let notes = read_note_ons(data)?;
+        let last = notes.iter().map(|note| note.0).max()
+            .ok_or("The MIDI file has no notes")?;
+        let whole_notes = last.get_numerator() / last.get_denominator() + 1;
+        Groove::from_notes(&notes, Duration::new(whole_notes, 1), grid)
+    }
+}
+
+/// Plays the events of an instrument clip of the given durations with the
+/// given transforms
+pub fn perform_clip(durations: &[Duration], transforms: &[Transform])
+    -> Vec<PlayedEvent> {
+    let mut events: Vec<Event> = Vec::new();
+    let mut time = Time::zero();
+    for duration in durations.iter() {
+        events.push(Event {start: time, end: time + *duration,
+            start_offset: 0.0, end_offset: 0.0,
+            velocity: DEFAULT_VELOCITY as i32});
+        time += *duration;
+    }
+    for transform in transforms.iter() {
+        transform.apply(&mut events);
+    }
+    let tick = Duration::new(1, 4 * TICKS_PER_QUARTER as u64);
+    let mut played: Vec<PlayedEvent> = Vec::new();
+    for event in events.iter() {
+        let mut start = shift(event.start, event.start_offset);
+        if let Some(last) = played.last() {
+            start = start.max(last.start);
+        }
+        let end = shift(event.end, event.end_offset).max(start + tick);
+        played.push(PlayedEvent {start, duration: end - start,
+            velocity: event.velocity.clamp(1, 127) as u8});
+    }
+    played
+}
+
+/// The index of the position of the grid at or before a time, and the time
+/// since that position
+fn grid_position(time: Time, grid: Duration) -> (u64, Duration) {
+    let ratio = time / grid;
+    let index = ratio.get_numerator() / ratio.get_denominator();
+    (index, time - grid * index)
+}
+
+/// How far swing moves a time, in whole notes
+fn swing_offset(time: Time, grid: Duration, amount: f64) -> f64 {
+    let (index, _) = grid_position(time, grid);
+    let position = (time - grid * (index - index % 2)).to_f64();
+    let grid = grid.to_f64();
+    let swung = if position <= grid {
+        position * 2.0 * amount
+    } else {
+        grid * 2.0 * amount + (position - grid) * 2.0 * (1.0 - amount)
+    };
+    swung - position
+}
+
+/// Moves a time by an offset in whole notes, rounded to MIDI ticks, but not
+/// before zero
+fn shift(time: Time, offset: f64) -> Time {
+    let ticks_per_whole = 4 * TICKS_PER_QUARTER as u64;
+    let ticks = (offset * ticks_per_whole as f64).round();
+    if ticks >= 0.0 {
+        time + Duration::new(ticks as u64, ticks_per_whole)
+    } else {
+        time - time.min(Duration::new(-ticks as u64, ticks_per_whole))
+    }
+}
@@ -0,0 +1,158 @@
+/// Library of instruments built with the synth DSL (see synth.rs), which
+/// clips can be played with by name, e.g. instr_clip!(lead, sine, ...) or
+/// "clip lead sine { ... }" in a score. An arrangement only defines the
+/// SynthDefs of the instruments it uses.
+///
+/// Every instrument takes the parameters set by a Pbind: freq (Hz), amp and
+/// pan (-1 left to 1 right). Instruments that hold their notes also take
+/// gate, which the Pbind closes at the end of each note. The drums (kick,
+/// snare, hihat) ignore freq. Other parameters are listed with each
+/// instrument, with their defaults.
+use synth;
+use synth::*;
+
+/// Names of the instruments of the library
+pub const INSTRUMENTS: [&str; 10] = ["sine", "saw", "square", "pad", "epiano",
+    "pluck", "kick", "snare", "hihat", "bass"];
+
+/// Sine wave with a short attack and a fixed release, from this video:
+/// "https://www.youtube.com/watch?v=nB_bVJ1c1Rg"
+/// atk (0.005 s) and rel (0.3 s): attack and release times.
+pub fn sine() -> InstrumentHandle {
+    Instrument::new("sine",
+        &[("atk", 0.005), ("rel", 0.3), ("amp", 1.0), ("pan", 0.0)],
+        pan2(sin_osc(param("freq")), param("pan"), param("amp")) *
+            env_gen(envelope(vec![0.0.into(), 1.0.into(), 0.0.into()],
+                vec![param("atk"), param("rel")], vec![1.0, -1.0]), 1.0))
+        .handle()
+}
+
+/// Saw lead: a sawtooth wave through a resonant low pass filter, held until
+/// the end of the note.
+/// cutoff (3000 Hz): cutoff of the filter.
+/// rq (0.3): reciprocal of the filter's Q, lower is more resonant.
+pub fn saw() -> InstrumentHandle {
+    Instrument::new("saw", &[("cutoff", 3000.0), ("rq", 0.3)],
+        pan2(rlpf(synth::saw(param("freq")), param("cutoff"), param("rq")),
+            param("pan"), param("amp")) * adsr(0.01, 0.1, 0.7, 0.2))
+        .handle()
+}
+
+/// Square lead: a pulse wave through a low pass filter, held until the end
+/// of the note.
+/// width (0.5): width of the pulse, 0.5 is square and less is thinner.
+/// cutoff (4000 Hz): cutoff of the filter.
+pub fn square() -> InstrumentHandle {
+    Instrument::new("square", &[("width", 0.5), ("cutoff", 4000.0)],
+        pan2(lpf(pulse(param("freq"), param("width")), param("cutoff")),
+            param("pan"), param("amp")) * adsr(0.01, 0.1, 0.7, 0.2))
+        .handle()
+}
+
+/// Subtractive pad: two detuned sawtooth waves through a low pass filter
+/// that opens during the attack, with a long attack and release.
+/// detune (0.005): detuning of each saw, as a fraction of freq.
+/// cutoff (1500 Hz): cutoff of the filter once open.
+/// atk (0.8 s) and rel (1.5 s): attack and release times.
+pub fn pad() -> InstrumentHandle {
+    let saws = synth::saw(param("freq") * (1.0 - param("detune"))) +
+        synth::saw(param("freq") * (1.0 + param("detune")));
+    let cutoff = line(param("cutoff") * 0.3, param("cutoff"), param("atk"));
+    Instrument::new("pad", &[("detune", 0.005), ("cutoff", 1500.0),
+        ("atk", 0.8), ("rel", 1.5)],
+        pan2(lpf(saws * 0.5, cutoff), param("pan"), param("amp")) *
+            adsr(param("atk"), 0.5, 0.8, param("rel")))
+        .handle()
+}
+
+/// FM electric piano: a sine carrier phase modulated by a sine whose depth
+/// fades during the note, so that the bright attack mellows.
+/// ratio (1): frequency of the modulator as a multiple of freq.
+/// index (2): depth of the modulation at the start of the note.
+/// decay (1.5 s): time over which the modulation and the level fade.
+pub fn epiano() -> InstrumentHandle {
+    let modulator = sin_osc(param("freq") * param("ratio")) *
+        line(param("index"), 0.0, param("decay"));
+    // SinOsc.ar(freq, phase)
+    let carrier = ugen("SinOsc", vec![param("freq"), modulator]);
+    Instrument::new("epiano", &[("ratio", 1.0), ("index", 2.0),
+        ("decay", 1.5)],
+        pan2(carrier, param("pan"), param("amp")) *
+            adsr(0.002, param("decay"), 0.3, 0.4))
+        .handle()
+}
+
+/// Karplus-Strong plucked string, which rings on after the end of the note.
+/// decay (2 s): time the string rings for.
+/// coef (0.3): damping of high frequencies, from -1 to 1.
+pub fn pluck() -> InstrumentHandle {
+    Instrument::new("pluck", &[("decay", 2.0), ("coef", 0.3)],
+        pan2(synth::pluck(white_noise(), 1.0 / param("freq"), param("decay"),
+            param("coef")), param("pan"), param("amp")) *
+            perc(0.001, param("decay")))
+        .handle()
+}
+
+/// 808-style kick: a sine wave whose pitch falls quickly to its tone.
+/// tone (50 Hz): pitch the kick settles on.
+/// decay (0.8 s): length of the kick.
+pub fn kick() -> InstrumentHandle {
+    Instrument::new("kick", &[("tone", 50.0), ("decay", 0.8), ("amp", 0.5)],
+        pan2(sin_osc(x_line(param("tone") * 4.0, param("tone"), 0.05)),
+            param("pan"), param("amp")) * perc(0.002, param("decay")))
+        .handle()
+}
+
+/// Snare: a short sine body under a burst of high passed noise.
+/// tone (180 Hz): pitch of the body.
+/// decay (0.2 s): length of the noise, the body lasts half as long.
+pub fn snare() -> InstrumentHandle {
+    // only the envelope of the noise frees the synth, it is the longest
+    let body = sin_osc(param("tone")) * line(1.0, 0.0, param("decay") * 0.5);
+    let noise = hpf(white_noise(), 1500.0) * perc(0.001, param("decay"));
+    Instrument::new("snare", &[("tone", 180.0), ("decay", 0.2),
+        ("amp", 0.3)],
+        pan2(body * 0.5 + noise, param("pan"), param("amp")))
+        .handle()
+}
+
+/// Hi-hat: high passed noise; a longer decay gives an open hi-hat.
+/// cutoff (8000 Hz): lowest frequency of the noise.
+/// decay (0.08 s): length of the hit.
+pub fn hihat() -> InstrumentHandle {
+    Instrument::new("hihat", &[("cutoff", 8000.0), ("decay", 0.08),
+        ("amp", 0.2)],
+        pan2(hpf(white_noise(), param("cutoff")), param("pan"),
+            param("amp")) * perc(0.001, param("decay")))
+        .handle()
+}
+
+/// Bass: a sawtooth wave and a square wave an octave below it, through a
+/// resonant low pass filter, held until the end of the note.
+/// cutoff (800 Hz): cutoff of the filter.
+/// rq (0.4): reciprocal of the filter's Q, lower is more resonant.
+pub fn bass() -> InstrumentHandle {
+    let oscillators = synth::saw(param("freq")) +
+        pulse(param("freq") * 0.5, 0.5);
+    Instrument::new("bass", &[("cutoff", 800.0), ("rq", 0.4), ("amp", 0.2)],
+        pan2(rlpf(oscillators * 0.5, param("cutoff"), param("rq")),
+            param("pan"), param("amp")) * adsr(0.005, 0.1, 0.8, 0.1))
+        .handle()
+}
+
+/// Looks up an instrument of the library by name
+pub fn builtin_instrument(name: &str) -> Option<InstrumentHandle> {
+    match name {
+        "sine" => Some(sine()),
+        "saw" => Some(saw()),
+        "square" => Some(square()),
+        "pad" => Some(pad()),
+        "epiano" => Some(epiano()),
+        "pluck" => Some(pluck()),
+        "kick" => Some(kick()),
+        "snare" => Some(snare()),
+        "hihat" => Some(hihat()),
+        "bass" => Some(bass()),
+        _ => None,
+    }
+}
@@ -0,0 +1,301 @@
+/// Defines the interval abstraction. An interval is the distance between two
+/// notes, named by a quality (major, minor, perfect, ...) and a number (third,
+/// fifth, ninth, ...) e.g. m3, P5, A4 and M9. Intervals let theory-level code
+/// talk about "a major third above" instead of counting half steps.
+use note::*;
+use scale::*;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+// half steps from the root to the major/perfect interval of each simple number
+const BASE_HALF_STEPS: [i16; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum Quality {
+    Diminished,
+    Minor,
+    Major,
+    Perfect,
+    Augmented
+}
+
+/// Consonance classification following common-practice harmony. Fourths are
+/// treated as perfect consonances, as they are when they appear above the bass
+/// of a chord.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum Consonance {
+    Perfect,
+    Imperfect,
+    Dissonant
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct Interval {
+    quality: Quality,
+    number: u8,
+}
+
+/// Unisons, fourths, fifths and octaves (and their compounds) take perfect
+/// qualities, all other numbers take major/minor qualities.
+fn is_perfect_number(number: u8) -> bool {
+    matches!((number - 1) % 7, 0 | 3 | 4)
+}
+
+impl Interval {
+    /// Creates an interval from a quality and a number (1 is a unison, 8 an
+    /// octave, 9 a compound second and so on).
+    /// Panics if the quality does not apply to the number e.g. a major fifth.
+    pub fn new(quality: Quality, number: u8) -> Interval {
+        assert!(number > 0, "Interval numbers start at 1 (unison)");
+        let perfect = is_perfect_number(number);
+        match quality {
+            Quality::Perfect => assert!(perfect, "Only unisons, fourths, \
+                fifths and octaves can be perfect"),
+            Quality::Major | Quality::Minor => assert!(!perfect, "Unisons, \
+                fourths, fifths and octaves cannot be major or minor"),
+            Quality::Diminished => assert!(number > 1,
+                "A unison cannot be diminished"),
+            Quality::Augmented => {},
+        }
+        Interval {quality, number}
+    }
+
+    /// Names the interval spanning the given number of half steps, preferring
+    /// the augmented fourth for the tritone.
+    pub fn from_half_steps(num_half_steps: u8) -> Interval {
+        let qualities = [Quality::Perfect, Quality::Minor, Quality::Major,
+            Quality::Minor, Quality::Major, Quality::Perfect,
+            Quality::Augmented, Quality::Perfect, Quality::Minor,
+            Quality::Major, Quality::Minor, Quality::Major];
+        let numbers: [u8; 12] = [1, 2, 2, 3, 3, 4, 4, 5, 6, 6, 7, 7];
+        let octaves = num_half_steps / NUM_TONES;
+        let pos = (num_half_steps % NUM_TONES) as usize;
+        // a whole number of octaves is named as an octave rather than a unison
+        if pos == 0 && octaves > 0 {
+            return Interval::new(Quality::Perfect, 7 * octaves + 1);
+        }
+        Interval::new(qualities[pos], numbers[pos] + 7 * octaves)
+    }
+
+    pub fn get_quality(&self) -> Quality {
+        self.quality
+    }
+
+    pub fn get_number(&self) -> u8 {
+        self.number
+    }
+
+    /// Returns the number of half steps spanned by this interval. Intervals
+    /// of up to 255 letters span up to 444 half steps, hence i16.
+    pub fn half_steps(&self) -> i16 {
+        let octaves = ((self.number - 1) / 7) as i16;
+        let base = BASE_HALF_STEPS[((self.number - 1) % 7) as usize];
+        let adjustment = match self.quality {
+            Quality::Perfect | Quality::Major => 0,
+            Quality::Minor => -1,
+            Quality::Augmented => 1,
+            Quality::Diminished if is_perfect_number(self.number) => -1,
+            Quality::Diminished => -2,
+        };
+        octaves * (NUM_TONES as i16) + base + adjustment
+    }
+
+    /// Returns the number of letters spanned by this interval e.g. 2 for a
+    /// third, as used when spelling the note an interval away from another.
+    /// Intervals of up to 255 letters span up to 254 steps, hence i16.
+    pub fn letter_steps(&self) -> i16 {
+        i16::from(self.number) - 1
+    }
+
+    /// Compound intervals span more than an octave e.g. a ninth
+    pub fn is_compound(&self) -> bool {
+        self.number > 8
+    }
+
+    /// Reduces a compound interval to its simple form e.g. M9 becomes M2.
+    /// Octaves (and multiples of octaves) reduce to an octave.
+    pub fn simple(&self) -> Interval {
+        let mut number = self.number;
+        while number > 8 {
+            number -= 7;
+        }
+        Interval {quality: self.quality, number}
+    }
+
+    /// Returns the inversion of this interval, e.g. the inversion of a major
+    /// third is a minor sixth. Compound intervals are reduced before
+    /// inverting. An augmented octave is an augmented unison an octave up,
+    /// so it inverts to a diminished octave like the unison does.
+    pub fn invert(&self) -> Interval {
+        let simple = self.simple();
+        if simple == Interval::new(Quality::Augmented, 8) {
+            return Interval::new(Quality::Diminished, 8);
+        }
+        let quality = match simple.quality {
+            Quality::Diminished => Quality::Augmented,
+            Quality::Minor => Quality::Major,
+            Quality::Major => Quality::Minor,
+            Quality::Perfect => Quality::Perfect,
+            Quality::Augmented => Quality::Diminished,
+        };
+        Interval::new(quality, 9 - simple.number)
+    }
+
+    pub fn consonance(&self) -> Consonance {
+        match (self.quality, (self.number - 1) % 7) {
+            (Quality::Perfect, _) => Consonance::Perfect,
+            (Quality::Major, 2) | (Quality::Minor, 2) |
+            (Quality::Major, 5) | (Quality::Minor, 5) => Consonance::Imperfect,
+            _ => Consonance::Dissonant,
+        }
+    }
+
+    pub fn is_consonant(&self) -> bool {
+        self.consonance() != Consonance::Dissonant
+    }
+}
+
+/// Arabic numbers describe tones relative to a major scale, so naturals map to
+/// major/perfect intervals, flats lower them and sharps augment them
+/// e.g. b3 is a minor third, b5 a diminished fifth and #11 an augmented 11th.
+/// Numbers below 1 and a flat unison (b1) give an error.
+impl TryFrom<ArabicNum> for Interval {
+    type Error = String;
+    fn try_from(arabic_num: ArabicNum) -> Result<Interval, String> {
+        let (num, flat, sharp) = match arabic_num {
+            ArabicNum::Natural(num) => (num, false, false),
+            ArabicNum::Flat(num) => (num, true, false),
+            ArabicNum::Sharp(num) => (num, false, true),
+        };
+        let number = match u8::try_from(num) {
+            Ok(number) if number > 0 => number,
+            _ => return Err(format!("Invalid interval number {}", num)),
+        };
+        let quality = match (is_perfect_number(number), flat, sharp) {
+            (_, _, true) => Quality::Augmented,
+            (true, true, _) if number == 1 => {
+                return Err("A unison cannot be flattened".into());
+            },
+            (true, true, _) => Quality::Diminished,
+            (true, false, _) => Quality::Perfect,
+            (false, true, _) => Quality::Minor,
+            (false, false, _) => Quality::Major,
+        };
+        Ok(Interval::new(quality, number))
+    }
+}
+
+/// Adding an interval keeps the spelling implied by its number, so a major
+/// third above D is F# while a diminished fourth above D is Gb. Like adding
+/// half steps, this panics if the note leaves the MIDI range.
+impl Add<Interval> for Note {
+    type Output = Note;
+    fn add(self, interval: Interval) -> Note {
+        let note = self.add_half_steps(midi_half_steps(interval));
+        let letter = self.get_letter().add_steps(simple_letter_steps(interval));
+        note.respell(letter).unwrap_or(note)
+    }
+}
+
+/// The half steps of an interval that fits within the MIDI range
+fn midi_half_steps(interval: Interval) -> i8 {
+    i8::try_from(interval.half_steps())
+        .expect("Note lies outside of the MIDI range")
+}
+
+/// The letters spanned by an interval, less whole octaves of letters, which
+/// do not change the letter of a note
+fn simple_letter_steps(interval: Interval) -> i8 {
+    i8::try_from(interval.letter_steps() % NUM_LETTERS as i16)
+        .expect("Fewer than seven steps fit in an i8")
+}
+
+impl Sub<Interval> for Note {
+    type Output = Note;
+    fn sub(self, interval: Interval) -> Note {
+        let note = self.add_half_steps(-midi_half_steps(interval));
+        let letter = self.get_letter()
+            .add_steps(-simple_letter_steps(interval));
+        note.respell(letter).unwrap_or(note)
+    }
+}
+
+/// The interval between two notes, regardless of which of them is higher.
+/// The number is read from the spelling of the notes, so C4 to D#4 is an
+/// augmented second while C4 to Eb4 is a minor third.
+impl Sub<Note> for Note {
+    type Output = Interval;
+    fn sub(self, other: Note) -> Interval {
+        let (low, high) = if (self.diatonic_index(), self.get_midi_value()) <
+            (other.diatonic_index(), other.get_midi_value()) {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let num_half_steps = (high.get_midi_value() - low.get_midi_value())
+            as i16;
+        let number = (high.diatonic_index() - low.diatonic_index() + 1) as u8;
+        let major_or_perfect = Interval {quality: Quality::Perfect, number}
+            .half_steps();
+        let quality = match (num_half_steps - major_or_perfect,
+            is_perfect_number(number)) {
+            (-1, true) if number > 1 => Some(Quality::Diminished),
+            (0, true) => Some(Quality::Perfect),
+            (1, _) => Some(Quality::Augmented),
+            (-2, false) => Some(Quality::Diminished),
+            (-1, false) => Some(Quality::Minor),
+            (0, false) => Some(Quality::Major),
+            _ => None,
+        };
+        match quality {
+            Some(quality) => Interval {quality, number},
+            // e.g. doubly augmented intervals, which are named by half steps
+            None => Interval::from_half_steps(num_half_steps.unsigned_abs()
+                as u8),
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quality = match self.quality {
+            Quality::Diminished => "d",
+            Quality::Minor => "m",
+            Quality::Major => "M",
+            Quality::Perfect => "P",
+            Quality::Augmented => "A",
+        };
+        write!(f, "{}{}", quality, self.number)
+    }
+}
+
+/// Parses the short interval names produced by Display e.g. "m3", "P5", "M9"
+impl FromStr for Interval {
+    type Err = &'static str;
+    fn from_str(interval: &str) -> Result<Self, Self::Err> {
+        let mut chars = interval.chars();
+        let quality = match chars.next() {
+            Some('d') => Quality::Diminished,
+            Some('m') => Quality::Minor,
+            Some('M') => Quality::Major,
+            Some('P') => Quality::Perfect,
+            Some('A') => Quality::Augmented,
+            _ => return Err("Invalid quality for interval"),
+        };
+        let number: u8 = match chars.as_str().parse() {
+            Ok(number) if number > 0 => number,
+            _ => return Err("Invalid number for interval"),
+        };
+        let perfect = is_perfect_number(number);
+        match quality {
+            Quality::Perfect if !perfect => Err("Invalid input for interval"),
+            Quality::Major | Quality::Minor if perfect =>
+                Err("Invalid input for interval"),
+            Quality::Diminished if number == 1 =>
+                Err("Invalid input for interval"),
+            _ => Ok(Interval::new(quality, number)),
+        }
+    }
+}
@@ -5,30 +5,84 @@ extern crate lazy_static;
 extern crate custom_derive;
 #[macro_use]
 extern crate enum_derive;
+extern crate xml;
+#[cfg(feature = "serialize")]
+extern crate serde;
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "serialize")]
+extern crate serde_json;
+#[cfg(feature = "serialize")]
+extern crate toml;
 #[macro_use]
 pub mod super_collider_templates;
 #[macro_use]
 pub mod syntax;
+pub mod abc;
 pub mod arrangement;
+pub mod automation;
+pub mod backend;
 pub mod chord;
 pub mod clip;
+pub mod drums;
+pub mod dsp;
+pub mod duration;
+pub mod effects;
+pub mod groove;
+pub mod instruments;
+pub mod interval;
+pub mod lilypond;
+pub mod midi;
+pub mod musicxml;
+pub mod notation;
 pub mod note;
+pub mod osc;
+#[cfg(feature = "serialize")]
+pub mod project;
+pub mod random;
+pub mod render;
+pub mod rhythm;
 pub mod scale;
+pub mod score;
+pub mod sequencer;
+pub mod session;
+pub mod synth;
 pub mod track;
 pub mod translate;
+pub mod tuning;
 
 
 
 #[cfg(test)]
 mod tests {
+    use abc::*;
     use arrangement::*;
+    use automation::*;
     use chord::*;
     use clip::*;
+    use drums::*;
+    use duration::*;
+    use effects::*;
+    use groove::*;
+    use instruments::*;
+    use interval::*;
+    use lilypond::*;
+    use midi::*;
+    use musicxml::*;
     use note::*;
+    use osc::*;
+    use render::*;
+    use rhythm::*;
     use scale::*;
+    use score::*;
+    use sequencer::*;
+    use session::*;
+    use synth::*;
     use syntax::*;
     use track::*;
     use translate::*;
+    use tuning::*;
     use super_collider_templates::*;
 
     #[test]
@@ -42,6 +96,247 @@ mod tests {
         assert_eq!(b_natural, d_natural.flat().flat().flat());
     }
 
+    #[test]
+    fn note_parsing_tests() {
+        assert_eq!(n!(C-1).get_midi_value(), 0);
+        assert_eq!(n!(G9).get_midi_value(), 127);
+        assert!(n!(F##4).same_spelling(&Note::spelled(Letter::F,
+            Accidental::DoubleSharp, 4)));
+        assert!(n!(Bbb3).same_spelling(&Note::spelled(Letter::B,
+            Accidental::DoubleFlat, 3)));
+        assert!(n!(c4).same_spelling(&n!(C4)));
+        assert!(n!(bb3).same_spelling(&n!(Bb3)));
+        assert_eq!(n!(Ab-1).get_octave(), -1);
+        assert_eq!(n!("E4-14c"), n!(E4).with_cents(-14));
+        assert!(n!(Cs4).same_spelling(&n!("C#4")));
+        for note in ["F#4", "Bbb3", "C-1", "G9", "E4-14c", "A4+50c"].iter() {
+            assert_eq!(note.parse::<Note>().unwrap().to_string(), *note);
+        }
+        for note in ["", "H4", "C", "C#", "Cbbb4", "C4x", "Ab9", "C-2",
+            "G#9", "C10", "C4+c", "4C"].iter() {
+            assert!(note.parse::<Note>().is_err(), "{} should not parse", note);
+        }
+    }
+
+    #[test]
+    fn spelling_tests() {
+        let f_sharp = n!(Fs4);
+        assert!(f_sharp.same_spelling(&Note::spelled(Letter::F,
+            Accidental::Sharp, 4)));
+        // notes compare by pitch, whatever their spelling
+        assert_eq!(f_sharp, n!(Gb4));
+        assert!(!f_sharp.same_spelling(&n!(Gb4)));
+        assert!(f_sharp.is_enharmonic(&n!(Gb4)));
+        assert_ne!(f_sharp, n!(Gb4).with_cents(10));
+        assert_eq!(f_sharp.to_string(), "F#4");
+        assert_eq!(f_sharp.get_name(), Name::Gb);
+        assert_eq!(n!(Bs3).get_midi_value(), n!(C4).get_midi_value());
+        assert_eq!(n!(Cbb4).get_midi_value(), 58);
+        assert_eq!(n!(Fx4).to_string(), "F##4");
+        let d_major = Scale::new(n!(D4), ScaleType::Major);
+        assert_eq!(d_major.get_degree(2).to_string(), "F#4");
+        assert_eq!(d_major.get_degree(6).to_string(), "C#5");
+        assert_eq!(d_major.spell(&n!(Gb4)).to_string(), "F#4");
+        assert_eq!(d_major.spell(&n!(Ab4)).to_string(), "G#4");
+        let f_major = Scale::new(n!(F4), ScaleType::Major);
+        assert_eq!(f_major.get_degree(3).to_string(), "Bb4");
+        assert_eq!(f_major.spell(&n!(Fs4)).to_string(), "Gb4");
+        let e_dom7 = Chord::new(n!(E4), ChordType::Dom7);
+        let e_dom7_names: Vec<String> = e_dom7.get_voicing(0).iter()
+            .map(|note| note.to_string()).collect();
+        assert_eq!(e_dom7_names, vec!["E4", "G#4", "B4", "D5"]);
+        let c_aug = chord!(C4, Aug);
+        assert_eq!(c_aug.spell(&n!(Ab4)).to_string(), "G#4");
+        assert_eq!(c_aug.get_voicing(0)[2].to_string(), "G#4");
+        assert!((n!(D4) + "M3".parse::<Interval>().unwrap())
+            .same_spelling(&n!(Fs4)));
+        assert!((n!(D4) + "d4".parse::<Interval>().unwrap())
+            .same_spelling(&n!(Gb4)));
+        // the ##5 of a scale whose 5th is already sharp cannot keep its
+        // letter, so it takes the next letter up
+        let g_sharp_lydian = Scale::new(n!(Gs4), ScaleType::Lydian);
+        assert_eq!(g_sharp_lydian.get_arabic_num(&ArabicNum::Sharp(4),
+            &n!(Gs4)).to_string(), "D#5");
+        assert_eq!(n!(Ds4) - n!(C4), "A2".parse().unwrap());
+        assert_eq!(n!(Eb4) - n!(C4), "m3".parse().unwrap());
+        assert_eq!(n!(C4) - n!(Bs3), "d2".parse().unwrap());
+    }
+
+    #[test]
+    fn interval_tests() {
+        use std::convert::TryFrom;
+        let minor_third: Interval = "m3".parse().unwrap();
+        assert_eq!(minor_third.half_steps(), 3);
+        assert_eq!(n!(C4) + minor_third, n!(Eb4));
+        assert_eq!(n!(G4) - n!(C4), Interval::new(Quality::Perfect, 5));
+        assert_eq!(n!(C4) - n!(G4), Interval::new(Quality::Perfect, 5));
+        assert_eq!(n!(D5) - n!(C4), Interval::new(Quality::Major, 9));
+        assert_eq!(n!(C5) - n!(C4), Interval::new(Quality::Perfect, 8));
+        assert_eq!(Interval::new(Quality::Major, 9).half_steps(), 14);
+        assert_eq!(Interval::new(Quality::Major, 9).simple().to_string(), "M2");
+        assert_eq!(Interval::new(Quality::Major, 3).invert().to_string(), "m6");
+        assert_eq!(Interval::new(Quality::Augmented, 4).invert().to_string(),
+            "d5");
+        assert_eq!(Interval::new(Quality::Perfect, 1).invert().to_string(),
+            "P8");
+        // augmented and diminished octaves invert to valid intervals
+        assert_eq!(Interval::new(Quality::Augmented, 8).invert().to_string(),
+            "d8");
+        assert_eq!(Interval::new(Quality::Diminished, 8).invert().to_string(),
+            "A1");
+        assert_eq!(Interval::from_half_steps(200).half_steps(), 200);
+        assert_eq!(Interval::new(Quality::Perfect, 5).consonance(),
+            Consonance::Perfect);
+        assert_eq!(minor_third.consonance(), Consonance::Imperfect);
+        assert!(!Interval::new(Quality::Augmented, 4).is_consonant());
+        let arabic = |num| Interval::try_from(num).map(|interval|
+            interval.to_string());
+        assert_eq!(arabic(ArabicNum::Flat(7)), Ok("m7".into()));
+        assert_eq!(arabic(ArabicNum::Flat(5)), Ok("d5".into()));
+        assert_eq!(arabic(ArabicNum::Natural(4)), Ok("P4".into()));
+        assert_eq!(arabic(ArabicNum::Sharp(1)), Ok("A1".into()));
+        assert_eq!(Interval::try_from(ArabicNum::Sharp(11)).unwrap()
+            .half_steps(), 18);
+        assert!(arabic(ArabicNum::Flat(1)).is_err());
+        assert!(arabic(ArabicNum::Natural(0)).is_err());
+        assert!(arabic(ArabicNum::Sharp(-3)).is_err());
+        assert_eq!(Interval::new(Quality::Major, 198).letter_steps(), 197);
+        assert_eq!(n!(C4) + Interval::new(Quality::Major, 10), n!(E5));
+        assert_eq!(n!(E5) - Interval::new(Quality::Major, 10), n!(C4));
+        assert!("P3".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn tuning_tests() {
+        let equal = Tuning::default();
+        let freq = |tuning: &Tuning, note: Note| {
+            tuning.frequency(&note, CONCERT_A).unwrap()
+        };
+        assert!(equal.is_equal_temperament());
+        assert!((freq(&equal, n!(A4)) - 440.0).abs() < 1e-9);
+        assert!((freq(&equal, n!(C4)) - 261.626).abs() < 1e-3);
+        let sharp_a = n!(A4).with_cents(50);
+        assert_eq!(sharp_a.to_string(), "A4+50c");
+        assert!((freq(&equal, sharp_a) - 452.893).abs() < 1e-3);
+        // in 19-EDO sharps and flats are different pitches
+        let edo19 = Tuning::Edo(19);
+        assert!(freq(&edo19, n!(Cs4)) < freq(&edo19, n!(Db4)));
+        assert!((freq(&edo19, n!(A4)) - 440.0).abs() < 1e-9);
+        assert!((freq(&edo19, n!(A5)) - 880.0).abs() < 1e-9);
+        let just = Tuning::just(Name::C);
+        let c4 = freq(&just, n!(C4));
+        assert!((freq(&just, n!(E4)) / c4 - 1.25).abs() < 1e-9);
+        assert!((freq(&just, n!(G3)) / c4 - 0.75).abs() < 1e-9);
+        let (key, bend) = just.pitch_bend(&n!(E4), CONCERT_A).unwrap();
+        assert_eq!(key, 64);
+        assert!(bend < 0); // a just major third is flatter than 12-TET
+        assert_eq!(just.mts_messages(0x7f, 0, CONCERT_A).len(), 2);
+
+        let scl = "! pentatonic.scl\n!\nSlendro-like pentatonic\n 5\n!\n \
+            240.0\n 480.0\n 720.0\n 960.0\n 2/1\n";
+        let pentatonic = Tuning::from_scala(scl, None).unwrap();
+        let c4 = pentatonic.key_frequency(60, CONCERT_A).unwrap();
+        assert!((pentatonic.key_frequency(61, CONCERT_A).unwrap() / c4
+            - cents_to_ratio(240.0)).abs() < 1e-9);
+        assert!((pentatonic.key_frequency(65, CONCERT_A).unwrap() / c4
+            - 2.0).abs() < 1e-9);
+        let kbm = "! white keys only\n12\n0\n127\n60\n69\n440.0\n5\n\
+            0\nx\n1\nx\n2\n3\nx\n4\nx\nx\nx\nx\n";
+        let mapped = Tuning::from_scala(scl, Some(kbm)).unwrap();
+        assert_eq!(mapped.key_frequency(61, CONCERT_A), None);
+        assert!((mapped.key_frequency(72, CONCERT_A).unwrap()
+            / mapped.key_frequency(60, CONCERT_A).unwrap() - 2.0).abs() < 1e-9);
+        assert!(Tuning::from_scala("bad\n3\n100.0\n", None).is_err());
+        // sizes and keys out of range are errors rather than casts
+        let negative = "-1\n0\n127\n60\n69\n440.0\n5\n";
+        assert!(Tuning::from_scala(scl, Some(negative)).is_err());
+        let high_key = "12\n0\n200\n60\n69\n440.0\n5\n";
+        assert!(Tuning::from_scala(scl, Some(high_key)).is_err());
+        assert!(Tuning::Edo(0).validate().is_err());
+        assert_eq!(Tuning::Edo(0).frequency(&n!(A4), CONCERT_A), None);
+        let mut arr = Arrangement::new(vec![], vec![]);
+        arr.set_tuning(Tuning::Edo(0));
+        assert!(arr.validate().is_err());
+
+        let clip = Clip::Instrument("v1".into(), sine(),
+            play!(vec![n!(C4), n!(E4)], ()), rhythm![H, H]);
+        let output = tuned_clip_to_super_collider(&clip, &just, CONCERT_A);
+        assert!(output.contains("\\freq, Pseq([[261.626, 327.032],Rest(),])"));
+        // clips keep the spelling of their notes, so C#4 and Db4 sound apart
+        // in 19-EDO in every backend
+        let clip = instr_clip!(v1, sine, play!(n!(Cs4), n!(Db4)),
+            rhythm![H, H]);
+        let output = tuned_clip_to_super_collider(&clip, &edo19, CONCERT_A);
+        assert!(output.contains(&format!("\\freq, Pseq([[{:.3}],[{:.3}],])",
+            freq(&edo19, n!(Cs4)), freq(&edo19, n!(Db4)))));
+        let mut arr = Arrangement::new(vec![track!(t1, v1)], vec![clip]);
+        arr.set_tuning(edo19.clone());
+        let events = arrangement_events(&arr);
+        let frequencies: Vec<Option<f64>> = events.iter()
+            .filter_map(|event| match event.event {
+                SequencerEvent::NoteOn {frequency, ..} => Some(frequency),
+                _ => None,
+            }).collect();
+        assert_eq!(frequencies, vec![Some(freq(&edo19, n!(Cs4))),
+            Some(freq(&edo19, n!(Db4)))]);
+        // played together, they share a MIDI key but are still told apart
+        // when they end
+        let chord = instr_clip!(v2, sine, play!(vec![n!(Cs4), n!(Db4)]),
+            rhythm![H]);
+        let mut together = Arrangement::new(vec![track!(t1, v2)],
+            vec![chord]);
+        together.set_tuning(edo19.clone());
+        let notes: Vec<(bool, usize)> = arrangement_events(&together).iter()
+            .map(|event| match event.event {
+                SequencerEvent::NoteOn {note, ..} => (true, note),
+                SequencerEvent::NoteOff {note, ..} => (false, note),
+            }).collect();
+        assert_eq!(notes, vec![(true, 0), (true, 1), (false, 0),
+            (false, 1)]);
+        // MIDI files bend C#4 away from key 61, which is retuned to Db4, and
+        // bend back for Db4
+        let midi = arrangement_to_midi(&arr);
+        let bend = (bend_to(ratio_to_cents(freq(&edo19, n!(Cs4)) /
+            freq(&edo19, n!(Db4))) / 100.0) + 8192) as u16;
+        assert!(midi.windows(3).any(|bytes| bytes == [0xe0,
+            (bend & 0x7f) as u8, (bend >> 7) as u8]));
+        assert!(midi.windows(3).any(|bytes| bytes == [0xe0, 0, 0x40]));
+        // detuned notes reach backends in twelve-tone equal temperament too
+        let clip = instr_clip!(v1, sine, play!(sharp_a), rhythm![W]);
+        assert!(clip_to_super_collider(&clip)
+            .contains("\\freq, Pseq([[452.893],])"));
+    }
+
+    #[test]
+    fn frequency_tests() {
+        assert!((n!(A4).frequency(440.0) - 440.0).abs() < 1e-9);
+        assert!((n!(A4).frequency(415.0) - 415.0).abs() < 1e-9);
+        assert!((n!(A5).frequency(432.0) - 864.0).abs() < 1e-9);
+        assert!((n!(C4).frequency(440.0) - 261.626).abs() < 1e-3);
+        let (note, cents) = Note::from_frequency(261.626, 440.0).unwrap();
+        assert_eq!(note, n!(C4));
+        assert!(cents.abs() < 0.01);
+        let (note, cents) = Note::from_frequency(450.0, 440.0).unwrap();
+        assert_eq!(note, n!(A4));
+        assert!((cents - 38.906).abs() < 1e-3);
+        // at A=415 the baroque A sounds like a modern Ab
+        let (note, cents) = Note::from_frequency(415.0, 440.0).unwrap();
+        assert_eq!(note, n!(Ab4));
+        assert!(cents.abs() < 2.0);
+        for &freq in &[0.0, -440.0, f64::NAN, f64::INFINITY, 1e-3, 1e6] {
+            assert_eq!(Note::from_frequency(freq, 440.0), None);
+        }
+        assert_eq!(Note::from_frequency(440.0, 0.0), None);
+
+        let clip = Clip::Instrument("v1".into(), sine(),
+            play!(n!(A4)), rhythm![W]);
+        let mut arr = Arrangement::new(vec![track!(t1, v1)], vec![clip]);
+        assert!(arrangement_to_super_collider(&arr).contains("\\midinote"));
+        arr.set_concert_pitch(432.0);
+        assert!(arrangement_to_super_collider(&arr)
+            .contains("\\freq, Pseq([[432.000],])"));
+    }
+
     #[test]
     fn scale_tests() {
         let c_major = Scale::new(Note::new(Name::C, 4), ScaleType::Major);
@@ -70,9 +365,9 @@ mod tests {
     #[test]
     fn clip_translation_tests() { // TODO: add config files for tests
         let ii_chord = Chord::new(Note::new(Name::D, 4), ChordType::Min7);
-        let ii_chord_clip = Clip::Instrument("v1".into(), "sine".into(), 
+        let ii_chord_clip = Clip::Instrument("v1".into(), sine(), 
             vec![Some(ii_chord.play()), Some(ii_chord.play())],
-            vec![1.0, 1.0]);
+            rhythm![W, W]);
         let expected_ii =
 "
 v1 = Pbind(
@@ -92,23 +387,1097 @@ v1 = Pbind(
     fn track_translation_tests() {
         let t = Track::new("t1".into(), vec!["v1".into(), "v2".into()]);
         //println!("Track output: {}", track_to_super_collider(&t));
+        assert_eq!(track_to_super_collider(&t), "\nt1 = Pseq([v1,v2,]);\n");
+        let rest = clip_to_super_collider(&Clip::Empty("gap".into(),
+            Duration::new(3, 4)));
+        assert_eq!(rest, "
+gap = Pbind(
+    \\dur, Pseq([0.75]),
+    \\note, Rest(),
+);
+");
+        let file = clip_to_super_collider(&Clip::File("drums".into(),
+            "/samples/\"drums\".wav".into()));
+        assert!(file.contains("~drumsBuffer = Buffer.read(s, \
+            \"/samples/\\\"drums\\\".wav\");"));
+        assert!(file.contains("drums = Plazy({Pbind("));
     }
 
     #[test]
     fn arrangement_translation_tests() {
         let ii_chord = Chord::new(n!(D4), ChordType::Min7);
-        let ii_chord_clip = Clip::Instrument("v1".into(), "sine".into(), 
+        let ii_chord_clip = Clip::Instrument("v1".into(), sine(), 
             vec![Some(ii_chord.play()), Some(ii_chord.play())],
-            vec![1.0, 1.0]);
+            rhythm![W, W]);
         let V_chord = Chord::new(Note::new(Name::G, 3), ChordType::Dom7);
-        let V_chord_clip = Clip::Instrument("v2".into(), "sine".into(),
+        let V_chord_clip = Clip::Instrument("v2".into(), sine(),
             vec![Some(V_chord.play()), Some(V_chord.play())],
-            vec![1.0, 1.0]);
+            rhythm![W, W]);
         let t = Track::new("t1".into(), vec!["v1".into(), "v2".into()]);
-        let arr: Arrangement = Arrangement::new(vec![t],
-            vec![ii_chord_clip, V_chord_clip]);
+        let gap = Clip::Empty("gap".into(), Duration::new(1, 2));
+        let t2 = Track::new("t2".into(), vec!["gap".into(), "v2".into()]);
+        let arr: Arrangement = Arrangement::new(vec![t, t2],
+            vec![ii_chord_clip, V_chord_clip, gap]);
         //println!("Arrangement output: {}", arrangement_to_super_collider(&arr));
-        
+        let output = arrangement_to_super_collider(&arr);
+        // each track plays its clips one after another, and the tracks play
+        // together: t1 lasts four whole notes, t2 two and a half
+        assert!(output.contains("t1 = Pseq([v1,v2,]);"));
+        assert!(output.contains("t2 = Pseq([gap,v2,]);"));
+        assert!(output.contains("\\dur, Pseq([1, 1]),"));
+        assert!(output.contains("\\dur, Pseq([0.5]),"));
+        assert!(output.trim_end().ends_with("Ppar([t1,t2,]).play;\n)"));
+        assert!(!output.contains(".do("));
+        // a whole note lasts two seconds at 120 quarter notes per minute
+        assert!(output.contains("TempoClock.default.tempo = 0.5;"));
+    }
+
+    #[test]
+    fn lilypond_tests() {
+        assert_eq!(note_to_lilypond(&n!(Fs4)), "fis'");
+        assert_eq!(note_to_lilypond(&n!(Bb2)), "bes,");
+        assert_eq!(note_to_lilypond(&n!(C3)), "c");
+        assert_eq!(duration_to_lilypond(Duration::new(3, 8)),
+            Ok(vec!["4.".to_string()]));
+        assert_eq!(duration_to_lilypond(Duration::new(7, 16)),
+            Ok(vec!["4..".to_string()]));
+        assert_eq!(duration_to_lilypond(Duration::new(5, 16)),
+            Ok(vec!["4".to_string(), "16".to_string()]));
+        // durations shorter than a 128th cannot be written
+        assert!(duration_to_lilypond(Duration::new(1, 256)).is_err());
+        let melody = instr_clip!(melody, sine,
+            play!(n!(Fs4), (), chord!(D4, Maj7), n!(A4)),
+            rhythm![Q., E, H., H]);
+        let arr = {
+            let mut arr = Arrangement::new(
+                vec![track!(lead, melody, gap), track!(bass, low)],
+                vec![melody, Clip::Empty("gap".into(), Duration::new(1, 4)),
+                    instr_clip!(low, sine, play!(n!(D2)), rhythm![W + W])]);
+            arr.set_key(Scale::new(n!(D4), ScaleType::Major));
+            arr.set_time_signature(3, 4);
+            arr
+        };
+        let expected =
+"\\version \"2.18.2\"
+
+\\score {
+  <<
+    \\new Staff \\with { instrumentName = \"lead\" } {
+      \\clef treble
+      \\key d \\major
+      \\time 3/4
+      fis'4. r8 <d' fis' a' cis''>4~ | <d' fis' a' cis''>2 a'4~ | a'4 r4
+    }
+    \\new Staff \\with { instrumentName = \"bass\" } {
+      \\clef bass
+      \\key d \\major
+      \\time 3/4
+      d,2.~ | d,2.~ | d,2
+    }
+  >>
+  \\layout { }
+}
+";
+        assert_eq!(arrangement_to_lilypond(&arr), Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn musicxml_tests() {
+        let melody = instr_clip!(melody, saw,
+            play!(n!(Fs4), (), chord!(D4, Maj7), n!(A4)),
+            rhythm![Q., E, H., H]);
+        let low = instr_clip!(low, sine, play!(n!(D2)), rhythm![W + W]);
+        let gap = Clip::Empty("gap".into(), Duration::new(1, 4));
+        let mut arr = Arrangement::new(
+            vec![track!(lead, melody, gap, melody), track!(bass, low)],
+            vec![melody.clone(), gap.clone(), low.clone()]);
+        arr.set_key(Scale::new(n!(D4), ScaleType::Major));
+        arr.set_time_signature(3, 4);
+        let document = arrangement_to_musicxml(&arr).unwrap();
+        assert!(document.contains("<key><fifths>2</fifths>"));
+        assert!(document.contains("<clef><sign>F</sign><line>4</line>"));
+        assert!(document.contains("<harmony><root><root-step>D</root-step>\
+            </root><kind>major-seventh</kind></harmony>"));
+        assert!(document.contains("<instrument-name>saw</instrument-name>"));
+        let imported = musicxml_to_arrangement(&document).unwrap();
+        assert_eq!(imported.get_tracks_ref(), arr.get_tracks_ref());
+        assert_eq!(imported.get_clip("melody"), Some(&melody));
+        assert_eq!(imported.get_clip("gap"), Some(&gap));
+        assert_eq!(imported.get_clip("low"), Some(&low));
+        assert_eq!(imported.get_key(), arr.get_key());
+        assert_eq!(imported.get_time_signature(), (3, 4));
+        assert!(musicxml_to_arrangement("<score-partwise>").is_err());
+    }
+
+    #[test]
+    fn abc_tests() {
+        let e_minor = Scale::new(n!(E4), ScaleType::Minor);
+        assert_eq!(e_minor.key_signature(), Some(1));
+        assert_eq!(Scale::from_key_signature(-1, ScaleType::Minor),
+            Some(Scale::new(n!(D4), ScaleType::Minor)));
+        let tunes = abc_to_arrangements("X:1
+T:Little Tune
+M:2/4
+L:1/8
+K:G
+|: G>A Bc | d2 [1 z2 :|[2 ^c2 |]
+
+X:2
+T:Second
+M:3/4
+K:Ador
+\"^intro\"[CEG]2 F2- F/2 (3e'de =f|
+", &sine()).unwrap();
+        assert_eq!(tunes.len(), 2);
+        assert_eq!(tunes[0].get_tracks_ref(), &vec![Track::new("1".into(),
+            vec!["little_tune".into()])]);
+        assert_eq!(tunes[0].get_clip("little_tune"), Some(&instr_clip!(
+            little_tune, sine, play!(n!(G4), n!(A4), n!(B4), n!(C5), n!(D5),
+            (), n!(G4), n!(A4), n!(B4), n!(C5), n!(D5), n!(Cs5)),
+            rhythm![E., S, E, E, Q, Q, E., S, E, E, Q, Q])));
+        assert_eq!(tunes[1].get_key(), Some(Scale::new(n!(A4),
+            ScaleType::Dorian)));
+        assert_eq!(tunes[1].get_clip("intro"), Some(&instr_clip!(intro, sine,
+            play!(vec![n!(C4), n!(E4), n!(G4)], n!(Fs4), n!(E6), n!(D5),
+            n!(E5), n!(F5)),
+            rhythm![Q, Q + S, 3(E, E, E), E])));
+        let melody = instr_clip!(melody, sine, play!(n!(B4), n!(Ds5), n!(D5),
+            (), chord!(E4, Min7)), rhythm![Q, E, E, Q, H.]);
+        let gap = Clip::Empty("gap".into(), Duration::new(1, 4));
+        let low = instr_clip!(low, sine, play!(n!(E2)), rhythm![W + W + W]);
+        let mut arr = Arrangement::new(
+            vec![track!(lead, melody, gap, melody), track!(bass, low)],
+            vec![melody.clone(), gap.clone(), low.clone()]);
+        arr.set_key(e_minor);
+        arr.set_time_signature(3, 4);
+        let abc = arrangement_to_abc(&arr, "Round Trip").unwrap();
+        assert!(abc.contains("K:Em\nV:lead\n\"^melody\"B2 ^d =d z2 |"));
+        assert!(abc.contains("V:bass clef=bass\n\"^low\"E,,6- |"));
+        let imported = abc_to_arrangement(&abc, &sine()).unwrap();
+        assert_eq!(imported.get_tracks_ref(), arr.get_tracks_ref());
+        assert_eq!(imported.get_clip("melody"), Some(&melody));
+        assert_eq!(imported.get_clip("gap"), Some(&gap));
+        assert_eq!(imported.get_clip("low"), Some(&low));
+        assert_eq!(imported.get_key(), Some(e_minor));
+        assert_eq!(imported.get_time_signature(), (3, 4));
+        assert!(abc_to_arrangement("K:C\nA B [CE", &sine()).is_err());
+        // malformed tunes give errors rather than panicking
+        for music in ["(99999999999CDE".to_string(), "[99999999999 C".into(),
+            "C99999999999".into(), format!("C{}", "/".repeat(40)),
+            format!("C{}", "'".repeat(200))].iter() {
+            assert!(abc_to_arrangement(&format!("K:C\n{}", music), &sine())
+                .is_err(), "{}", music);
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn project_tests() {
+        use project::*;
+        let melody = instr_clip!(melody, saw,
+            play!(n!(Fs4), (), chord!(D4, Maj7)),
+            rhythm![Q., E, 3(H)]);
+        let groove = drum_clip!(groove, 8, Duration::new(1, 8),
+            Kick => "X..o x..?");
+        let clips = vec![melody, Clip::Empty("gap".into(), Duration::new(1, 4)),
+            Clip::File("drums".into(), "/tmp/drums.wav".into()), groove];
+        let mut arr = Arrangement::new(vec![track!(lead, melody, gap),
+            track!(beat, drums, groove)], clips.clone());
+        arr.set_key(Scale::new(n!(Bb3), ScaleType::Dorian));
+        arr.set_time_signature(6, 8);
+        arr.set_concert_pitch(432.0);
+        arr.set_effects(EffectTarget::Track("lead".into()), vec![
+            Effect::Filter(FilterType::HighPass, 200.0, 1.0),
+            Effect::Chorus(0.5, 0.005, 0.5)]);
+        arr.set_transforms("melody", vec![Transform::Swing(60.0,
+            Duration::new(1, 8)), Transform::Humanize(0.01, 8, 1)]);
+        let scl = "! pentatonic.scl\nSlendro\n 5\n 240.0\n 480.0\n 720.0\n \
+            960.0\n 2/1\n";
+        let kbm = "! white keys only\n12\n0\n127\n60\n69\n440.0\n5\n\
+            0\nx\n1\nx\n2\n3\nx\n4\nx\nx\nx\nx\n";
+        let toml = arrangement_to_toml(&arr).unwrap();
+        assert!(toml.contains("notes = [[\"F#4\"], \"rest\", [\"D4\", \"F#4\", \
+            \"A4\", \"C#5\"]]"));
+        assert!(toml.contains("durations = [\"3/8\", \"1/8\", \"1/3\"]"));
+        for tuning in [Tuning::just(Name::D),
+            Tuning::from_scala(scl, Some(kbm)).unwrap()] {
+            arr.set_tuning(tuning);
+            let json = arrangement_to_json(&arr).unwrap();
+            let toml = arrangement_to_toml(&arr).unwrap();
+            for loaded in [arrangement_from_json(&json).unwrap(),
+                arrangement_from_toml(&toml).unwrap()] {
+                assert_eq!(loaded.get_tracks_ref(), arr.get_tracks_ref());
+                assert_eq!(loaded.get_clips_ref(), &clips);
+                assert_eq!(loaded.get_tuning(), arr.get_tuning());
+                assert_eq!(loaded.get_concert_pitch(), 432.0);
+                assert_eq!(loaded.get_key(), arr.get_key());
+                assert_eq!(loaded.get_time_signature(), (6, 8));
+                assert_eq!(loaded.get_effect_chains(),
+                    arr.get_effect_chains());
+                assert_eq!(loaded.get_transforms("melody"),
+                    arr.get_transforms("melody"));
+            }
+        }
+        // fields that are left out take their defaults
+        let loaded = arrangement_from_toml("[[tracks]]\nname = \"lead\"\n\
+            clip_names = [\"gap\"]\n[[clips]]\nname = \"gap\"\n\
+            type = \"empty\"\nduration = 0.5\n").unwrap();
+        assert_eq!(loaded.get_time_signature(), (4, 4));
+        assert_eq!(loaded.get_clip("gap"),
+            Some(&Clip::Empty("gap".into(), Duration::new(1, 2))));
+        assert!(arrangement_from_toml("[[clips]]\nname = \"a\"\n\
+            type = \"loop\"\n").is_err());
+    }
+
+    #[test]
+    fn score_tests() {
+        let arr = parse_score("// a short tune
+clip melody sine {
+    notes F#4 r [D4 F#4 A4] Dmaj7
+    durations q. e h+e 3/8
+}
+clip gap empty h
+clip drums file \"drums.wav\"
+track lead { melody gap melody }
+track beat { drums }
+arrangement {
+    key D major
+    time 3/4
+    pitch 432
+    tempo 96
+    tuning edo 19
+    tracks lead
+}
+").unwrap();
+        assert_eq!(arr.get_tracks_ref(), &vec![track!(lead, melody, gap,
+            melody)]);
+        assert_eq!(arr.get_clip("melody"), Some(&instr_clip!(melody, sine,
+            play!(n!(Fs4), (), vec![n!(D4), n!(Fs4), n!(A4)],
+            chord!(D4, Maj7)), rhythm![Q., E, H + E, Q.])));
+        assert_eq!(arr.get_clip("gap"), Some(&Clip::Empty("gap".into(),
+            Duration::new(1, 2))));
+        assert_eq!(arr.get_clip("drums"), Some(&Clip::File("drums".into(),
+            "drums.wav".into())));
+        assert_eq!(arr.get_key(), Some(Scale::new(n!(D4), ScaleType::Major)));
+        assert_eq!(arr.get_time_signature(), (3, 4));
+        assert_eq!(arr.get_concert_pitch(), 432.0);
+        assert_eq!(arr.get_tempo(), 96.0);
+        assert_eq!(arr.get_tuning(), &Tuning::Edo(19));
+        assert_eq!(parse_duration("q.."), Some(Duration::new(7, 16)));
+        assert_eq!(parse_chord_symbol("G3dom7"), Some(chord!(G3, Dom7)));
+        assert_eq!(parse_chord_symbol("C7"), None);
+        let error = |score: &str| parse_score(score).err().unwrap()
+            .to_string();
+        assert_eq!(error("clip a sine {\n  notes C4 H4\n}"),
+            "line 2, column 12: Expected a note, chord or rest, found \"H4\"");
+        assert_eq!(error("clip a sine { notes C4 durations q q }"),
+            "line 1, column 8: Clip \"a\" has 1 notes but 2 durations");
+        assert_eq!(error("track t { a }"),
+            "line 1, column 11: Unknown clip \"a\"");
+        assert_eq!(error("clip a sine {\n  notes C4"),
+            "line 2, column 11: Expected \"}\", found the end of the score");
+        // names become SuperCollider variables
+        for name in ["Lead", "2nd", "my-clip", "a.b"] {
+            assert!(error(&format!("clip {} empty h", name))
+                .starts_with(&format!("line 1, column 6: Invalid name \"{}\"",
+                name)));
+        }
+        assert!(error("clip a empty h\ntrack Lead { a }")
+            .starts_with("line 2, column 7: Invalid name \"Lead\""));
+        assert_eq!(error("clip a empty h\nclip a empty q"),
+            "line 2, column 6: Clip \"a\" is already defined");
+        assert_eq!(error("clip a empty h\ntrack a { a }"),
+            "line 2, column 7: \"a\" is already the name of a clip");
+        assert_eq!(error("track t { a }\nclip t empty h"),
+            "line 2, column 6: \"t\" is already the name of a track");
+        assert!(parse_score("clip a_2 empty h\ntrack t1 { a_2 }").is_ok());
+        assert_eq!(error("arrangement { time 4/3 }"),
+            "line 1, column 20: Invalid time signature \"4/3\"");
+    }
+
+    #[test]
+    fn backend_tests() {
+        let mut arr = Arrangement::new(vec![track!(lead, melody, gap, melody),
+            track!(bass, low)], vec![instr_clip!(melody, sine,
+            play!(vec![n!(C4), n!(E4)], ()), rhythm![Q, Q]),
+            Clip::Empty("gap".into(), Duration::new(1, 2)),
+            instr_clip!(low, sine, play!(n!(C2)), rhythm![W])]);
+        assert_eq!(arr.validate(), Ok(()));
+        assert_eq!(arr.get_length(), Duration::new(3, 2));
+        assert_eq!(arr.get_timed_notes()[2], TimedNote {track: 0,
+            clip: "melody".into(), instrument: "sine".into(), pitch: n!(C4),
+            start: Duration::new(1, 1), duration: Duration::new(1, 4),
+            velocity: 100});
+        // tempos and concert pitches must be positive numbers
+        arr.set_tempo(0.0);
+        assert!(arr.validate().is_err());
+        arr.set_tempo(90.0);
+        arr.set_concert_pitch(f64::NAN);
+        assert!(arr.validate().is_err());
+        arr.set_concert_pitch(CONCERT_A);
+        // MIDI files can only hold beat values that are powers of two
+        arr.set_time_signature(5, 6);
+        assert_eq!(arr.validate(), Err("The time signature has a beat value \
+            of 6, which is not a power of two".into()));
+        arr.set_time_signature(4, 4);
+        assert!(arrangement_to_super_collider(&arr)
+            .contains("TempoClock.default.tempo = 0.375;"));
+        let midi = arrangement_to_midi(&arr);
+        assert_eq!(&midi[..14], &[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1,
+            0, 3, 1, 0xe0]);
+        // 90 quarter notes per minute is 666667 microseconds per quarter note
+        assert_eq!(&midi[22..29], &[0, 0xff, 0x51, 3, 0x0a, 0x2c, 0x2b]);
+        let mut length = Vec::new();
+        write_variable_length(&mut length, 1920);
+        assert_eq!(length, vec![0x8f, 0x00]);
+        // a whole note at 90 quarter notes per minute lasts 8/3 seconds,
+        // and the low note has faded out before the end of the arrangement
+        assert_eq!(render_arrangement(&arr, 1000).len(), 4000);
+        let wav = arrangement_to_wav(&arr, 1000);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav.len(), 44 + 8000);
+        // gated instruments hold their notes for as long as they last and
+        // then release, following their envelopes
+        let saw_envelope = Envelope::of(::instruments::saw().get_instrument());
+        assert!((saw_envelope.length(2.0) - 2.2).abs() < 1e-9);
+        assert!((Envelope::default().length(2.0) - 0.305).abs() < 1e-9);
+        let held = Arrangement::new(vec![track!(lead, long)],
+            vec![instr_clip!(long, saw, play!(n!(A4)), rhythm![W])]);
+        let samples = render_arrangement(&held, 1000);
+        assert_eq!(samples.len(), 2200);
+        assert!(samples[1900..2000].iter().any(|sample| sample.abs() > 0.01));
+        assert!(samples[2190..].iter().all(|sample| sample.abs() < 0.01));
+        let invalid = Arrangement::new(vec![track!(lead, missing)],
+            Vec::new());
+        assert_eq!(invalid.validate(), Err("Track \"lead\" uses an unknown \
+            clip \"missing\"".into()));
+    }
+
+    #[test]
+    fn session_tests() {
+        let mut session = Session::new();
+        assert!(!is_complete("clip melody sine {\n  notes C4 [E4"));
+        assert!(is_complete("clip melody sine {\n  notes C4 [E4 G4]\n}"));
+        assert_eq!(session.execute("clip melody sine {\n  notes C4 [E4 G4] \
+            r\n  durations q q h\n}"), Ok("Defined clip melody".into()));
+        assert_eq!(session.execute("track lead { melody gap }"),
+            Err("line 1, column 21: Unknown clip \"gap\"".into()));
+        session.execute("clip gap empty h").unwrap();
+        session.execute("track lead { melody gap melody }").unwrap();
+        assert_eq!(session.execute("listener"), Ok(SCLANG_LISTENER.into()));
+        assert!(!session.execute("help").unwrap().contains("osc::"));
+        assert_eq!(session.execute("tempo fast"),
+            Err("line 1, column 7: Invalid tempo \"fast\"".into()));
+        assert_eq!(session.execute("tempo inf"),
+            Err("line 1, column 7: Invalid tempo \"inf\"".into()));
+        session.execute("tempo 90").unwrap();
+        session.execute("clip gap empty w").unwrap();
+        let arr = session.get_arrangement();
+        assert_eq!(arr.get_tempo(), 90.0);
+        assert_eq!(arr.get_clip("gap"), Some(&Clip::Empty("gap".into(),
+            Duration::new(1, 1))));
+        assert_eq!(arr.get_length(), Duration::new(3, 1));
+        assert_eq!(session.execute("chord E4 G4 B4 D5"),
+            Ok("Emin7: E4 G4 B4 D5 (MIDI [64, 67, 71, 74])".into()));
+        assert_eq!(session.execute("note A4"),
+            Ok("A4: MIDI 69, 440.000 Hz".into()));
+        assert!(session.execute("sc melody").unwrap()
+            .contains("\\midinote, Pseq([[60],[64, 67],Rest(),])"));
+        assert_eq!(session.execute("midi melody").unwrap().lines().nth(2),
+            Some("1/4      1/4      [64, 67]"));
+        assert!(session.execute("remove melody").is_err());
+        session.execute("track lead { gap }").unwrap();
+        assert_eq!(session.execute("remove melody"),
+            Ok("Removed melody".into()));
+        assert_eq!(session.execute("list"),
+            Ok("clips: gap\ntracks: lead { gap }".into()));
+    }
+
+    #[test]
+    fn osc_tests() {
+        use std::net::UdpSocket;
+        use std::time::{Duration, SystemTime};
+        let message = OscMessage::new("/n_set", vec![OscArg::Int(1000),
+            OscArg::Str("freq".into()), OscArg::Float(440.0)]);
+        assert_eq!(message.to_bytes(), b"/n_set\0\0,isf\0\0\0\0\0\0\x03\xe8\
+            freq\0\0\0\0\x43\xdc\0\0".to_vec());
+        let bundle = OscPacket::Bundle(IMMEDIATELY, vec![
+            OscPacket::Message(message), OscPacket::Message(OscMessage::new(
+            "/blob", vec![OscArg::Blob(vec![1, 2, 3])]))]);
+        assert_eq!(OscPacket::parse(&bundle.to_bytes()), Ok(bundle));
+        assert!(OscPacket::parse(b"/s_new\0\0,i\0\0\0\0").is_err());
+        // a stand-in for scsynth and sclang
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let receive = || {
+            let mut buffer = [0u8; 65536];
+            let length = server.recv(&mut buffer).unwrap();
+            OscPacket::parse(&buffer[..length]).unwrap()
+        };
+        let address = server.local_addr().unwrap();
+        let mut scsynth = Scsynth::connect(address).unwrap();
+        let node = scsynth.new_synth("sine", &[("freq", 220.0)]).unwrap();
+        assert_eq!(receive(), OscPacket::Message(OscMessage::new("/s_new",
+            vec![OscArg::Str("sine".into()), OscArg::Int(node),
+            OscArg::Int(1), OscArg::Int(1), OscArg::Str("freq".into()),
+            OscArg::Float(220.0)])));
+        scsynth.free(node).unwrap();
+        assert_eq!(receive(), OscPacket::Message(OscMessage::new("/n_free",
+            vec![OscArg::Int(node)])));
+        scsynth.stop().unwrap();
+        assert_eq!(receive(), OscPacket::Message(OscMessage::new(
+            "/g_freeAll", vec![OscArg::Int(1)])));
+        let arr = Arrangement::new(vec![track!(lead, melody)],
+            vec![instr_clip!(melody, sine, play!(n!(A4), (), n!(A5)),
+            rhythm![Q, Q, H])]);
+        let before = time_tag(SystemTime::now());
+        assert_eq!(scsynth.play(&arr, Duration::from_millis(100)).unwrap(), 2);
+        let (first, second) = match (receive(), receive()) {
+            (OscPacket::Bundle(first, a4), OscPacket::Bundle(second, a5)) => {
+                assert_eq!(a4[0], OscPacket::Message(OscMessage::new("/s_new",
+                    vec![OscArg::Str("sine".into()), OscArg::Int(node + 1),
+                    OscArg::Int(1), OscArg::Int(1), OscArg::Str("freq".into()),
+                    OscArg::Float(440.0), OscArg::Str("amp".into()),
+                    OscArg::Float(0.1)])));
+                assert_eq!(a5.len(), 1);
+                (first, second)
+            },
+            packets => panic!("Expected two bundles, found {:?}", packets),
+        };
+        // the second note starts a half note (a second at 120 quarter notes
+        // per minute) after the first, which starts after the latency
+        assert!(first > before);
+        assert!(((second - first) as f64 / (1u64 << 32) as f64 - 1.0).abs()
+            < 1e-6);
+        let sclang = Sclang::connect(address).unwrap();
+        sclang.play(&arr).unwrap();
+        match receive() {
+            OscPacket::Message(ref message) => {
+                assert_eq!(message.address, "/dawpl/interpret");
+                assert_eq!(message.args, vec![OscArg::Str(
+                    arrangement_to_super_collider(&arr))]);
+            },
+            packet => panic!("Expected a message, found {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn sequencer_tests() {
+        use std::io;
+        use std::sync::{Arc, Mutex};
+        use std::time::{Duration, SystemTime};
+        struct Recorder(Arc<Mutex<Vec<(SequencerEvent, SystemTime)>>>);
+        impl Sink for Recorder {
+            fn send(&mut self, event: &SequencerEvent, due: SystemTime)
+                -> io::Result<()> {
+                self.0.lock().unwrap().push((event.clone(), due));
+                Ok(())
+            }
+            fn stop(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let arr = Arrangement::new(vec![track!(lead, melody)],
+            vec![instr_clip!(melody, sine, play!(n!(C4), (), n!(E4)),
+            rhythm![Q, Q, H])]);
+        let mut sequencer = Sequencer::new(&arr, ManualClock::new());
+        let positions: Vec<f64> = sequencer.get_events().iter()
+            .map(|event| event.position.to_f64()).collect();
+        assert_eq!(positions, vec![0.0, 0.25, 0.5, 1.0]);
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        sequencer.add_sink(Box::new(Recorder(recorded.clone())));
+        let synth = Arc::new(Mutex::new(Synth::new(1000)));
+        sequencer.add_sink(Box::new(synth.clone()));
+        // a whole note lasts two seconds at the default tempo, so only the
+        // first note starts within the lookahead
+        assert_eq!(sequencer.tick().unwrap(), 1);
+        let mut clock = ManualClock::new();
+        clock.sleep(Duration::from_millis(450));
+        let mut sequencer_later = Sequencer::new(&arr, clock);
+        assert_eq!(sequencer_later.get_position(), Time::new(9, 40));
+        assert_eq!(sequencer_later.tick().unwrap(), 2);
+        // halving the tempo from here on doubles the time to the next note
+        assert!(sequencer_later.set_tempo(0.0).is_err());
+        assert!(sequencer_later.set_tempo(f64::NAN).is_err());
+        sequencer_later.set_tempo(60.0).unwrap();
+        assert_eq!(sequencer_later.time_of(Time::new(1, 2)),
+            Duration::from_millis(1550));
+        assert_eq!(sequencer_later.time_of(Time::new(1, 1)),
+            Duration::from_millis(3550));
+        assert_eq!(sequencer_later.tick().unwrap(), 0);
+        sequencer.run().unwrap();
+        assert!(sequencer.is_finished());
+        assert!(sequencer.get_clock().elapsed() >= Duration::from_millis(1900));
+        let recorded = recorded.lock().unwrap();
+        assert_eq!(recorded.len(), 4);
+        assert_eq!(recorded[2].0, SequencerEvent::NoteOn {note: 1, track: 0,
+            channel: 0, instrument: "sine".into(), midi_value: 64,
+            frequency: Some(n!(E4).frequency(440.0)), velocity: 100});
+        assert_eq!(recorded[3].1.duration_since(recorded[0].1).unwrap(),
+            Duration::from_secs(2));
+        assert_eq!(synth.lock().unwrap().get_voice_count(), 2);
+        let mut samples = [0.0f32; 400];
+        synth.lock().unwrap().render(&mut samples);
+        assert!(samples.iter().any(|sample| *sample != 0.0));
+        assert_eq!(synth.lock().unwrap().get_voice_count(), 0);
+        let mut midi = MidiSink::new(Vec::new());
+        midi.send(&recorded[0].0, recorded[0].1).unwrap();
+        midi.stop().unwrap();
+        assert_eq!(midi.get_port(), &vec![0x90, 60, 100, 0x80, 60, 0]);
+        // drum tracks play on channel 10, as in MIDI files
+        let beat = drum_clip!(beat, 1, Beat::Q.get_duration(), Kick => "x");
+        let arr = Arrangement::new(vec![track!(lead, melody),
+            track!(drums, beat)], vec![instr_clip!(melody, sine,
+            play!(n!(C4)), rhythm![Q]), beat]);
+        let mut midi = MidiSink::new(Vec::new());
+        for event in arrangement_events(&arr).iter() {
+            midi.send(&event.event, SystemTime::now()).unwrap();
+        }
+        assert_eq!(midi.get_port(), &vec![0x90, 60, 100, 0x99, 36, 100,
+            0x80, 60, 0, 0x89, 36, 0]);
+    }
+
+    #[test]
+    fn synth_tests() {
+        let output = pan2(rlpf(::synth::saw(param("freq") * 1.01),
+            param("cutoff"), 0.5), param("pan"), param("amp")) *
+            perc(0.01, 0.5);
+        let lead = Instrument::new("lead", &[("cutoff", 2000.0)], output)
+            .handle();
+        assert_eq!(lead.get_instrument().get_parameters(), &vec![
+            ("cutoff".to_string(), 2000.0), ("freq".to_string(), 440.0),
+            ("pan".to_string(), 0.0), ("amp".to_string(), 0.1)]);
+        assert_eq!(lead.get_instrument().to_super_collider(),
+            "SynthDef.new(\\lead, {\n    \
+            arg out=0, cutoff=2000, freq=440, pan=0, amp=0.1;\n    \
+            Out.ar(out, (Pan2.ar(RLPF.ar(Saw.ar((freq * 1.01)), cutoff, 0.5), \
+            pan, amp) * EnvGen.kr(Env.new([0, 1, 0], [0.01, 0.5], [-4, -4], \
+            nil), 1, 1, 0, 1, 2)));\n}).add;");
+        assert_eq!((2.0 - sin_osc(3.0).kr()).to_super_collider(),
+            "(2 - SinOsc.kr(3))");
+        // only the instruments that are used are defined
+        let riff = instr_clip!(riff, lead.clone(), play!(n!(E2)),
+            rhythm![H]);
+        let arr = Arrangement::new(vec![track!(bass, riff)], vec![riff]);
+        let sc = arrangement_to_super_collider(&arr);
+        assert!(sc.contains("SynthDef.new(\\lead"));
+        assert!(sc.contains("\\instrument, \\lead"));
+        assert!(!sc.contains("SynthDef.new(\\sine"));
+        assert!(!sc.contains("dawplPlayBuf"));
+        let drums = Clip::File("drums".into(), "/tmp/drums.wav".into());
+        let arr = Arrangement::new(vec![track!(beat, drums)], vec![drums]);
+        assert!(arrangement_to_super_collider(&arr).contains("dawplPlayBuf"));
+        // scores name instruments of the library
+        let arr = parse_score("clip a saw { notes C4 durations q }").unwrap();
+        assert_eq!(arr.get_clip("a"), Some(&instr_clip!(a, saw,
+            play!(n!(C4)), rhythm![Q])));
+        let error = parse_score("clip a organ { notes C4 durations q }")
+            .err().unwrap().to_string();
+        assert_eq!(error, "line 1, column 8: Unknown instrument \"organ\"");
+    }
+
+    #[test]
+    fn instrument_library_tests() {
+        for name in INSTRUMENTS.iter() {
+            let instrument = builtin_instrument(name).unwrap();
+            assert_eq!(instrument.get_name(), name);
+            let parameters = instrument.get_instrument().get_parameters();
+            assert!(parameters.iter().any(|parameter| parameter.0 == "amp"));
+        }
+        assert!(builtin_instrument("organ").is_none());
+        assert!(::instruments::saw().get_instrument().is_gated());
+        assert!(!kick().get_instrument().is_gated());
+        assert_eq!(kick().get_instrument().to_super_collider(),
+            "SynthDef.new(\\kick, {\n    \
+            arg out=0, tone=50, decay=0.8, amp=0.5, pan=0;\n    \
+            Out.ar(out, (Pan2.ar(SinOsc.ar(XLine.kr((tone * 4), tone, 0.05)), \
+            pan, amp) * EnvGen.kr(Env.new([0, 1, 0], [0.002, decay], \
+            [-4, -4], nil), 1, 1, 0, 1, 2)));\n}).add;");
+        let string = ::instruments::pluck().get_instrument()
+            .to_super_collider();
+        assert!(string.contains("Pluck.ar(WhiteNoise.ar(), Impulse.kr(0), \
+            0.05, (1 / freq), decay, coef)"));
+        // each instrument is defined once, and only if it is used
+        let arr = parse_score("clip beat kick { notes C2 C2 durations q q }
+            clip hat hihat { notes F#2 durations h }
+            clip keys epiano { notes Cmaj7 durations h }
+            track drums { beat hat }
+            track chords { keys }").unwrap();
+        let sc = arrangement_to_super_collider(&arr);
+        for name in ["kick", "hihat", "epiano"] {
+            assert_eq!(sc.matches(&format!("SynthDef.new(\\{},", name)[..])
+                .count(), 1);
+        }
+        assert_eq!(sc.matches("SynthDef.new").count(), 3);
+        let mut session = Session::new();
+        assert_eq!(session.execute("instruments").unwrap(),
+            "sine saw square pad epiano pluck kick snare hihat bass");
+    }
+
+    #[test]
+    fn effects_tests() {
+        let riff = instr_clip!(riff, saw, play!(n!(E3)), rhythm![H]);
+        let hit = instr_clip!(hit, kick, play!(n!(C2)), rhythm![Q]);
+        let mut arr = Arrangement::new(vec![track!(lead, riff, riff),
+            track!(drums, hit), track!(bass, riff)], vec![riff, hit]);
+        assert_eq!(Effect::Distortion(4.0, 0.5).apply(param("sig"))
+            .to_super_collider(), "((sig * 0.5) + ((sig * 4).tanh * 0.5))");
+        arr.set_effects(EffectTarget::Clip("riff".into()),
+            vec![Effect::Distortion(4.0, 0.5),
+            Effect::Filter(FilterType::LowPass, 1200.0, 0.5)]);
+        arr.set_effects(EffectTarget::Track("lead".into()),
+            vec![Effect::Delay(0.25, 2.0, 0.4)]);
+        arr.set_effects(EffectTarget::Master, vec![Effect::Reverb(0.3, 0.8,
+            0.5), Effect::Compressor(0.5, 4.0, 0.01, 0.1)]);
+        assert_eq!(arr.get_effects(&EffectTarget::Track("drums".into())),
+            &[]);
+        assert!(arr.validate().is_ok());
+        let sc = arrangement_to_super_collider(&arr);
+        assert!(sc.contains("SynthDef.new(\\dawplEffects_lead_riff, {\n    \
+            arg in=0, out=0;\n    var sig = In.ar(in, 2);\n    \
+            sig = ((sig * 0.5) + ((sig * 4).tanh * 0.5));\n    \
+            sig = RLPF.ar(sig, 1200, 0.5);\n    Out.ar(out, sig);\n}).add;"));
+        assert!(sc.contains("sig = (sig + (CombL.ar(sig, 0.25, 0.25, 2) * \
+            0.4));"));
+        assert!(sc.contains("sig = FreeVerb.ar(sig, 0.3, 0.8, 0.5);\n    \
+            sig = Compander.ar(sig, sig, 0.5, 1, 0.25, 0.01, 0.1);"));
+        // clips go through their own effects, then their track's, then the
+        // master's
+        assert!(sc.contains("~dawplEffects = Group.after(s.defaultGroup);\n\
+            ~lead_riffBus = Bus.audio(s, 2);\n\
+            ~bass_riffBus = Bus.audio(s, 2);\n\
+            ~leadBus = Bus.audio(s, 2);\n\
+            ~dawplMasterBus = Bus.audio(s, 2);\n\
+            Synth.tail(~dawplEffects, \\dawplEffects_lead_riff, \
+            [\\in, ~lead_riffBus, \\out, ~leadBus]);\n\
+            Synth.tail(~dawplEffects, \\dawplEffects_bass_riff, \
+            [\\in, ~bass_riffBus, \\out, ~dawplMasterBus]);\n\
+            Synth.tail(~dawplEffects, \\dawplEffects_lead, \
+            [\\in, ~leadBus, \\out, ~dawplMasterBus]);\n\
+            Synth.tail(~dawplEffects, \\dawplEffectsMaster, \
+            [\\in, ~dawplMasterBus, \\out, 0]);"));
+        assert!(sc.contains("lead = Pseq([Pset(\\out, ~lead_riffBus, riff),\
+            Pset(\\out, ~lead_riffBus, riff),]);"));
+        assert!(sc.contains("drums = Pseq([Pset(\\out, ~dawplMasterBus, \
+            hit),]);"));
+        // without effects nothing is routed
+        arr.set_effects(EffectTarget::Master, Vec::new());
+        arr.remove("lead").unwrap();
+        arr.set_effects(EffectTarget::Clip("riff".into()), Vec::new());
+        let sc = arrangement_to_super_collider(&arr);
+        assert!(!sc.contains("Bus") && !sc.contains("Pset"));
+        arr.set_effects(EffectTarget::Track("lead".into()),
+            vec![Effect::Eq(100.0, -6.0, 1.0)]);
+        assert!(arr.validate().is_err());
+    }
+
+    #[test]
+    fn mixing_tests() {
+        let riff = instr_clip!(riff, saw, play!(n!(E3)), rhythm![Q]);
+        let hit = instr_clip!(hit, kick, play!(n!(C2)), rhythm![Q]);
+        let mut arr = Arrangement::new(vec![track!(lead, riff),
+            track!(keys, riff), track!(drums, hit)], vec![riff, hit]);
+        arr.set_group("band", vec!["lead".into(), "keys".into()]);
+        arr.set_effects(EffectTarget::Group("band".into()),
+            vec![Effect::Compressor(0.5, 4.0, 0.01, 0.1)]);
+        arr.set_return("verb", vec![Effect::Reverb(1.0, 0.9, 0.5)]);
+        arr.set_send("lead", "verb", 0.5);
+        arr.set_send("drums", "verb", 0.2);
+        assert_eq!(arr.get_group_of("keys").unwrap().name, "band");
+        assert_eq!(arr.get_sends("lead")[0].level, 0.5);
+        assert!(arr.validate().is_ok());
+        let sc = arrangement_to_super_collider(&arr);
+        assert!(sc.contains("SynthDef.new(\\dawplEffects_lead, {\n    \
+            arg in=0, out=0, send0=0;\n    var sig = In.ar(in, 2);\n    \
+            Out.ar(send0, sig * 0.5);\n    Out.ar(out, sig);\n}).add;"));
+        assert!(sc.contains("SynthDef.new(\\dawplGroup_band"));
+        assert!(sc.contains("SynthDef.new(\\dawplReturn_verb"));
+        // tracks run before groups, which run before returns
+        assert!(sc.contains("\
+            Synth.tail(~dawplEffects, \\dawplEffects_lead, [\\in, \
+            ~leadBus, \\out, ~bandGroupBus, \\send0, ~verbReturnBus]);\n\
+            Synth.tail(~dawplEffects, \\dawplEffects_drums, [\\in, \
+            ~drumsBus, \\out, 0, \\send0, ~verbReturnBus]);\n\
+            Synth.tail(~dawplEffects, \\dawplGroup_band, [\\in, \
+            ~bandGroupBus, \\out, 0]);\n\
+            Synth.tail(~dawplEffects, \\dawplReturn_verb, [\\in, \
+            ~verbReturnBus, \\out, 0]);"));
+        assert!(sc.contains("keys = Pseq([Pset(\\out, ~bandGroupBus, \
+            riff),]);"));
+        arr.set_send("drums", "room", 0.2);
+        assert!(arr.validate().is_err());
+        arr.set_send("drums", "room", 0.0);
+        arr.remove("drums").unwrap();
+        assert!(arr.get_sends("drums").is_empty());
+        // the renderer mixes the same way: a group without effects changes
+        // nothing, and a send is heard through its return
+        let low = instr_clip!(low, sine, play!(n!(A3)), rhythm![Q]);
+        let mut arr = Arrangement::new(vec![track!(bass, low)], vec![low]);
+        let dry = render_arrangement(&arr, 8000);
+        arr.set_group("rhythm", vec!["bass".into()]);
+        assert_eq!(render_arrangement(&arr, 8000), dry);
+        arr.set_return("echo", vec![Effect::Delay(1.0, 1.0, 1.0)]);
+        arr.set_send("bass", "echo", 1.0);
+        let wet = render_arrangement(&arr, 8000);
+        // the return plays the note along with the track, then its echo a
+        // second later
+        assert!(wet.len() > dry.len());
+        assert!(dry.iter().zip(wet.iter()).all(|(dry, wet)|
+            (wet - 2.0 * dry).abs() < 1e-6));
+        arr.set_effects(EffectTarget::Master, vec![Effect::Reverb(0.5, 0.8,
+            0.5), Effect::Chorus(0.5, 0.005, 0.5), Effect::Distortion(4.0, 0.5),
+            Effect::Eq(200.0, 6.0, 1.0), Effect::Compressor(0.1, 4.0, 0.01,
+            0.1), Effect::Filter(FilterType::BandPass, 440.0, 0.5)]);
+        let mastered = render_arrangement(&arr, 8000);
+        assert!(mastered.iter().all(|sample| sample.is_finite()));
+        assert!(mastered != wet);
+        assert!(wet[8000..10400].iter().any(|sample| sample.abs() > 0.01));
+    }
+
+    #[test]
+    fn automation_tests() {
+        let riff = instr_clip!(riff, saw, play!(n!(E3), n!(G3)),
+            rhythm![H, H]);
+        let mut arr = Arrangement::new(vec![track!(lead, riff)], vec![riff]);
+        let cutoff = AutomationTarget::EffectParameter(
+            EffectTarget::Track("lead".into()), 0, 0);
+        arr.set_effects(EffectTarget::Track("lead".into()),
+            vec![Effect::Filter(FilterType::LowPass, 200.0, 0.5)]);
+        let (half, whole) = (Duration::new(1, 2), Duration::new(1, 1));
+        arr.set_automation(cutoff.clone(), vec![
+            Breakpoint::new(whole, 4000.0, Curve::Linear),
+            Breakpoint::new(half, 200.0, Curve::Exponential)]);
+        arr.set_automation(AutomationTarget::Volume("lead".into()),
+            vec![Breakpoint::new(Time::zero(), 1.0, Curve::Step),
+            Breakpoint::new(half, 0.5, Curve::Linear),
+            Breakpoint::new(whole, 0.0, Curve::Linear)]);
+        arr.set_automation(AutomationTarget::SynthParameter("lead".into(),
+            "rq".into()), vec![Breakpoint::new(Time::zero(), 0.3,
+            Curve::Linear)]);
+        assert!(arr.validate().is_ok());
+        // breakpoints are put in order and values are held at either end
+        let lane = arr.get_automation(&cutoff).unwrap();
+        assert_eq!(lane.value_at(Time::zero()), 200.0);
+        assert!((lane.value_at(Duration::new(3, 4)) - 894.427).abs() < 1e-3);
+        assert_eq!(lane.value_at(Duration::new(2, 1)), 4000.0);
+        let volume = arr.get_automation(&AutomationTarget::Volume(
+            "lead".into())).unwrap();
+        assert_eq!(volume.value_at(Duration::new(1, 4)), 1.0);
+        assert_eq!(volume.value_at(Duration::new(3, 4)), 0.25);
+        let sc = arrangement_to_super_collider(&arr);
+        // a whole note lasts 2 seconds at 120 quarter notes per minute
+        assert!(sc.contains("SynthDef.new(\\dawplAutomation_0, {\n    \
+            arg out=0;\n    Out.kr(out, EnvGen.kr(Env.new([200, 200, 4000], \
+            [1, 1], [\\lin, \\exp])));\n}).add;"));
+        assert!(sc.contains("Env.new([1, 1, 0.5, 0], [0, 1, 1], \
+            [\\lin, \\hold, \\lin])"));
+        assert!(sc.contains("SynthDef.new(\\dawplEffects_lead, {\n    \
+            arg in=0, out=0, fx0_0=200, volume=1, pan=0;\n    \
+            var sig = In.ar(in, 2);\n    sig = RLPF.ar(sig, fx0_0, 0.5);\n    \
+            sig = Balance2.ar(sig[0], sig[1], pan, volume);\n"));
+        assert!(sc.contains("~dawplAutomation = Group.before(s.defaultGroup);\n\
+            ~dawplAutomation0Bus = Bus.control(s, 1);"));
+        assert!(sc.contains("Synth.tail(~dawplAutomation, \\dawplAutomation_2, \
+            [\\out, ~dawplAutomation2Bus]);"));
+        assert!(sc.contains("Synth.tail(~dawplEffects, \\dawplEffects_lead, \
+            [\\in, ~leadBus, \\out, 0, \\fx0_0, ~dawplAutomation0Bus.asMap, \
+            \\volume, ~dawplAutomation1Bus.asMap]);"));
+        assert!(sc.contains("lead = Pseq([Pset(\\rq, \
+            ~dawplAutomation2Bus.asMap, Pset(\\out, ~leadBus, riff)),]);"));
+        // volume is written as controller 7, every 64th note while it fades
+        let midi = arrangement_to_midi(&arr);
+        let changes: Vec<u8> = midi.windows(3).filter(|event|
+            event[0] == 0xb0 && event[1] == 7).map(|event| event[2])
+            .collect();
+        assert_eq!(changes.len(), 34);
+        assert_eq!((changes[0], changes[1], changes[2]), (127, 64, 62));
+        // the renderer fades the track out by the end of the second note
+        let samples = render_arrangement(&arr, 8000);
+        let peak = |range: ::std::ops::Range<usize>| samples[range].iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak(15200..16000) < peak(0..800) * 0.2);
+        arr.set_automation(AutomationTarget::Pan("keys".into()),
+            vec![Breakpoint::new(Time::zero(), -1.0, Curve::Linear)]);
+        assert!(arr.validate().is_err());
+        arr.set_automation(AutomationTarget::Pan("keys".into()), Vec::new());
+        arr.set_automation(AutomationTarget::EffectParameter(
+            EffectTarget::Track("lead".into()), 0, 2),
+            vec![Breakpoint::new(Time::zero(), 1.0, Curve::Linear)]);
+        assert!(arr.validate().is_err());
+        arr.remove("lead").unwrap();
+        assert!(arr.get_automation_lanes().is_empty());
+    }
+
+    #[test]
+    fn drum_tests() {
+        let beat = drum_clip!(beat, 8, Beat::E.get_duration(),
+            Kick => "X... x...",
+            Snare => "..o. x", HiHat => "x?x? x?x?");
+        let pattern = match beat {
+            Clip::Drums(_, ref pattern) => pattern.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(pattern.lanes[1].steps[2], DrumStep::new(50, 1.0));
+        assert_eq!(pattern.lanes[1].steps[7], DrumStep::rest());
+        assert_eq!(pattern.get_length(), Duration::new(1, 1));
+        assert_eq!(parse_steps("x.q").err().unwrap(),
+            "Unknown step 'q' in \"x.q\"");
+        assert!(DrumPattern::new(4, Duration::new(1, 4))
+            .lane(DrumVoice::Kick, "x.x.x").is_err());
+        // steps left to chance are chosen once, the same way every time
+        let hits = pattern.get_hits();
+        assert_eq!(hits, pattern.get_hits());
+        let hats = hits.iter().filter(|hit| hit.lane == 2).count();
+        assert!((4..8).contains(&hats));
+        assert_eq!(hits[0], DrumHit {lane: 0, start: Time::zero(),
+            velocity: 127});
+        let mut arr = Arrangement::new(vec![track!(drums, beat, beat),
+            track!(bass, riff)], vec![beat,
+            instr_clip!(riff, bass, play!(n!(E2)), rhythm![W])]);
+        assert!(arr.validate().is_ok());
+        assert_eq!(arr.get_length(), Duration::new(2, 1));
+        let notes = arr.get_timed_notes();
+        assert_eq!(notes.iter().filter(|note| note.pitch == n!(C2)).count(),
+            4);
+        assert_eq!(notes[2], TimedNote {track: 0, clip: "beat".into(),
+            instrument: "snare".into(), pitch: n!(D2),
+            start: Duration::new(1, 4), duration: Duration::new(1, 8),
+            velocity: 50});
+        let sc = arrangement_to_super_collider(&arr);
+        assert!(sc.contains("beat = Ppar([\nPbind(\n    \
+            \\instrument, \\kick,\n    \\dur, 0.125,\n    \\midinote, 36,\n    \
+            \\amp, Pseq([0.635, Rest(), Rest(), Rest(), 0.5, Rest(), Rest(), \
+            Rest()]),\n),"));
+        assert!(sc.contains("\\amp, Pseq([0.2, Pwrand([0.2, Rest()], \
+            [0.5, 0.5]), 0.2,"));
+        assert_eq!(sc.matches("SynthDef.new").count(), 4);
+        // drums are on channel 10, the bass on a channel of its own
+        let midi = arrangement_to_midi(&arr);
+        assert!(midi.windows(3).any(|event| event == [0x99, 36, 127]));
+        assert!(midi.windows(3).any(|event| event == [0x99, 38, 50]));
+        assert!(midi.windows(3).any(|event| event == [0x91, 40, 100]));
+        arr.set_clip(Clip::Drums("beat".into(), DrumPattern::new(4,
+            Duration::zero())));
+        assert!(arr.validate().is_err());
+    }
+
+    #[test]
+    fn rhythm_tests() {
+        let steps = |rhythm: &Rhythm| rhythm.onsets.iter()
+            .map(|onset| if *onset { 'x' } else { '.' }).collect::<String>();
+        let eighth = Beat::E.get_duration();
+        assert_eq!(steps(&Rhythm::euclidean(3, 8, 0, eighth)), "x..x..x.");
+        assert_eq!(Rhythm::euclidean(3, 8, 0, eighth),
+            Rhythm::tresillo(eighth));
+        assert_eq!(Rhythm::euclidean(5, 8, 0, eighth),
+            Rhythm::cinquillo(eighth));
+        assert_eq!(steps(&Rhythm::euclidean(4, 12, 0, eighth)),
+            "x..x..x..x..");
+        assert_eq!(steps(&Rhythm::euclidean(5, 16, 0, eighth)),
+            "x..x..x..x..x...");
+        assert_eq!(steps(&Rhythm::euclidean(3, 8, 1, eighth)), "..x..x.x");
+        assert_eq!(steps(&Rhythm::euclidean(0, 4, 0, eighth)), "....");
+        assert_eq!(steps(&Rhythm::euclidean(6, 4, 0, eighth)), "xxxx");
+        assert_eq!(steps(&Rhythm::son_clave(Beat::S.get_duration())),
+            "x..x..x...x.x...");
+        assert_eq!(rhythm!(E(3, 8), E), rhythm![Q., Q., Q]);
+        assert_eq!(rhythm!(E(3, 8, 1), E), rhythm![Q, Q., Q, E]);
+        // a rhythm that starts with a rest gets a rest first
+        let quarter = Beat::Q.get_duration();
+        let offbeat = Rhythm::from_binary("0110 | 0100", quarter).unwrap();
+        assert_eq!(offbeat.durations(), rhythm![Q, Q, H., H.]);
+        let melody = offbeat.melody(&play!(n!(C4), n!(E4)));
+        assert_eq!(melody, play!((), n!(C4), n!(E4), n!(C4)));
+        assert_eq!(offbeat.step_melody(&play!(n!(C4)))[..3],
+            play!((), n!(C4), n!(C4))[..]);
+        assert_eq!(offbeat.step_durations().len(), 8);
+        assert_eq!(offbeat.get_length(), Duration::new(2, 1));
+        let clip = Clip::Instrument("offbeat".into(), sine(), melody,
+            offbeat.durations());
+        let arr = Arrangement::new(vec![track!(lead, offbeat)], vec![clip]);
+        assert!(arr.validate().is_ok());
+        assert_eq!(arr.get_length(), Duration::new(2, 1));
+        assert!(Rhythm::from_binary("10x2", quarter).is_err());
+        // random rhythms follow their density and seed
+        let random = Rhythm::random(64, 0.25, 7, eighth);
+        assert_eq!(random, Rhythm::random(64, 0.25, 7, eighth));
+        assert!(random != Rhythm::random(64, 0.25, 8, eighth));
+        let count = random.onsets.iter().filter(|onset| **onset).count();
+        assert!((8..24).contains(&count));
+        assert!(Rhythm::random(16, 0.0, 1, quarter).onsets.iter()
+            .all(|onset| !onset));
+    }
+
+    #[test]
+    fn duration_tests() {
+        let quarter = Beat::Q.get_duration();
+        assert_eq!(quarter, Duration::new(2, 8));
+        assert_eq!(quarter.dotted(1), Duration::new(3, 8));
+        assert_eq!(Beat::H.get_duration().dotted(2), Duration::new(7, 8));
+        assert!(::std::panic::catch_unwind(|| quarter.dotted(64)).is_err());
+        assert_eq!(Beat::X.get_length(), 0.015625);
+        assert!("Z".parse::<Beat>().is_err());
+        // twelve triplet eighths fill a bar exactly
+        let triplet = Beat::E.get_duration().tuplet(3, 2);
+        assert_eq!(triplet, Duration::new(1, 12));
+        assert_eq!(vec![triplet; 12].into_iter().sum::<Duration>(),
+            Duration::new(1, 1));
+        assert_eq!(Duration::new(1, 1) - triplet * 3, Duration::new(3, 4));
+        assert!(triplet < Beat::E.get_duration());
+        assert_eq!(format!("{} {}", triplet, Duration::new(6, 3)), "1/12 2");
+        assert_eq!((tuplet_space(3), tuplet_space(5), tuplet_space(7)),
+            (2, 4, 4));
+        // notation of rhythm!
+        let d = Duration::new;
+        assert_eq!(rhythm![Q., E, H.., S...], vec![d(3, 8), d(1, 8),
+            d(7, 8), d(15, 128)]);
+        assert_eq!(rhythm![H + E, Q, T, X], vec![d(5, 8), d(1, 4),
+            d(1, 32), d(1, 64)]);
+        assert_eq!(rhythm![3(E, E, E), H], vec![triplet, triplet, triplet,
+            d(1, 2)]);
+        assert_eq!(rhythm![5:4(S, S, S, S, S)], vec![d(1, 20); 5]);
+        assert_eq!(rhythm![3(Q, Q, Q + E), E], vec![d(1, 6), d(1, 6),
+            d(1, 4), d(1, 8)]);
+        assert_eq!(rhythm![3(E, 3(S, S, S), E)], vec![triplet, d(1, 36),
+            d(1, 36), d(1, 36), triplet]);
+        assert_eq!(rhythm![Q, Q,], vec![quarter, quarter]);
+        assert_eq!(parse_duration("t."), Some(d(3, 64)));
+        assert_eq!(parse_duration("h..."), Some(d(15, 16)));
+        assert_eq!(parse_duration(&format!("q{}", ".".repeat(64))), None);
+        assert_eq!(parse_duration("x+1/12"), Some(d(1, 64) + triplet));
+    }
+
+    #[test]
+    fn time_tests() {
+        use notation::*;
+        let triplet = Beat::E.get_duration().tuplet(3, 2);
+        assert_eq!(Duration::from_f64(1.0 / 3.0), Some(Duration::new(1, 3)));
+        assert_eq!(Duration::from_f64(0.375), Some(Duration::new(3, 8)));
+        assert_eq!(Duration::from_f64(-0.5), None);
+        assert_eq!("1/12".parse::<Duration>(), Ok(triplet));
+        assert_eq!("0.25".parse::<Duration>(), Ok(Duration::new(1, 4)));
+        assert!("1/0".parse::<Duration>().is_err());
+        assert_eq!(Duration::new(1, 2) / triplet, Duration::new(6, 1));
+        // quintuplet sixteenths end on the beat, in ticks and in notation
+        let quintuplet = rhythm![5(S, S, S, S, S)];
+        assert_eq!(time_to_ticks(quintuplet.iter().cloned().sum()), 480);
+        assert_eq!(time_to_ticks(triplet), 160);
+        let arr = Arrangement::new(vec![track!(lead, fives, after)], vec![
+            instr_clip!(fives, sine, vec![Some(vec![n!(C4)]); 20],
+                quintuplet.repeat(4)),
+            instr_clip!(after, sine, play!(n!(D4)), rhythm![W])]);
+        assert_eq!(arr.get_length(), Duration::new(2, 1));
+        let notes = arr.get_timed_notes();
+        assert_eq!(notes[20].start, Duration::new(1, 1));
+        // the written note values add up to whole bars
+        let items = notate_track(&arr, &arr.get_tracks_ref()[0]).unwrap();
+        let mut bars = vec![Duration::zero()];
+        for item in items.iter() {
+            match *item {
+                NotationItem::Event {value, ..} =>
+                    *bars.last_mut().unwrap() += value.length(),
+                NotationItem::BarLine => bars.push(Duration::zero()),
+                _ => (),
+            }
+        }
+        assert_eq!(bars, vec![Duration::new(1, 1), Duration::new(1, 1),
+            Duration::zero()]);
+        assert_eq!(items.iter().filter(|item|
+            **item == NotationItem::TupletStart(5, 4, 5)).count(), 4);
+        // tuplets are written as tuplets, and read back
+        let tuplets = instr_clip!(tuplets, sine,
+            vec![Some(vec![n!(C4)]); 14], rhythm![3(E, E, E), Q,
+            5(S, S, S, S, S), Q, 3(Q, Q, Q), H]);
+        let arr = Arrangement::new(vec![track!(lead, tuplets)],
+            vec![tuplets.clone()]);
+        let lilypond = arrangement_to_lilypond(&arr).unwrap();
+        assert!(lilypond.contains("\\tuplet 3/2 { c'8 c'8 c'8 } c'4 \
+            \\tuplet 5/4 { c'16 c'16 c'16 c'16 c'16 } c'4 | \
+            \\tuplet 3/2 { c'4 c'4 c'4 } c'2 |"));
+        let document = arrangement_to_musicxml(&arr).unwrap();
+        assert!(document.contains("<divisions>15</divisions>"));
+        assert!(document.contains("<duration>5</duration>\
+            <instrument id=\"P1-I1\"/><voice>1</voice><type>eighth</type>\
+            <time-modification><actual-notes>3</actual-notes>\
+            <normal-notes>2</normal-notes></time-modification>\
+            <notations><tuplet type=\"start\" bracket=\"yes\"/>\
+            </notations>"));
+        assert_eq!(musicxml_to_arrangement(&document).unwrap()
+            .get_clip("tuplets"), Some(&tuplets));
+        let abc = arrangement_to_abc(&arr, "Tuplets").unwrap();
+        assert!(abc.contains("(3C C C C2 (5:4:5C/ C/ C/ C/ C/ C2 | \
+            (3C2 C2 C2 C4 |"));
+        assert_eq!(abc_to_arrangement(&abc, &sine()).unwrap()
+            .get_clip("tuplets"), Some(&tuplets));
+    }
+
+    #[test]
+    fn groove_tests() {
+        let eighths = instr_clip!(eighths, sine, vec![Some(vec![n!(C4)]); 4],
+            rhythm![E, E, E, E]);
+        let mut arr = Arrangement::new(vec![track!(lead, eighths)],
+            vec![eighths.clone()]);
+        // 75% swing plays eighth notes as a dotted eighth and a sixteenth
+        arr.set_transforms("eighths", vec![Transform::Swing(75.0,
+            Beat::E.get_duration())]);
+        assert!(arr.validate().is_ok());
+        let notes = arr.get_timed_notes();
+        assert_eq!((notes[1].start, notes[0].duration, notes[1].duration),
+            (Duration::new(3, 16), Duration::new(3, 16), Duration::new(1, 16)));
+        assert_eq!(notes[2].start, Duration::new(1, 4));
+        assert_eq!(arr.get_length(), Duration::new(1, 2));
+        assert!(arrangement_to_super_collider(&arr)
+            .contains("\\dur, Pseq([0.1875, 0.0625, 0.1875, 0.0625])"));
+        // a groove taken from the swung MIDI file swings the same way
+        let midi = arrangement_to_midi(&arr);
+        assert_eq!(read_note_ons(&midi).unwrap()[1],
+            (Duration::new(3, 16), 100));
+        let groove = Groove::from_midi(&midi, Beat::E.get_duration())
+            .unwrap();
+        assert_eq!(groove.timing[..4], [0.0, 0.0625, 0.0, 0.0625]);
+        assert_eq!(groove.velocity[..4], [0, 0, 0, 0]);
+        arr.set_transforms("eighths", vec![Transform::Groove(groove)]);
+        let starts = |notes: &[TimedNote]| notes.iter()
+            .map(|note| note.start).collect::<Vec<Time>>();
+        assert_eq!(starts(&arr.get_timed_notes()), starts(&notes));
+        // grooves from drum clips follow their accents
+        let beat = drum_clip!(beat, 4, Beat::E.get_duration(),
+            HiHat => "Xxox");
+        let groove = Groove::from_clip(&beat, Beat::E.get_duration())
+            .unwrap();
+        assert_eq!(groove.velocity, vec![33, 6, -44, 6]);
+        arr.set_transforms("eighths", vec![Transform::Groove(groove)]);
+        let velocities: Vec<u8> = arr.get_timed_notes().iter()
+            .map(|note| note.velocity).collect();
+        assert_eq!(velocities, vec![127, 106, 56, 106]);
+        // humanizing is random, but the same for the same seed
+        arr.set_transforms("eighths", vec![Transform::Humanize(1.0 / 64.0, 10,
+            3)]);
+        let humanized = arr.get_timed_notes();
+        assert_eq!(arr.get_timed_notes(), humanized);
+        assert!(humanized.iter().enumerate().all(|(i, played)|
+            played.velocity.abs_diff(100) <= 10 &&
+            (played.start.to_f64() - i as f64 / 8.0).abs() <= 1.0 / 60.0));
+        assert!(humanized != notes);
+        // notes moved back onto the note before them still last a tick
+        let played = perform_clip(&rhythm![E, E], &[Transform::Groove(
+            Groove {grid: Beat::E.get_duration(), timing: vec![0.0, -0.25],
+            velocity: vec![0, 0]})]);
+        assert_eq!((played[1].start, played[1].duration),
+            (Time::zero(), Duration::new(1, 1920)));
+        // and notes that end in the tick they start in end after they start
+        let beat = drum_clip!(beat, 1, Duration::new(1, 4096), Kick => "x");
+        let short = Arrangement::new(vec![track!(drums, beat)], vec![beat]);
+        let midi = arrangement_to_midi(&short);
+        let on = midi.windows(3).position(|event| event == [0x99, 36, 100]);
+        let off = midi.windows(3).position(|event| event == [0x89, 36, 0]);
+        assert!(on.is_some() && on < off);
+        arr.set_transforms("eighths", vec![Transform::Swing(100.0,
+            Beat::E.get_duration())]);
+        assert!(arr.validate().is_err());
+        arr.set_transforms("eighths", Vec::new());
+        assert_eq!(arr.get_transforms("eighths"), &[]);
+        assert!(Groove::from_midi(b"MThd", Beat::E.get_duration()).is_err());
     }
 
     #[test]
@@ -116,13 +1485,15 @@ v1 = Pbind(
         assert_eq!(n!(C4), Note::new(Name::C, 4));
         assert_eq!(chord!(C4, Maj7), Chord::new(Note::new(Name::C, 4),
             ChordType::Maj7));
-        assert_eq!(rhythm![W, Q, W, H], vec![1.0, 0.25, 1.0, 0.5]);
+        assert_eq!(rhythm![W, Q, W, H], vec![Duration::new(1, 1),
+            Duration::new(1, 4), Duration::new(1, 1), Duration::new(1, 2)]);
         println!("track: {:?}", track!(track1, clip1, clip2));
         assert_eq!(track!(track1, clip1, clip2), Track::new("track1".into(),
             vec!["clip1".into(), "clip2".into()]));
         println!("Play output: {:?}", play!(n!(C4), chord!(C4, Maj7), ()));
         assert_eq!(play!(n!(C4), chord!(C4, Maj7), ()),
-            vec![Some(vec![60]), Some(vec![60, 64, 67, 71]), None]);
+            vec![Some(vec![n!(C4)]), Some(vec![n!(C4), n!(E4), n!(G4),
+            n!(B4)]), None]);
     }
 
     #[test]
@@ -130,7 +1501,7 @@ v1 = Pbind(
         let ii_chord = chord!(D4, Min7).play();
         let V_chord = chord!(G3, Dom7).play();
         let I_chord = chord!(C4, Maj7).play();
-        let mut melody_notes: Vec<i8> = Vec::new();
+        let mut melody_notes: Vec<Note> = Vec::new();
         melody_notes.extend(&ii_chord);
         melody_notes.extend(&V_chord);
         melody_notes.extend(&I_chord);
@@ -0,0 +1,125 @@
+/// Translation module that renders a DAWPL arrangement as a LilyPond score, so
+/// that it can be engraved as sheet music. Every track becomes a staff, every
+/// clip is written out in the order given by its track. Notes are spelled
+/// according to the key of the arrangement, and durations are split at bar
+/// lines and into dotted and tied note values. Tuplets are written with
+/// \tuplet.
+use arrangement::*;
+use duration::*;
+use notation::*;
+use note::*;
+use scale::*;
+
+macro_rules! k_lilypond_score_template {
+    () => ("\\version \"2.18.2\"
+
+\\score {{
+  <<
+{staves}  >>
+  \\layout {{ }}
+}}
+")
+}
+
+macro_rules! k_lilypond_staff_template {
+    () => ("    \\new Staff \\with {{ instrumentName = \"{track_name}\" }} {{
+      \\clef {clef}
+      \\key {key}
+      \\time {time}
+      {music}
+    }}
+")
+}
+
+pub fn arrangement_to_lilypond(arrangement: &Arrangement)
+    -> Result<String, String> {
+    let key = notation_key(arrangement);
+    let (beats, beat_value) = arrangement.get_time_signature();
+    let mut staves: String = "".into();
+    for track in arrangement.get_tracks_ref().iter() {
+        let items = notate_track(arrangement, track)?;
+        let clef = if needs_bass_clef(&items) { "bass" } else { "treble" };
+        staves += &format!(k_lilypond_staff_template!(),
+            track_name=track.get_name(), clef=clef,
+            key=key_to_lilypond(&key),
+            time=format!("{}/{}", beats, beat_value),
+            music=items_to_lilypond(&items));
+    }
+    Ok(format!(k_lilypond_score_template!(), staves=staves))
+}
+
+/// Returns the LilyPond name of a note e.g. fis' for F#4 and bes, for Bb2
+pub fn note_to_lilypond(note: &Note) -> String {
+    let accidental = match note.get_accidental() {
+        Accidental::DoubleFlat => "eses",
+        Accidental::Flat => "es",
+        Accidental::Natural => "",
+        Accidental::Sharp => "is",
+        Accidental::DoubleSharp => "isis",
+    };
+    // in absolute mode c is the octave below middle C (C3)
+    let octave_marks = if note.get_octave() >= 3 {
+        "'".repeat((note.get_octave() - 3) as usize)
+    } else {
+        ",".repeat((3 - note.get_octave()) as usize)
+    };
+    format!("{}{}{}", format!("{:?}", note.get_letter()).to_lowercase(),
+        accidental, octave_marks)
+}
+
+fn key_to_lilypond(key: &Scale) -> String {
+    let tonic = note_to_lilypond(&key.get_base_note());
+    let mode = match key.get_scale_type() {
+        ScaleType::Mixolydian => "\\mixolydian",
+        ScaleType::Dorian => "\\dorian",
+        ScaleType::Minor => "\\minor",
+        ScaleType::Lydian => "\\lydian",
+        ScaleType::Phrygian => "\\phrygian",
+        ScaleType::Locrian => "\\locrian",
+        _ => "\\major",
+    };
+    format!("{} {}", tonic.trim_end_matches(&['\'', ','][..]), mode)
+}
+
+/// Returns the LilyPond note values of a duration, longest first, e.g. 3/8
+/// becomes ["4."] and 5/16 becomes ["4", "16"] (to be tied together). The
+/// note values of tuplets are written as they are inside a \tuplet.
+pub fn duration_to_lilypond(duration: Duration)
+    -> Result<Vec<String>, String> {
+    Ok(split_duration(duration)?.iter().map(value_to_lilypond).collect())
+}
+
+fn value_to_lilypond(value: &NoteValue) -> String {
+    format!("{}{}", value.denominator, ".".repeat(value.dots as usize))
+}
+
+fn items_to_lilypond(items: &[NotationItem]) -> String {
+    let mut music: Vec<String> = Vec::new();
+    for item in items.iter() {
+        match *item {
+            NotationItem::Event {ref notes, ref value, tie_start, ..} => {
+                let pitch = match *notes {
+                    Some(ref notes) if notes.len() == 1 =>
+                        note_to_lilypond(&notes[0]),
+                    Some(ref notes) => format!("<{}>", notes.iter()
+                        .map(note_to_lilypond).collect::<Vec<String>>()
+                        .join(" ")),
+                    None => "r".into(),
+                };
+                let tie = if tie_start { "~" } else { "" };
+                music.push(format!("{}{}{}", pitch, value_to_lilypond(value),
+                    tie));
+            },
+            NotationItem::Omitted(ref name) => {
+                music.push(format!("\n      % clip {} omitted\n     ", name));
+            },
+            NotationItem::BarLine => music.push("|".into()),
+            NotationItem::TupletStart(notes, space, _) => {
+                music.push(format!("\\tuplet {}/{} {{", notes, space));
+            },
+            NotationItem::TupletEnd => music.push("}".into()),
+            NotationItem::ClipStart(..) => {},
+        }
+    }
+    music.join(" ")
+}
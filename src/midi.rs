@@ -0,0 +1,305 @@
+/// Translation module that writes a DAWPL arrangement as a Standard MIDI File
+/// (format 1). The first MIDI track holds the tempo, time signature and key
+/// signature, and every DAWPL track becomes a MIDI track of its own on its own
+/// channel (channel 10 is skipped, since it is reserved for drums). Tracks
+/// that play drum clips are on channel 10 instead. Tunings
+/// other than twelve-tone equal temperament at A=440 are written as MIDI
+/// Tuning Standard messages at the start of the file, which retune the keys of
+/// instruments that support them. Notes that sound apart from their retuned
+/// key, because they are spelled other than the default way (C#4 rather than
+/// Db4 in 19-EDO) or detuned in cents, bend the pitch of their channel as
+/// they start; notes that sound together share the bend of the last of them.
+/// Automation of a track's volume, pan and amp
+/// is written as controller changes on the track's channel. The note ons of
+/// MIDI files can also be read, to take grooves from them (see groove.rs).
+use arrangement::*;
+use automation::*;
+use clip::*;
+use duration::*;
+use note::*;
+use scale::*;
+use tuning::*;
+
+/// Ticks per quarter note
+pub const TICKS_PER_QUARTER: u16 = 480;
+pub const VELOCITY: u8 = DEFAULT_VELOCITY;
+const DRUM_CHANNEL: u8 = 9;
+/// Parts of a whole note at each of which curved automation is sampled
+const AUTOMATION_STEPS: u64 = 64;
+
+pub fn arrangement_to_midi(arrangement: &Arrangement) -> Vec<u8> {
+    let tracks = arrangement.get_tracks_ref();
+    let mut midi: Vec<u8> = Vec::new();
+    midi.extend(b"MThd");
+    midi.extend(&6u32.to_be_bytes());
+    midi.extend(&1u16.to_be_bytes());
+    midi.extend(&(tracks.len() as u16 + 1).to_be_bytes());
+    midi.extend(&TICKS_PER_QUARTER.to_be_bytes());
+    write_chunk(&mut midi, &conductor_track(arrangement));
+    let notes = arrangement.get_timed_notes();
+    for (index, track) in tracks.iter().enumerate() {
+        let channel = track_channel(arrangement, index);
+        let drums = channel == DRUM_CHANNEL;
+        // (tick, order of the event within the tick, message); note offs come
+        // before controller changes, which come before note ons, so that
+        // repeated keys are not cut short and notes start at their levels,
+        // but a note that ends in the tick it starts in ends after it starts
+        let mut events: Vec<(u32, u8, [u8; 3])> = Vec::new();
+        let mut bend = 0;
+        for note in notes.iter().filter(|note| note.track == index) {
+            let start = time_to_ticks(note.start);
+            let key = note.pitch.get_midi_value() as u8;
+            let note_bend = if drums { 0 } else { key_bend(arrangement, note) };
+            if note_bend != bend {
+                bend = note_bend;
+                let value = (bend as i32 + 8192) as u16;
+                events.push((start, 1, [0xe0 | channel, (value & 0x7f) as u8,
+                    (value >> 7) as u8]));
+            }
+            events.push((start, 2, [0x90 | channel, key, note.velocity]));
+            let end = time_to_ticks(note.start + note.duration);
+            events.push((end, if end == start { 3 } else { 0 },
+                [0x80 | channel, key, 0]));
+        }
+        for lane in arrangement.get_automation_lanes().iter().filter(|lane|
+            lane.target.get_track() == Some(track.get_name())) {
+            for (tick, controller, value) in controller_changes(lane) {
+                events.push((tick, 1, [0xb0 | channel, controller, value]));
+            }
+        }
+        events.sort_by_key(|&(tick, order, _)| (tick, order));
+        let mut data: Vec<u8> = Vec::new();
+        write_meta(&mut data, 0, 0x03, track.get_name().as_bytes());
+        let mut last_tick = 0;
+        for &(tick, _, message) in events.iter() {
+            write_variable_length(&mut data, tick - last_tick);
+            data.extend(&message);
+            last_tick = tick;
+        }
+        write_meta(&mut data, 0, 0x2f, &[]);
+        write_chunk(&mut midi, &data);
+    }
+    midi
+}
+
+/// The first track of the file, which holds the settings of the arrangement
+fn conductor_track(arrangement: &Arrangement) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    let micros_per_quarter = (60_000_000.0 / arrangement.get_tempo()).round()
+        .clamp(1.0, 16_777_215.0) as u32;
+    write_meta(&mut data, 0, 0x51, &micros_per_quarter.to_be_bytes()[1..]);
+    let (beats, beat_value) = arrangement.get_time_signature();
+    write_meta(&mut data, 0, 0x58, &[beats,
+        beat_value.trailing_zeros() as u8, 24, 8]);
+    if let Some(key) = arrangement.get_key() {
+        if let Some(fifths) = key.key_signature() {
+            let minor = key.get_scale_type() == ScaleType::Minor;
+            write_meta(&mut data, 0, 0x59, &[fifths as u8, minor as u8]);
+        }
+    }
+    let tuning = arrangement.get_tuning();
+    let a4_hz = arrangement.get_concert_pitch();
+    if !tuning.is_equal_temperament() || a4_hz != CONCERT_A {
+        for message in tuning.mts_messages(0x7f, 0, a4_hz).iter() {
+            // system exclusive events are written without their leading 0xf0
+            data.extend(&[0, 0xf0]);
+            write_variable_length(&mut data, message.len() as u32 - 1);
+            data.extend(&message[1..]);
+        }
+    }
+    write_meta(&mut data, 0, 0x2f, &[]);
+    data
+}
+
+/// The pitch bend that makes the key of a note, as tuned at the start of the
+/// file, sound the note as it is spelled and detuned
+fn key_bend(arrangement: &Arrangement, note: &TimedNote) -> i16 {
+    let tuning = arrangement.get_tuning();
+    let a4_hz = arrangement.get_concert_pitch();
+    let key = note.pitch.get_midi_value();
+    match (tuning.frequency(&note.pitch, a4_hz),
+        tuning.key_frequency(key, a4_hz)) {
+        (Some(freq), Some(key_freq)) =>
+            bend_to(ratio_to_cents(freq / key_freq) / 100.0),
+        _ => 0,
+    }
+}
+
+/// Controller changes that follow an automation lane, as ticks, controller
+/// numbers and values: the value at the start, at every breakpoint and, on
+/// curves, every 1/AUTOMATION_STEPS of a whole note, whenever it changes.
+/// Lanes of targets without a controller give none.
+fn controller_changes(lane: &AutomationLane) -> Vec<(u32, u8, u8)> {
+    let step = Duration::new(1, AUTOMATION_STEPS);
+    let mut times: Vec<Time> = vec![Time::zero()];
+    for (i, breakpoint) in lane.breakpoints.iter().enumerate() {
+        times.push(breakpoint.time);
+        if breakpoint.curve == Curve::Step {
+            continue;
+        }
+        if let Some(next) = lane.breakpoints.get(i + 1) {
+            let mut time = breakpoint.time + step;
+            while time < next.time {
+                times.push(time);
+                time += step;
+            }
+        }
+    }
+    times.sort();
+    let mut changes: Vec<(u32, u8, u8)> = Vec::new();
+    for time in times.into_iter() {
+        let (controller, value) = match lane.target
+            .midi_controller(lane.value_at(time)) {
+            Some(change) => change,
+            None => return Vec::new(),
+        };
+        if changes.last().map_or(true, |last| last.2 != value) {
+            changes.push((time_to_ticks(time), controller, value));
+        }
+    }
+    changes
+}
+
+/// Returns the channel of the track at the given index: the drum channel if
+/// it plays drum clips, and otherwise a channel of its own
+pub fn track_channel(arrangement: &Arrangement, index: usize) -> u8 {
+    let drums = arrangement.get_tracks_ref().get(index).is_some_and(|track|
+        track.get_names_ref().iter().any(|name| matches!(
+        arrangement.get_clip(name), Some(Clip::Drums(_, _)))));
+    if drums {
+        return DRUM_CHANNEL;
+    }
+    let channel = (index % 15) as u8;
+    if channel >= DRUM_CHANNEL { channel + 1 } else { channel }
+}
+
+/// Converts a time to the nearest tick, exactly, so that notes of tuplets
+/// that add up to a beat end on its tick
+pub fn time_to_ticks(time: Time) -> u32 {
+    let ticks = u128::from(time.get_numerator()) * 4 *
+        u128::from(TICKS_PER_QUARTER);
+    let denominator = u128::from(time.get_denominator());
+    ((2 * ticks + denominator) / (2 * denominator)).min(u32::MAX as u128)
+        as u32
+}
+
+fn write_chunk(midi: &mut Vec<u8>, data: &[u8]) {
+    midi.extend(b"MTrk");
+    midi.extend(&(data.len() as u32).to_be_bytes());
+    midi.extend(data);
+}
+
+fn write_meta(data: &mut Vec<u8>, delta: u32, meta_type: u8, contents: &[u8]) {
+    write_variable_length(data, delta);
+    data.extend(&[0xff, meta_type]);
+    write_variable_length(data, contents.len() as u32);
+    data.extend(contents);
+}
+
+/// Reads the note ons of a Standard MIDI File, from all of its tracks, as
+/// their times from the start of the file and their velocities, in order of
+/// time. Files timed in SMPTE frames are not read.
+pub fn read_note_ons(data: &[u8]) -> Result<Vec<(Time, u8)>, String> {
+    let invalid = || "Invalid MIDI file".to_string();
+    if data.len() < 14 || &data[..4] != b"MThd" {
+        return Err(invalid());
+    }
+    let header_length = u32::from_be_bytes([data[4], data[5], data[6],
+        data[7]]) as usize;
+    let division = u16::from_be_bytes([data[12], data[13]]);
+    if division == 0 || division & 0x8000 != 0 {
+        return Err("MIDI files timed in SMPTE frames are not read".into());
+    }
+    let ticks_per_whole = 4 * division as u64;
+    let mut notes: Vec<(Time, u8)> = Vec::new();
+    let mut position = 8 + header_length;
+    while position + 8 <= data.len() {
+        let length = u32::from_be_bytes([data[position + 4],
+            data[position + 5], data[position + 6], data[position + 7]])
+            as usize;
+        let start = position + 8;
+        let end = start.checked_add(length).filter(|end| *end <= data.len())
+            .ok_or_else(invalid)?;
+        if &data[position..position + 4] == b"MTrk" {
+            for (tick, velocity) in track_note_ons(&data[start..end])
+                .ok_or_else(invalid)? {
+                notes.push((Duration::new(tick, ticks_per_whole), velocity));
+            }
+        }
+        position = end;
+    }
+    notes.sort_by_key(|note| note.0);
+    Ok(notes)
+}
+
+/// The note ons of the events of a track chunk, as ticks and velocities, or
+/// None if the events cannot be read
+fn track_note_ons(data: &[u8]) -> Option<Vec<(u64, u8)>> {
+    let mut notes: Vec<(u64, u8)> = Vec::new();
+    let mut position = 0;
+    let mut tick: u64 = 0;
+    let mut running_status: u8 = 0;
+    while position < data.len() {
+        tick += read_variable_length(data, &mut position)? as u64;
+        let mut status = *data.get(position)?;
+        if status < 0x80 {
+            // running status: the data bytes of another event of the kind
+            // before
+            status = running_status;
+        } else {
+            position += 1;
+        }
+        match status {
+            0xff => {
+                position += 1;
+                let length = read_variable_length(data, &mut position)?;
+                position += length as usize;
+            },
+            0xf0 | 0xf7 => {
+                let length = read_variable_length(data, &mut position)?;
+                position += length as usize;
+            },
+            0x80..=0xef => {
+                running_status = status;
+                let data_bytes = match status & 0xf0 {
+                    0xc0 | 0xd0 => 1,
+                    _ => 2,
+                };
+                let message = data.get(position..position + data_bytes)?;
+                if status & 0xf0 == 0x90 && message[1] > 0 {
+                    notes.push((tick, message[1]));
+                }
+                position += data_bytes;
+            },
+            _ => return None,
+        }
+    }
+    Some(notes)
+}
+
+/// Reads a number written seven bits at a time, moving past it
+fn read_variable_length(data: &[u8], position: &mut usize) -> Option<u32> {
+    let mut value: u32 = 0;
+    for _ in 0..4 {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value = (value << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Writes a number seven bits at a time, most significant first, setting the
+/// top bit of every byte but the last
+pub fn write_variable_length(data: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    data.extend(bytes);
+}
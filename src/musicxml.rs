@@ -0,0 +1,517 @@
+/// Translation module between DAWPL arrangements and MusicXML partwise
+/// documents, the interchange format of notation programs such as MuseScore,
+/// Finale and Sibelius. Every track becomes a part. Clip names are written as
+/// words above the first note of each clip and the instrument of each clip as
+/// a score instrument, so that importing an exported document restores the
+/// clips of every track. Chords that DAWPL can name are written as chord
+/// symbols, and tuplets with their time modifications and brackets.
+use arrangement::*;
+use chord::*;
+use clip::*;
+use duration::*;
+use instruments::*;
+use notation::*;
+use note::*;
+use scale::*;
+use track::*;
+use xml::reader::{EventReader, XmlEvent};
+
+macro_rules! k_musicxml_template {
+    () => ("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>
+<!DOCTYPE score-partwise \
+PUBLIC \"-//Recordare//DTD MusicXML 3.1 Partwise//EN\" \
+\"http://www.musicxml.org/dtds/partwise.dtd\">
+<score-partwise version=\"3.1\">
+  <part-list>
+{score_parts}  </part-list>
+{parts}</score-partwise>
+")
+}
+
+macro_rules! k_musicxml_attributes_template {
+    () => ("      <attributes>
+        <divisions>{divisions}</divisions>
+        <key><fifths>{fifths}</fifths><mode>{mode}</mode></key>
+        <time><beats>{beats}</beats><beat-type>{beat_type}</beat-type></time>
+        <clef><sign>{sign}</sign><line>{line}</line></clef>
+      </attributes>
+")
+}
+
+/// Escapes text for use in XML content and attribute values
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn arrangement_to_musicxml(arrangement: &Arrangement)
+    -> Result<String, String> {
+    let key = notation_key(arrangement);
+    let (beats, beat_type) = arrangement.get_time_signature();
+    let tracks_items: Vec<Vec<NotationItem>> = arrangement.get_tracks_ref()
+        .iter().map(|track| notate_track(arrangement, track))
+        .collect::<Result<_, _>>()?;
+    // divisions of a quarter note, fine enough for every note value and
+    // tuplet
+    let divisions = tracks_items.iter().flat_map(|items| items.iter())
+        .filter_map(|item| match *item {
+            NotationItem::Event {value, ..} => Some(value.length() * 4),
+            _ => None,
+        }).fold(1, |divisions, length|
+            divisions * (length * divisions).get_denominator()) as u32;
+    let mut score_parts: String = "".into();
+    let mut parts: String = "".into();
+    for (i, (track, items)) in arrangement.get_tracks_ref().iter()
+        .zip(tracks_items.iter()).enumerate() {
+        let part_id = format!("P{}", i + 1);
+        let mut instruments: Vec<String> = Vec::new();
+        for item in items.iter() {
+            if let NotationItem::ClipStart(_, Some(ref instrument)) = *item {
+                if !instruments.contains(instrument) {
+                    instruments.push(instrument.clone());
+                }
+            }
+        }
+        score_parts += &format!("    <score-part id=\"{}\">\n      \
+            <part-name>{}</part-name>\n", part_id, escape(track.get_name()));
+        for (j, instrument) in instruments.iter().enumerate() {
+            score_parts += &format!("      <score-instrument id=\"{}-I{}\">\
+                <instrument-name>{}</instrument-name></score-instrument>\n",
+                part_id, j + 1, escape(instrument));
+        }
+        score_parts += "    </score-part>\n";
+        let (sign, line) = if needs_bass_clef(items) { ("F", 4) }
+            else { ("G", 2) };
+        let attributes = format!(k_musicxml_attributes_template!(),
+            divisions=divisions, fifths=key.key_signature().unwrap_or(0),
+            mode=key_mode(&key), beats=beats, beat_type=beat_type, sign=sign,
+            line=line);
+        parts += &format!("  <part id=\"{}\">\n", part_id);
+        parts += &items_to_measures(items, &attributes, divisions, &part_id,
+            &instruments);
+        parts += "  </part>\n";
+    }
+    Ok(format!(k_musicxml_template!(), score_parts=score_parts,
+        parts=parts))
+}
+
+fn key_mode(key: &Scale) -> &'static str {
+    match key.get_scale_type() {
+        ScaleType::Dorian => "dorian",
+        ScaleType::Mixolydian => "mixolydian",
+        ScaleType::Minor => "minor",
+        ScaleType::Lydian => "lydian",
+        ScaleType::Phrygian => "phrygian",
+        ScaleType::Locrian => "locrian",
+        _ => "major",
+    }
+}
+
+fn value_type(value: &NoteValue) -> &'static str {
+    match value.denominator {
+        1 => "whole",
+        2 => "half",
+        4 => "quarter",
+        8 => "eighth",
+        16 => "16th",
+        32 => "32nd",
+        64 => "64th",
+        _ => "128th",
+    }
+}
+
+fn chord_kind(chord_type: ChordType) -> &'static str {
+    match chord_type {
+        ChordType::Maj7 => "major-seventh",
+        ChordType::Min7 => "minor-seventh",
+        ChordType::Dom7 => "dominant",
+        ChordType::Dim => "diminished",
+        ChordType::Aug => "augmented",
+        ChordType::Maj6 => "major-sixth",
+    }
+}
+
+/// Writes the pitch of a note, with its detuning as a fractional alteration
+/// e.g. 1.5 for a sharp 50 cents sharp
+fn pitch_to_musicxml(note: &Note) -> String {
+    let alter = note.get_accidental().half_steps() as f64 +
+        note.get_cents() as f64 / 100.0;
+    let alter = if alter == 0.0 {
+        "".into()
+    } else {
+        format!("<alter>{}</alter>", alter)
+    };
+    format!("<pitch><step>{:?}</step>{}<octave>{}</octave></pitch>",
+        note.get_letter(), alter, note.get_octave())
+}
+
+/// Writes the notation items of a track as measures, the first of which
+/// carries the given attributes
+fn items_to_measures(items: &[NotationItem], attributes: &str, divisions: u32,
+    part_id: &str, instruments: &[String]) -> String {
+    let mut measures: Vec<String> = vec!["".into()];
+    let mut instrument_id: Option<String> = None;
+    // the next note starts a tuplet bracket
+    let mut tuplet_start = false;
+    for (index, item) in items.iter().enumerate() {
+        let measure = measures.last_mut().unwrap();
+        match *item {
+            NotationItem::ClipStart(ref name, ref instrument) => {
+                *measure += &format!("      <direction placement=\"above\">\
+                    <direction-type><words>{}</words></direction-type>\
+                    </direction>\n", escape(name));
+                instrument_id = instrument.as_ref().map(|instrument| {
+                    let index = instruments.iter()
+                        .position(|other| other == instrument).unwrap();
+                    format!("{}-I{}", part_id, index + 1)
+                });
+            },
+            NotationItem::Event {ref notes, ref value, tie_start, tie_stop,
+                ref chord} => {
+                if let Some(ref chord) = *chord {
+                    let root = chord.get_root();
+                    let root_alter = match root.get_accidental() {
+                        Accidental::Natural => "".into(),
+                        accidental => format!("<root-alter>{}</root-alter>",
+                            accidental.half_steps()),
+                    };
+                    *measure += &format!("      <harmony><root><root-step>{:?}\
+                        </root-step>{}</root><kind>{}</kind></harmony>\n",
+                        root.get_letter(), root_alter,
+                        chord_kind(chord.get_chord_type()));
+                }
+                // a whole number, since divisions fit every note value
+                let duration = value.length() * (4 * divisions as u64);
+                let duration = (duration.get_numerator() /
+                    duration.get_denominator()) as u32;
+                let pitches: Vec<String> = match *notes {
+                    Some(ref notes) => notes.iter().map(pitch_to_musicxml)
+                        .collect(),
+                    None => vec!["<rest/>".into()],
+                };
+                let mut ties: String = "".into();
+                let mut tied: String = "".into();
+                if tie_stop {
+                    ties += "<tie type=\"stop\"/>";
+                    tied += "<tied type=\"stop\"/>";
+                }
+                if tie_start {
+                    ties += "<tie type=\"start\"/>";
+                    tied += "<tied type=\"start\"/>";
+                }
+                let instrument = match instrument_id {
+                    Some(ref id) if notes.is_some() =>
+                        format!("<instrument id=\"{}\"/>", id),
+                    _ => "".into(),
+                };
+                let time_modification = match value.tuplet {
+                    Some((notes, space)) => format!("<time-modification>\
+                        <actual-notes>{}</actual-notes><normal-notes>{}\
+                        </normal-notes></time-modification>", notes, space),
+                    None => "".into(),
+                };
+                let mut brackets: String = "".into();
+                if tuplet_start {
+                    brackets += "<tuplet type=\"start\" bracket=\"yes\"/>";
+                    tuplet_start = false;
+                }
+                if items.get(index + 1) == Some(&NotationItem::TupletEnd) {
+                    brackets += "<tuplet type=\"stop\"/>";
+                }
+                for (i, pitch) in pitches.iter().enumerate() {
+                    let chord_mark = if i > 0 { "<chord/>" } else { "" };
+                    // brackets are put on the first note of a chord
+                    let marks = if i == 0 {
+                        format!("{}{}", tied, brackets)
+                    } else {
+                        tied.clone()
+                    };
+                    let notations = if marks.is_empty() { "".into() }
+                        else { format!("<notations>{}</notations>", marks) };
+                    *measure += &format!("      <note>{}{}<duration>{}\
+                        </duration>{}{}<voice>1</voice><type>{}</type>{}{}{}\
+                        </note>\n", chord_mark, pitch, duration, ties,
+                        instrument, value_type(value),
+                        "<dot/>".repeat(value.dots as usize),
+                        time_modification, notations);
+                }
+            },
+            NotationItem::TupletStart(..) => tuplet_start = true,
+            NotationItem::TupletEnd => {},
+            NotationItem::Omitted(ref name) => {
+                *measure += &format!("      <!-- clip {} omitted -->\n",
+                    escape(name).replace("--", "- -"));
+            },
+            NotationItem::BarLine => measures.push("".into()),
+        }
+    }
+    if measures.len() > 1 && measures.last().unwrap().is_empty() {
+        measures.pop();
+    }
+    let mut output: String = "".into();
+    for (i, measure) in measures.iter().enumerate() {
+        output += &format!("    <measure number=\"{}\">\n", i + 1);
+        if i == 0 {
+            output += attributes;
+        }
+        output += measure;
+        output += "    </measure>\n";
+    }
+    output
+}
+
+/// A parsed XML element, keeping only what MusicXML import needs
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str)
+        -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|attribute| attribute.0 == name)
+            .map(|attribute| &attribute.1[..])
+    }
+
+    /// Text of a child element, trimmed
+    fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim())
+    }
+
+    fn child_number<T: ::std::str::FromStr>(&self, name: &str)
+        -> Result<Option<T>, String> {
+        match self.child_text(name) {
+            Some(text) => text.parse().map(Some)
+                .map_err(|_| format!("Invalid <{}> value: {}", name, text)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_xml(document: &str) -> Result<Element, String> {
+    let mut stack: Vec<Element> = Vec::new();
+    for event in EventReader::from_str(document) {
+        match event.map_err(|error| format!("Invalid XML: {}", error))? {
+            XmlEvent::StartElement {name, attributes, ..} => {
+                stack.push(Element {name: name.local_name,
+                    attributes: attributes.into_iter().map(|attribute|
+                        (attribute.name.local_name, attribute.value)).collect(),
+                    children: Vec::new(), text: "".into()});
+            },
+            XmlEvent::EndElement {..} => {
+                let element = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            },
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text += &text;
+                }
+            },
+            _ => {},
+        }
+    }
+    Err("Invalid XML: missing root element".into())
+}
+
+/// Key signature (sharps or flats) and mode to the scale of the key
+fn key_from_musicxml(fifths: i8, mode: &str) -> Scale {
+    let scale_type = match mode {
+        "dorian" => ScaleType::Dorian,
+        "mixolydian" => ScaleType::Mixolydian,
+        "minor" | "aeolian" => ScaleType::Minor,
+        "lydian" => ScaleType::Lydian,
+        "phrygian" => ScaleType::Phrygian,
+        "locrian" => ScaleType::Locrian,
+        _ => ScaleType::Major,
+    };
+    Scale::from_key_signature(fifths, scale_type).unwrap_or_else(||
+        Scale::new(Note::new(Name::C, 4), ScaleType::Major))
+}
+
+fn pitch_from_musicxml(pitch: &Element) -> Result<Note, String> {
+    let step = pitch.child_text("step").unwrap_or("");
+    let letter: Letter = step.parse()
+        .map_err(|_| format!("Invalid <step> value: {}", step))?;
+    let alter: f64 = pitch.child_number("alter")?.unwrap_or(0.0);
+    let octave: i32 = pitch.child_number("octave")?
+        .ok_or_else(|| "Missing <octave> in pitch".to_string())?;
+    // microtonal alterations e.g. 0.5 are kept as cents
+    let half_steps = alter.round();
+    let accidental = Accidental::from_half_steps(half_steps.clamp(-3.0, 3.0)
+        as i8).ok_or_else(|| format!("Invalid <alter> value: {}", alter))?;
+    let midi = (octave + 1) * NUM_TONES as i32 + letter.half_steps() as i32
+        + half_steps as i32;
+    if midi < 0 || midi >= MIDI_NUM as i32 {
+        return Err(format!("Pitch outside of the MIDI range: {}", midi));
+    }
+    Ok(Note::spelled(letter, accidental, octave as i8)
+        .with_cents(((alter - half_steps) * 100.0).round() as i16))
+}
+
+/// Reads a MusicXML partwise document into an arrangement. Every part becomes
+/// a track, and the notes of a part are split into clips wherever words
+/// (such as those written by arrangement_to_musicxml) appear above the staff.
+/// Only the first voice of every part is imported, and the key and time
+/// signature are read from the first measure of the first part.
+pub fn musicxml_to_arrangement(document: &str)
+    -> Result<Arrangement, String> {
+    let root = parse_xml(document)?;
+    if root.name != "score-partwise" {
+        return Err(format!("Expected a partwise score, found <{}>",
+            root.name));
+    }
+    let part_list = root.child("part-list")
+        .ok_or_else(|| "Missing <part-list>".to_string())?;
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut clips: Vec<Clip> = Vec::new();
+    let mut key: Option<Scale> = None;
+    let mut time_signature: Option<(u8, u8)> = None;
+    for part in root.children("part") {
+        let part_id = part.attribute("id").unwrap_or("");
+        let score_part = part_list.children("score-part")
+            .find(|score_part| score_part.attribute("id") == Some(part_id));
+        let track_name = score_part.and_then(|score_part|
+            score_part.child_text("part-name")).unwrap_or(part_id).to_string();
+        let instrument_name = |id: &str| score_part.and_then(|score_part| {
+            score_part.children("score-instrument")
+                .find(|instrument| instrument.attribute("id") == Some(id))
+                .and_then(|instrument| instrument.child_text("instrument-name"))
+        }).map(|name| name.to_string());
+        let default_instrument = score_part.and_then(|score_part| {
+            score_part.child("score-instrument")
+                .and_then(|instrument| instrument.child_text("instrument-name"))
+        }).map(|name| name.to_string());
+        let mut track = Track::new(track_name.clone(), Vec::new());
+        let mut builder: Option<ClipBuilder> = None;
+        // divisions of a quarter note
+        let mut divisions = Duration::new(1, 1);
+        let mut finished: Vec<ClipBuilder> = Vec::new();
+        for measure in part.children("measure") {
+            for element in measure.children.iter() {
+                match &element.name[..] {
+                    "attributes" => {
+                        divisions = element.child_number("divisions")?
+                            .unwrap_or(divisions);
+                        if divisions.is_zero() {
+                            return Err("Invalid <divisions> value: 0".into());
+                        }
+                        if let Some(key_element) = element.child("key") {
+                            let fifths = key_element.child_number("fifths")?
+                                .unwrap_or(0);
+                            let mode = key_element.child_text("mode")
+                                .unwrap_or("major");
+                            key = key.or_else(||
+                                Some(key_from_musicxml(fifths, mode)));
+                        }
+                        if let Some(time) = element.child("time") {
+                            if let (Some(beats), Some(beat_type)) =
+                                (time.child_number("beats")?,
+                                 time.child_number("beat-type")?) {
+                                time_signature = time_signature
+                                    .or(Some((beats, beat_type)));
+                            }
+                        }
+                    },
+                    "direction" => {
+                        let words = element.children("direction-type")
+                            .filter_map(|direction_type|
+                                direction_type.child_text("words"))
+                            .find(|words| !words.is_empty());
+                        if let Some(words) = words {
+                            if let Some(done) = builder.take() {
+                                finished.push(done);
+                            }
+                            builder = Some(ClipBuilder::new(words.to_string()));
+                        }
+                    },
+                    "note" => {
+                        let voice = element.child_text("voice").unwrap_or("1");
+                        if voice != "1" || element.child("grace").is_some() ||
+                            element.child("cue").is_some() {
+                            continue;
+                        }
+                        let current = builder.get_or_insert_with(||
+                            ClipBuilder::new(format!("{}_clip{}", track_name,
+                                finished.len() + 1)));
+                        let pitch = match element.child("pitch") {
+                            Some(pitch) => Some(pitch_from_musicxml(pitch)?),
+                            None => None,
+                        };
+                        if current.instrument.is_none() && pitch.is_some() {
+                            current.instrument = element.child("instrument")
+                                .and_then(|instrument|
+                                    instrument.attribute("id"))
+                                .and_then(&instrument_name)
+                                .or_else(|| default_instrument.clone());
+                        }
+                        if element.child("chord").is_some() {
+                            if let (Some(pitch), Some(Some(notes))) =
+                                (pitch, current.melody.last_mut()) {
+                                if !notes.contains(&pitch) {
+                                    notes.push(pitch);
+                                }
+                            }
+                            continue;
+                        }
+                        let duration = element.child_number("duration")?
+                            .unwrap_or_else(Duration::zero) / divisions / 4;
+                        let tie_stop = element.children("tie")
+                            .any(|tie| tie.attribute("type") == Some("stop"));
+                        let continues_last = match current.melody.last() {
+                            Some(Some(notes)) => tie_stop && pitch
+                                .is_some_and(|pitch| notes.contains(&pitch)),
+                            _ => false,
+                        };
+                        if continues_last {
+                            *current.durations.last_mut().unwrap() += duration;
+                        } else {
+                            current.melody.push(pitch.map(|pitch| vec![pitch]));
+                            current.durations.push(duration);
+                        }
+                    },
+                    "forward" => {
+                        let voice = element.child_text("voice").unwrap_or("1");
+                        let duration = element.child_number("duration")?
+                            .unwrap_or_else(Duration::zero) / divisions / 4;
+                        if voice == "1" && !duration.is_zero() {
+                            let current = builder.get_or_insert_with(||
+                                ClipBuilder::new(format!("{}_clip{}",
+                                    track_name, finished.len() + 1)));
+                            current.melody.push(None);
+                            current.durations.push(duration);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+        finished.extend(builder.take());
+        for clip_builder in finished.into_iter() {
+            let clip = clip_builder.build(&sine());
+            let name = add_clip(&mut clips, clip);
+            track.append_clip(name);
+        }
+        tracks.push(track);
+    }
+    let mut arrangement = Arrangement::new(tracks, clips);
+    if let Some(key) = key {
+        arrangement.set_key(key);
+    }
+    if let Some((beats, beat_type)) = time_signature {
+        arrangement.set_time_signature(beats, beat_type);
+    }
+    Ok(arrangement)
+}
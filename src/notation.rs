@@ -0,0 +1,317 @@
+/// Shared groundwork for the notation backends (LilyPond, MusicXML, ABC). A
+/// track is laid out as a sequence of notation items: notes (spelled as they
+/// are in their clips) and rests with written note values, bar lines, and the
+/// starts of clips. Durations that do not fit a single note value or that
+/// cross a bar line are split into tied pieces, and durations of tuplets are
+/// written as note values of a tuplet, in groups that end back on the beat.
+/// Importers collect the clips they read with a ClipBuilder.
+use arrangement::*;
+use chord::*;
+use clip::*;
+use duration::*;
+use instruments::*;
+use note::*;
+use scale::*;
+use synth::*;
+use track::*;
+
+const SHORTEST_VALUE: u32 = 128; // shortest note value written (128th note)
+
+/// A written note value e.g. a dotted quarter is {denominator: 4, dots: 1}.
+/// Note values of tuplets have the number of notes of the tuplet and the
+/// number of notes whose time they are played in, e.g. (3, 2) for triplets.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct NoteValue {
+    pub denominator: u32,
+    pub dots: u8,
+    pub tuplet: Option<(u32, u32)>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum NotationItem {
+    /// A note or chord (spelled), or a rest if there are no notes. Pieces of
+    /// a split note are tied to the piece before (tie_stop) and/or after
+    /// (tie_start). The chord formed by the notes is named on the first piece.
+    Event {
+        notes: Option<Vec<Note>>,
+        value: NoteValue,
+        tie_start: bool,
+        tie_stop: bool,
+        chord: Option<Chord>,
+    },
+    /// Start of a clip, with its name and instrument (if any)
+    ClipStart(String, Option<String>),
+    /// A clip that cannot be written as notes e.g. an audio file clip
+    Omitted(String),
+    BarLine,
+    /// Start of a group of notes of a tuplet, with the number of notes of
+    /// the tuplet, the number of notes whose time they are played in and the
+    /// number of events in the group
+    TupletStart(u32, u32, usize),
+    TupletEnd,
+}
+
+impl NoteValue {
+    /// Length of the note value as it is written, outside of any tuplet
+    pub fn written_length(&self) -> Duration {
+        Duration::new(1, self.denominator as u64).dotted(self.dots as u32)
+    }
+
+    /// Exact length of the note value as it is played
+    pub fn length(&self) -> Duration {
+        match self.tuplet {
+            Some((notes, space)) => self.written_length()
+                .tuplet(notes as u64, space as u64),
+            None => self.written_length(),
+        }
+    }
+}
+
+/// Splits a duration into note values, longest first, e.g. 3/8 becomes a
+/// dotted quarter and 5/16 a quarter and a sixteenth. Durations whose
+/// denominators are not powers of two are written as note values of a
+/// tuplet, e.g. 1/12 as an eighth of a triplet and 1/20 as a sixteenth of a
+/// quintuplet (5 in the time of 4). Durations that cannot be written exactly
+/// with notes down to a 128th give an error.
+pub fn split_duration(duration: Duration) -> Result<Vec<NoteValue>, String> {
+    let denominator = duration.get_denominator();
+    let notes = denominator >> denominator.trailing_zeros();
+    let (tuplet, mut remaining) = if notes > 1 {
+        let space = tuplet_space(notes);
+        (Some((notes as u32, space as u32)),
+            duration * Duration::new(notes, space))
+    } else {
+        (None, duration)
+    };
+    let mut values: Vec<NoteValue> = Vec::new();
+    'fill: while !remaining.is_zero() {
+        let mut denominator = 1;
+        while denominator <= SHORTEST_VALUE {
+            // try double dotted, dotted and plain values of this length
+            for dots in (0..3).rev() {
+                let value = NoteValue {denominator, dots, tuplet};
+                if value.written_length() <= remaining {
+                    values.push(value);
+                    remaining -= value.written_length();
+                    continue 'fill;
+                }
+            }
+            denominator *= 2;
+        }
+        return Err(format!("A duration of {} cannot be written as note \
+            values", duration));
+    }
+    Ok(values)
+}
+
+/// Returns the key the notes of an arrangement are written in, C major if
+/// the arrangement has no key
+pub fn notation_key(arrangement: &Arrangement) -> Scale {
+    arrangement.get_key().unwrap_or_else(|| {
+        Scale::new(Note::new(Name::C, 4), ScaleType::Major)
+    })
+}
+
+/// True if the notes of a track reach below the treble staff
+pub fn needs_bass_clef(items: &[NotationItem]) -> bool {
+    items.iter().any(|item| match *item {
+        NotationItem::Event {notes: Some(ref notes), ..} =>
+            notes.iter().any(|note| note.get_midi_value() < 55),
+        _ => false,
+    })
+}
+
+/// Lays out the clips of a track as notation items, or gives an error if a
+/// duration cannot be written
+pub fn notate_track(arrangement: &Arrangement, track: &Track)
+    -> Result<Vec<NotationItem>, String> {
+    let mut notator = Notator {bar_length: arrangement.get_bar_length(),
+        position: Time::zero(), items: Vec::new()};
+    for clip_name in track.get_names_ref().iter() {
+        match arrangement.get_clip(clip_name) {
+            Some(clip) => notator.notate_clip(clip)?,
+            None => notator.items.push(NotationItem::Omitted(
+                clip_name.clone())),
+        }
+    }
+    Ok(group_tuplets(notator.items))
+}
+
+/// Puts consecutive events of the same tuplet into groups, each of which
+/// ends once its events add up to a length without a tuplet (e.g. three
+/// eighths of a triplet make a quarter) or at the next item that is not one
+/// of its events
+fn group_tuplets(items: Vec<NotationItem>) -> Vec<NotationItem> {
+    let tuplet_of = |item: &NotationItem| match *item {
+        NotationItem::Event {value, ..} => value.tuplet,
+        _ => None,
+    };
+    let mut grouped: Vec<NotationItem> = Vec::new();
+    let mut i = 0;
+    while i < items.len() {
+        let tuplet = match tuplet_of(&items[i]) {
+            Some(tuplet) => tuplet,
+            None => {
+                grouped.push(items[i].clone());
+                i += 1;
+                continue;
+            },
+        };
+        let mut end = i;
+        let mut length = Duration::zero();
+        while end < items.len() && tuplet_of(&items[end]) == Some(tuplet) {
+            if let NotationItem::Event {value, ..} = items[end] {
+                length += value.length();
+            }
+            end += 1;
+            if length.get_denominator().is_power_of_two() {
+                break;
+            }
+        }
+        grouped.push(NotationItem::TupletStart(tuplet.0, tuplet.1, end - i));
+        grouped.extend(items[i..end].iter().cloned());
+        grouped.push(NotationItem::TupletEnd);
+        i = end;
+    }
+    grouped
+}
+
+/// Keeps track of the position within the current bar while notating a
+/// track, so that events can be split and tied across bar lines.
+struct Notator {
+    bar_length: Duration,
+    position: Time,
+    items: Vec<NotationItem>,
+}
+
+impl Notator {
+    fn notate_clip(&mut self, clip: &Clip) -> Result<(), String> {
+        match *clip {
+            Clip::Instrument(ref name, ref instrument, ref melody,
+                ref durations) => {
+                self.items.push(NotationItem::ClipStart(name.clone(),
+                    Some(instrument.get_name().clone())));
+                for (notes, duration) in melody.iter().zip(durations.iter()) {
+                    match *notes {
+                        Some(ref notes) if !notes.is_empty() =>
+                            self.notate_notes(notes, *duration)?,
+                        _ => self.notate_event(None, None, *duration)?,
+                    }
+                }
+            },
+            Clip::File(ref name, _) => {
+                self.items.push(NotationItem::Omitted(name.clone()));
+            },
+            Clip::Empty(ref name, duration) => {
+                self.items.push(NotationItem::ClipStart(name.clone(), None));
+                self.notate_event(None, None, duration)?;
+            },
+            Clip::Drums(ref name, ref pattern) => {
+                // drums are not written, but they take their time so that
+                // the clips after them stay in place
+                self.items.push(NotationItem::Omitted(name.clone()));
+                self.notate_event(None, None, pattern.get_length())?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Names the chord formed by the notes of an event, if they form a known
+    /// chord. The notes are written as they are spelled in the clip.
+    fn notate_notes(&mut self, notes: &[Note], duration: Duration)
+        -> Result<(), String> {
+        let chord = if notes.len() > 2 {
+            Chord::identify(notes)
+        } else {
+            None
+        };
+        self.notate_event(Some(notes.to_vec()), chord, duration)
+    }
+
+    fn notate_event(&mut self, notes: Option<Vec<Note>>, chord: Option<Chord>,
+        duration: Duration) -> Result<(), String> {
+        let mut values: Vec<Option<NoteValue>> = Vec::new(); // None: bar line
+        let mut remaining = duration;
+        while !remaining.is_zero() {
+            let length = remaining.min(self.bar_length - self.position);
+            values.extend(split_duration(length)?.into_iter().map(Some));
+            remaining -= length;
+            self.position += length;
+            if self.position >= self.bar_length {
+                self.position = Time::zero();
+                values.push(None);
+            }
+        }
+        // only notes are tied, rests are simply repeated
+        let tied = notes.is_some();
+        let num_pieces = values.iter().filter(|value| value.is_some()).count();
+        let mut piece = 0;
+        for value in values.into_iter() {
+            match value {
+                Some(value) => {
+                    self.items.push(NotationItem::Event {notes: notes.clone(),
+                        value, tie_start: tied && piece + 1 < num_pieces,
+                        tie_stop: tied && piece > 0,
+                        chord: if piece == 0 { chord } else { None }});
+                    piece += 1;
+                },
+                None => self.items.push(NotationItem::BarLine),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects the events of a clip while importing it from a notation format
+pub struct ClipBuilder {
+    pub name: String,
+    pub instrument: Option<String>,
+    pub melody: Vec<Option<Vec<Note>>>,
+    pub durations: Vec<Duration>,
+}
+
+impl ClipBuilder {
+    pub fn new(name: String) -> ClipBuilder {
+        ClipBuilder {name, instrument: None, melody: Vec::new(),
+            durations: Vec::new()}
+    }
+
+    /// Clips without any notes become empty clips. Clips whose instrument is
+    /// not in the instrument library are played by the default instrument.
+    pub fn build(self, default_instrument: &InstrumentHandle) -> Clip {
+        if self.melody.iter().all(|notes| notes.is_none()) {
+            Clip::Empty(self.name, self.durations.into_iter().sum())
+        } else {
+            let instrument = self.instrument.as_ref()
+                .and_then(|name| builtin_instrument(name))
+                .unwrap_or_else(|| default_instrument.clone());
+            Clip::Instrument(self.name, instrument, self.melody,
+                self.durations)
+        }
+    }
+}
+
+/// Adds an imported clip unless an identical clip with the same name exists
+/// (a clip used several times), renaming clips whose names are taken.
+/// Returns the name the clip is known by.
+pub fn add_clip(clips: &mut Vec<Clip>, clip: Clip) -> String {
+    if clips.contains(&clip) {
+        return clip.get_name();
+    }
+    let base_name = clip.get_name();
+    let mut name = base_name.clone();
+    let mut suffix = 2;
+    while clips.iter().any(|other| other.get_name() == name) {
+        name = format!("{}_{}", base_name, suffix);
+        suffix += 1;
+    }
+    let clip = match clip {
+        Clip::Instrument(_, instrument, melody, durations) =>
+            Clip::Instrument(name.clone(), instrument, melody, durations),
+        Clip::File(_, path) => Clip::File(name.clone(), path),
+        Clip::Empty(_, duration) => Clip::Empty(name.clone(), duration),
+        Clip::Drums(_, pattern) => Clip::Drums(name.clone(), pattern),
+    };
+    clips.push(clip);
+    name
+}
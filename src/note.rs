@@ -1,42 +1,52 @@
 /// Defines the note abstraction, which serves as a basis for producing sounds
 /// from an instrument. Notes are also important for developing and defining
 /// other abstractions such as scales and chords.
-use std::collections::HashMap;
+use duration::*;
 use std::cmp::Ordering;
+#[cfg(feature = "serialize")]
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
 pub const MIDI_NUM: u8 = 128; // number of possible MIDI note values
 pub const NUM_TONES: u8 = 12;
+pub const NUM_LETTERS: u8 = 7;
+pub const CONCERT_A: f64 = 440.0; // standard frequency of A4 in hertz
+pub const A4_MIDI: i8 = 69;
 
-#[derive(PartialEq, Eq, Debug, Hash, Clone, Copy)]
+/// A note is spelled with a letter and an accidental, so F#4 and Gb4 are
+/// written differently but share a pitch. Notes compare (and hash) by pitch,
+/// so F#4 == Gb4, and same_spelling tells them apart. The octave is the
+/// octave of the letter in scientific pitch notation, so B#3 sounds the same
+/// as C4. Notes may also be detuned by a number of cents for microtonal music
+/// (see tuning.rs).
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize),
+    serde(into = "String", try_from = "String"))]
 pub struct Note {
-    name: Name,
+    letter: Letter,
+    accidental: Accidental,
     octave: i8,
+    cents: i16,
 }
 
 lazy_static! {
-    // mappings from midi numbers to (tone, octave) (and the reverse direction)
-    static ref MIDI_MAPS: (Vec<Note>, HashMap<Note, i8>) = {
-        let notes: Vec<Name> = vec![Name::C, Name::Db, Name::D, Name::Eb, 
-        Name::E, Name::F, Name::Gb, Name::G, Name::Ab, Name::A, Name::Bb, 
-        Name::B];
-        let mut midi_to_note: Vec<Note> = Vec::new();
-        let mut note_to_midi: HashMap<Note, i8> = HashMap::new();
-        for i in 0..MIDI_NUM {
-            // octave adjusted so middle C (midi num 60) is C4
-            // in order to be consistent with scientific pitch notation
-            let note_name: Name = notes[(i % NUM_TONES) as usize];
-            let note_octave: i8 = ((i / NUM_TONES) as i8) - 1;
-            let new_note = Note {name: note_name, octave: note_octave};
-            midi_to_note.push(new_note);
-            note_to_midi.insert(new_note, i as i8);
-        }
-        (midi_to_note, note_to_midi)
-    };
+    // default spellings of the twelve pitch classes, starting from C. These
+    // are used whenever a note is produced from a raw pitch (e.g. by counting
+    // half steps) and there is no key or chord context to spell it from.
+    static ref DEFAULT_SPELLINGS: Vec<(Letter, Accidental)> = vec![
+        (Letter::C, Accidental::Natural), (Letter::D, Accidental::Flat),
+        (Letter::D, Accidental::Natural), (Letter::E, Accidental::Flat),
+        (Letter::E, Accidental::Natural), (Letter::F, Accidental::Natural),
+        (Letter::G, Accidental::Flat), (Letter::G, Accidental::Natural),
+        (Letter::A, Accidental::Flat), (Letter::A, Accidental::Natural),
+        (Letter::B, Accidental::Flat), (Letter::B, Accidental::Natural)];
 }
 
-/// Names of notes, sharps are currently not included for sake of simplicity as
-/// wella as eliminiting redundancy.
+/// Names of pitch classes. Sharps are not included for sake of simplicity as
+/// well as eliminating redundancy; spelled notes (see Letter and Accidental)
+/// are used wherever the distinction between e.g. F# and Gb matters.
 /// Most Jazz-theoretic formulas are based on flats e.g. Mixolydian scales are
 /// just major scales with a flat 7.
 // usage of custom_derive inspired by
@@ -44,56 +54,247 @@ lazy_static! {
 // can-i-convert-a-string-to-enum-without-macros-in-rust
 custom_derive! {
     #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, EnumFromStr)]
+    #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
     pub enum Name {C, Db, D, Eb, E, F, Gb, G, Ab, A, Bb, B}
 }
 
-/// Whole note, half note, quarter note, eighth note, sixteenth note
+custom_derive! {
+    /// The seven natural note letters
+    #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, EnumFromStr)]
+    pub enum Letter {C, D, E, F, G, A, B}
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum Accidental {
+    DoubleFlat,
+    Flat,
+    Natural,
+    Sharp,
+    DoubleSharp
+}
+
+pub const LETTERS: [Letter; 7] = [Letter::C, Letter::D, Letter::E, Letter::F,
+    Letter::G, Letter::A, Letter::B];
+const NAMES: [Name; 12] = [Name::C, Name::Db, Name::D, Name::Eb, Name::E,
+    Name::F, Name::Gb, Name::G, Name::Ab, Name::A, Name::Bb, Name::B];
+
+impl Letter {
+    /// Position of the letter within an octave, starting from C
+    pub fn index(&self) -> u8 {
+        LETTERS.iter().position(|letter| letter == self).unwrap() as u8
+    }
+
+    /// Returns the letter that is a given number of letters above this one
+    /// (wrapping around at B)
+    pub fn add_steps(&self, num_steps: i8) -> Letter {
+        let index = (self.index() as i8 + num_steps)
+            .rem_euclid(NUM_LETTERS as i8);
+        LETTERS[index as usize]
+    }
+
+    /// Half steps from C to the natural note with this letter
+    pub fn half_steps(&self) -> i8 {
+        [0, 2, 4, 5, 7, 9, 11][self.index() as usize]
+    }
+}
+
+impl Accidental {
+    /// Returns the accidental that alters a natural note by the given number
+    /// of half steps, if there is one.
+    pub fn from_half_steps(num_half_steps: i8) -> Option<Accidental> {
+        match num_half_steps {
+            -2 => Some(Accidental::DoubleFlat),
+            -1 => Some(Accidental::Flat),
+            0 => Some(Accidental::Natural),
+            1 => Some(Accidental::Sharp),
+            2 => Some(Accidental::DoubleSharp),
+            _ => None,
+        }
+    }
+
+    /// The number of half steps this accidental alters a natural note by
+    pub fn half_steps(&self) -> i8 {
+        match *self {
+            Accidental::DoubleFlat => -2,
+            Accidental::Flat => -1,
+            Accidental::Natural => 0,
+            Accidental::Sharp => 1,
+            Accidental::DoubleSharp => 2,
+        }
+    }
+}
+
+impl Name {
+    /// Position of the pitch class within an octave, starting from C
+    pub fn index(&self) -> u8 {
+        NAMES.iter().position(|name| name == self).unwrap() as u8
+    }
+}
+
+/// Whole note, half note, quarter note, eighth note, sixteenth note,
+/// thirty-second note (T) and sixty-fourth note (X)
 custom_derive! {
     #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, EnumFromStr)]
-    pub enum Beat {W, H, Q, E, S}
+    pub enum Beat {W, H, Q, E, S, T, X}
 }
 
+impl Beat {
+    /// Exact length of the note value
+    pub fn get_duration(&self) -> Duration {
+        let denominator = match *self {
+            Beat::W => 1,
+            Beat::H => 2,
+            Beat::Q => 4,
+            Beat::E => 8,
+            Beat::S => 16,
+            Beat::T => 32,
+            Beat::X => 64,
+        };
+        Duration::new(1, denominator)
+    }
 
+    /// Length of the note value in whole notes
+    pub fn get_length(&self) -> f64 {
+        self.get_duration().to_f64()
+    }
+}
+
+
+/// Things that sound as notes of an instrument clip. The notes keep their
+/// spelling and detuning, which tunings other than twelve-tone equal
+/// temperament tell apart.
 pub trait Playable {
-    fn play(&self) -> Vec<i8>;
+    fn play(&self) -> Vec<Note>;
 }
 
 impl Playable for Note {
-    fn play(&self) -> Vec<i8> {
-        vec![self.get_midi_value()]
+    fn play(&self) -> Vec<Note> {
+        vec![*self]
     }
 }
 
 /// When multiple notes are in a vector, they will be sounded at the same time
 /// as one unit.
 impl Playable for Vec<Note> {
-    fn play(&self) -> Vec<i8> {
-        self.iter()
-        .map(|note: &Note| note.get_midi_value())
-        .collect()
+    fn play(&self) -> Vec<Note> {
+        self.clone()
+    }
+}
+
+/// Notes are equal if they sound the same: enharmonic notes are equal, and
+/// detuned notes are equal to notes detuned by as many cents
+impl PartialEq for Note {
+    fn eq(&self, other: &Note) -> bool {
+        (self.pitch(), self.cents) == (other.pitch(), other.cents)
+    }
+}
+
+impl Eq for Note {}
+
+impl Hash for Note {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.pitch(), self.cents).hash(state);
+    }
+}
+
+/// Notes are ordered by pitch, then by detuning
+impl Ord for Note {
+    fn cmp(&self, other: &Note) -> Ordering {
+        (self.pitch(), self.cents).cmp(&(other.pitch(), other.cents))
     }
 }
 
 impl PartialOrd for Note {
     fn partial_cmp(&self, other: &Note) -> Option<Ordering> {
-        Some(self.get_midi_value().cmp(&other.get_midi_value()))
+        Some(self.cmp(other))
     }
 }
 
-// TODO: add macro for creating notes e.g. note!("C4")
 impl Note {
+    /// Creates a note from a pitch class, spelled the default way (flats)
     pub fn new(name: Name, octave: i8) -> Note {
-        Note {name, octave}
+        let (letter, accidental) = DEFAULT_SPELLINGS[name.index() as usize];
+        Note {letter, accidental, octave, cents: 0}
+    }
+
+    /// Creates a note with an explicit spelling e.g. F#4 or Bbb3
+    pub fn spelled(letter: Letter, accidental: Accidental, octave: i8) -> Note {
+        Note {letter, accidental, octave, cents: 0}
     }
 
+    /// Returns this note detuned by the given number of cents (hundredths of
+    /// an equal-tempered half step)
+    pub fn with_cents(&self, cents: i16) -> Note {
+        Note {cents, ..*self}
+    }
+
+    /// Returns the default spelling of the note with the given MIDI value
+    pub fn from_midi(midi_value: i8) -> Note {
+        assert!(midi_value >= 0, "MIDI values must lie in 0..127");
+        let pitch_class = (midi_value as u8 % NUM_TONES) as usize;
+        Note::new(NAMES[pitch_class], (midi_value as u8 / NUM_TONES) as i8 - 1)
+    }
+
+    /// Returns the pitch class of this note
     pub fn get_name(&self) -> Name {
-        self.name
+        let pitch_class = (self.letter.half_steps()
+            + self.accidental.half_steps()).rem_euclid(NUM_TONES as i8);
+        NAMES[pitch_class as usize]
+    }
+
+    pub fn get_letter(&self) -> Letter {
+        self.letter
+    }
+
+    pub fn get_accidental(&self) -> Accidental {
+        self.accidental
     }
 
     pub fn get_octave(&self) -> i8 {
         self.octave
     }
 
+    pub fn get_cents(&self) -> i16 {
+        self.cents
+    }
+
+    /// Returns the number of letters between C-1 and this note, which is the
+    /// diatonic counterpart of the MIDI value.
+    pub fn diatonic_index(&self) -> i16 {
+        (self.octave as i16 + 1) * (NUM_LETTERS as i16)
+            + self.letter.index() as i16
+    }
+
+    /// Two notes are enharmonic if they sound the same but may be spelled
+    /// differently e.g. F#4 and Gb4
+    pub fn is_enharmonic(&self, other: &Note) -> bool {
+        self.get_midi_value() == other.get_midi_value()
+    }
+
+    /// True if two notes are written the same, with the same letter,
+    /// accidental, octave and cents e.g. F#4 and F#4 but not F#4 and Gb4
+    pub fn same_spelling(&self, other: &Note) -> bool {
+        (self.letter, self.accidental, self.octave, self.cents) ==
+            (other.letter, other.accidental, other.octave, other.cents)
+    }
+
+    /// Returns the note with the given letter that sounds the same as this
+    /// one, if it can be written with at most a double sharp or double flat.
+    pub fn respell(&self, letter: Letter) -> Option<Note> {
+        let midi = self.get_midi_value() as i16;
+        // try the octaves on either side, so that e.g. C4 respells to B#3
+        for octave in &[self.octave - 1, self.octave, self.octave + 1] {
+            let natural = (*octave as i16 + 1) * (NUM_TONES as i16)
+                + letter.half_steps() as i16;
+            if let Some(accidental) = Accidental::from_half_steps(
+                (midi - natural) as i8) {
+                return Some(Note {letter, accidental, octave: *octave,
+                    cents: self.cents});
+            }
+        }
+        None
+    }
+
     /// Returns the note that is one half step above this one
     pub fn sharp(&self) -> Note {
         self.add_half_steps(1)
@@ -104,20 +305,51 @@ impl Note {
         self.add_half_steps(-1)
     }
 
-    /// Returns the MIDI value associated with this particular note
+    /// Returns the MIDI value associated with this particular note. Cents are
+    /// ignored, so detuned notes keep the MIDI value of the note they detune.
     pub fn get_midi_value(&self) -> i8 {
-        let (_, ref note_to_midi) = *MIDI_MAPS;
-        *(note_to_midi.get(&self).unwrap())
+        let midi = self.pitch();
+        assert!(midi >= 0 && midi < MIDI_NUM as i16,
+            "Note lies outside of the MIDI range");
+        midi as i8
+    }
+
+    /// The MIDI value of the note, even if it lies outside of the MIDI range
+    fn pitch(&self) -> i16 {
+        (self.octave as i16 + 1) * (NUM_TONES as i16)
+            + self.letter.half_steps() as i16
+            + self.accidental.half_steps() as i16
+    }
+
+    /// Returns the frequency of this note in twelve-tone equal temperament,
+    /// given the frequency of A4 (the concert pitch) e.g. 440, 432 or 415.
+    pub fn frequency(&self, a4_hz: f64) -> f64 {
+        let half_steps = (self.get_midi_value() - A4_MIDI) as f64
+            + self.cents as f64 / 100.0;
+        a4_hz * 2.0_f64.powf(half_steps / NUM_TONES as f64)
+    }
+
+    /// Returns the (default spelled) note closest to a frequency given the
+    /// frequency of A4, along with the deviation of the frequency from that
+    /// note in cents (between -50 and 50). Frequencies that are not positive
+    /// or that lie outside of the MIDI range give None.
+    pub fn from_frequency(freq: f64, a4_hz: f64) -> Option<(Note, f64)> {
+        let exact = A4_MIDI as f64 + NUM_TONES as f64 * (freq / a4_hz).log2();
+        let midi_value = exact.round();
+        // NaN fails both comparisons
+        if !(midi_value >= 0.0 && midi_value < MIDI_NUM as f64) {
+            return None;
+        }
+        Some((Note::from_midi(midi_value as i8),
+            (exact - midi_value) * 100.0))
     }
 
     /// Adds a given number of half steps (potentially negative) to this note
-    /// and returns that note.
+    /// and returns that note, spelled the default way.
     // TODO: add bounds checking (assertions should work)
     pub fn add_half_steps(&self, num_half_steps: i8) -> Note {
-        let (ref midi_to_note, ref note_to_midi) = *MIDI_MAPS;
-        let new_midi_num = note_to_midi.get(&self).unwrap() 
-            + num_half_steps;
-        midi_to_note[new_midi_num as usize]
+        Note::from_midi(self.get_midi_value() + num_half_steps)
+            .with_cents(self.cents)
     }
 
     /// Adds a certain number of whole steps to the given note
@@ -126,32 +358,104 @@ impl Note {
     }
 }
 
-// Inspiration from this example:
-// https://stackoverflow.com/questions/36508580/
-// how-to-convert-a-string-to-an-enum
+impl fmt::Display for Accidental {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match *self {
+            Accidental::DoubleFlat => "bb",
+            Accidental::Flat => "b",
+            Accidental::Natural => "",
+            Accidental::Sharp => "#",
+            Accidental::DoubleSharp => "##",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Notes are displayed in scientific pitch notation e.g. C4, F#4, Bbb3, with
+/// any detuning appended in cents e.g. E4-14c
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}{}{}", self.letter, self.accidental, self.octave)?;
+        if self.cents != 0 {
+            write!(f, "{:+}c", self.cents)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses notes in scientific pitch notation: a letter (in either case), any
+/// accidentals ("#" or "s" for sharps, "b" for flats, "x" for a double sharp,
+/// at most two in either direction), an octave from -1 to 9, and an optional
+/// detuning in cents as produced by Display e.g. "C4", "f#4", "Bbb3", "C-1",
+/// "G9", "Fx4", "E4-14c". Notes outside of the MIDI range are rejected.
 impl FromStr for Note {
-    type Err = &'static str;
+    type Err = String;
     fn from_str(note: &str) -> Result<Self, Self::Err> {
-        assert!(note.len() > 0);
-        let name_octave: Vec<char> = note.chars().collect();
-        let name: Name = name_octave[0].to_string().parse().unwrap();
-        match name_octave.len() {
-            2 => {
-                let octave: i8 = name_octave[1].to_string().parse().unwrap();
-                Ok(Note::new(name, octave))
-            },
-            3 => {
-                let octave: i8 = name_octave[2].to_string().parse().unwrap();
-                match name_octave[1] {
-                    's' => Ok(Note::new(name, octave).sharp()),
-                    'b' => Ok(Note::new(name, octave).flat()),
-                    _ => Err("Invalid input for note"),
-
-                }
-            },
-            _ => Err("Invalid input for note"),
+        let error = |reason: &str| format!("Invalid note \"{}\": {}", note,
+            reason);
+        let chars: Vec<char> = note.chars().collect();
+        let letter: Letter = match chars.first() {
+            Some(c) => c.to_ascii_uppercase().to_string().parse()
+                .map_err(|_| error("expected a letter from A to G"))?,
+            None => return Err(error("expected a letter from A to G")),
+        };
+        let mut pos = 1;
+        let mut alteration: i8 = 0;
+        while pos < chars.len() {
+            match chars[pos] {
+                '#' | 's' => alteration += 1,
+                'x' => alteration += 2,
+                'b' => alteration -= 1,
+                _ => break,
+            }
+            pos += 1;
+        }
+        let accidental = Accidental::from_half_steps(alteration)
+            .ok_or_else(|| error("at most a double sharp or flat is allowed"))?;
+        // the octave may be negative, the cents are always signed
+        let octave_start = pos;
+        if pos < chars.len() && chars[pos] == '-' {
+            pos += 1;
+        }
+        while pos < chars.len() && chars[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        let octave_str: String = chars[octave_start..pos].iter().collect();
+        let octave: i8 = octave_str.parse()
+            .map_err(|_| error("expected an octave from -1 to 9"))?;
+        let mut cents: i16 = 0;
+        if pos < chars.len() {
+            let cents_str: String = chars[pos..].iter().collect();
+            let valid_cents = (cents_str.starts_with('+') ||
+                cents_str.starts_with('-')) && cents_str.ends_with('c');
+            if !valid_cents {
+                return Err(error("unexpected characters after octave"));
+            }
+            cents = cents_str[..cents_str.len() - 1].parse()
+                .map_err(|_| error("expected cents e.g. +14c"))?;
         }
+        let midi = (octave as i16 + 1) * (NUM_TONES as i16)
+            + letter.half_steps() as i16 + accidental.half_steps() as i16;
+        if midi < 0 || midi >= MIDI_NUM as i16 {
+            return Err(error("outside of the MIDI range (C-1 to G9)"));
+        }
+        Ok(Note::spelled(letter, accidental, octave).with_cents(cents))
     }
 }
 
+/// Notes are saved in project files as their names e.g. "F#4" or "E4-14c"
+#[cfg(feature = "serialize")]
+impl From<Note> for String {
+    fn from(note: Note) -> String {
+        note.to_string()
+    }
+}
 
+/// Notes are read from project files by parsing their names
+#[cfg(feature = "serialize")]
+impl TryFrom<String> for Note {
+    type Error = String;
+    fn try_from(note: String) -> Result<Note, String> {
+        note.parse()
+    }
+}
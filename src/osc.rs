@@ -0,0 +1,408 @@
+/// Open Sound Control over UDP, for playing arrangements live. A Sclang
+/// connection sends the code generated by arrangement_to_super_collider() to
+/// a running sclang, which evaluates it once it has run SCLANG_LISTENER. A
+/// Scsynth connection talks straight to the server instead, starting a synth
+/// for every note (/s_new) at the time it should sound; this needs the
+/// SynthDefs to be loaded on the server already, e.g. by sclang.
+/// Spec: http://opensoundcontrol.org/spec-1_0
+use arrangement::*;
+use clip::*;
+use translate::*;
+use std::convert::TryInto;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default ports of sclang and scsynth
+pub const SCLANG_PORT: u16 = 57120;
+pub const SCSYNTH_PORT: u16 = 57110;
+/// Seconds between 1900 (the OSC epoch) and 1970 (the Unix epoch)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+/// A time tag meaning "now"
+pub const IMMEDIATELY: u64 = 1;
+/// Group that synths are added to, the default group of scsynth
+const DEFAULT_GROUP: i32 = 1;
+/// Node ids below this are left for sclang
+const FIRST_NODE: i32 = 10000;
+/// Amplitude of notes, the default of a SuperCollider Pbind
+pub const AMPLITUDE: f32 = 0.1;
+
+/// Code to evaluate in sclang once so that it runs the code DAWPL sends
+pub const SCLANG_LISTENER: &str = "(
+OSCdef(\\dawplInterpret, {arg msg; msg[1].asString.interpret},
+    '/dawpl/interpret');
+OSCdef(\\dawplStop, {CmdPeriod.run}, '/dawpl/stop');
+)";
+
+/// An argument of an OSC message
+#[derive(Debug, PartialEq, Clone)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// A message, or a bundle of packets to act on at the time given by an NTP
+/// time tag
+#[derive(Debug, PartialEq, Clone)]
+pub enum OscPacket {
+    Message(OscMessage),
+    Bundle(u64, Vec<OscPacket>),
+}
+
+impl OscMessage {
+    pub fn new(address: &str, args: Vec<OscArg>) -> OscMessage {
+        OscMessage {address: address.into(), args}
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        write_string(&mut bytes, &self.address);
+        let mut type_tags: String = ",".into();
+        for arg in self.args.iter() {
+            type_tags.push(match *arg {
+                OscArg::Int(_) => 'i',
+                OscArg::Float(_) => 'f',
+                OscArg::Str(_) => 's',
+                OscArg::Blob(_) => 'b',
+            });
+        }
+        write_string(&mut bytes, &type_tags);
+        for arg in self.args.iter() {
+            match *arg {
+                OscArg::Int(value) => bytes.extend(&value.to_be_bytes()),
+                OscArg::Float(value) => bytes.extend(&value.to_be_bytes()),
+                OscArg::Str(ref value) => write_string(&mut bytes, value),
+                OscArg::Blob(ref value) => {
+                    bytes.extend(&(value.len() as i32).to_be_bytes());
+                    bytes.extend(value);
+                    pad(&mut bytes);
+                },
+            }
+        }
+        bytes
+    }
+}
+
+impl OscPacket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            OscPacket::Message(ref message) => message.to_bytes(),
+            OscPacket::Bundle(time_tag, ref packets) => {
+                let mut bytes: Vec<u8> = Vec::new();
+                write_string(&mut bytes, "#bundle");
+                bytes.extend(&time_tag.to_be_bytes());
+                for packet in packets.iter() {
+                    let packet = packet.to_bytes();
+                    bytes.extend(&(packet.len() as i32).to_be_bytes());
+                    bytes.extend(packet);
+                }
+                bytes
+            },
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<OscPacket, String> {
+        let mut reader = Reader {bytes, position: 0};
+        let address = reader.string()?;
+        if address == "#bundle" {
+            let time_tag = u64::from_be_bytes(reader.take(8)?.try_into()
+                .unwrap());
+            let mut packets: Vec<OscPacket> = Vec::new();
+            while reader.position < bytes.len() {
+                let length = reader.int()?;
+                if length < 0 {
+                    return Err("Invalid OSC bundle element size".into());
+                }
+                let packet = reader.take(length as usize)?;
+                packets.push(OscPacket::parse(packet)?);
+            }
+            return Ok(OscPacket::Bundle(time_tag, packets));
+        }
+        if !address.starts_with('/') {
+            return Err(format!("Invalid OSC address \"{}\"", address));
+        }
+        let type_tags = if reader.position < bytes.len() {
+            reader.string()?
+        } else {
+            ",".into()
+        };
+        if !type_tags.starts_with(',') {
+            return Err(format!("Invalid OSC type tags \"{}\"", type_tags));
+        }
+        let mut args: Vec<OscArg> = Vec::new();
+        for tag in type_tags.chars().skip(1) {
+            args.push(match tag {
+                'i' => OscArg::Int(reader.int()?),
+                'f' => OscArg::Float(f32::from_be_bytes(reader.take(4)?
+                    .try_into().unwrap())),
+                's' => OscArg::Str(reader.string()?),
+                'b' => {
+                    let length = reader.int()?;
+                    if length < 0 {
+                        return Err("Invalid OSC blob size".into());
+                    }
+                    let blob = reader.take(length as usize)?.to_vec();
+                    reader.align();
+                    OscArg::Blob(blob)
+                },
+                _ => return Err(format!("Unsupported OSC type tag '{}'",
+                    tag)),
+            });
+        }
+        Ok(OscPacket::Message(OscMessage {address, args}))
+    }
+}
+
+/// Reads the parts of an OSC packet
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < length {
+            return Err("OSC packet ends too soon".into());
+        }
+        let taken = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(taken)
+    }
+
+    fn int(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.bytes[self.position..];
+        let length = rest.iter().position(|byte| *byte == 0)
+            .ok_or("OSC string is not terminated")?;
+        let string = String::from_utf8(rest[..length].to_vec())
+            .map_err(|_| "OSC string is not UTF-8")?;
+        self.position += length + 1;
+        self.align();
+        Ok(string)
+    }
+
+    /// Skips to the next multiple of four bytes
+    fn align(&mut self) {
+        self.position = ((self.position + 3) / 4 * 4).min(self.bytes.len());
+    }
+}
+
+/// Writes a string with a terminating null, padded to a multiple of four
+/// bytes
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend(string.as_bytes());
+    bytes.push(0);
+    pad(bytes);
+}
+
+fn pad(bytes: &mut Vec<u8>) {
+    while bytes.len() % 4 != 0 {
+        bytes.push(0);
+    }
+}
+
+/// Returns the OSC (NTP) time tag of a time: seconds since 1900 in the top
+/// 32 bits, and the fraction of a second in the bottom 32 bits
+pub fn time_tag(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+    let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = (since_epoch.subsec_nanos() as u64 * (1 << 32))
+        / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+/// Sends OSC packets over UDP from a local port to one destination
+pub struct OscClient {
+    socket: UdpSocket,
+}
+
+impl OscClient {
+    /// Opens a client that sends to the given address e.g. "127.0.0.1:57110"
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<OscClient> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        Ok(OscClient {socket})
+    }
+
+    pub fn send(&self, packet: &OscPacket) -> io::Result<()> {
+        self.socket.send(&packet.to_bytes()).map(|_| ())
+    }
+
+    /// Waits for a reply, e.g. to /status or /notify
+    pub fn receive(&self, timeout: Duration) -> io::Result<OscPacket> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        let mut buffer = [0u8; 65536];
+        let length = self.socket.recv(&mut buffer)?;
+        OscPacket::parse(&buffer[..length])
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData,
+                message))
+    }
+}
+
+/// A connection to sclang, which evaluates the code it is sent once it has
+/// run SCLANG_LISTENER
+pub struct Sclang {
+    client: OscClient,
+}
+
+impl Sclang {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Sclang> {
+        Ok(Sclang {client: OscClient::connect(address)?})
+    }
+
+    /// Evaluates SuperCollider code. Code must fit in one UDP packet (about
+    /// 64KB).
+    pub fn interpret(&self, code: &str) -> io::Result<()> {
+        self.client.send(&OscPacket::Message(OscMessage::new(
+            "/dawpl/interpret", vec![OscArg::Str(code.into())])))
+    }
+
+    /// Plays an arrangement by sending the code it translates to
+    pub fn play(&self, arrangement: &Arrangement) -> io::Result<()> {
+        self.interpret(&arrangement_to_super_collider(arrangement))
+    }
+
+    /// Stops everything that is playing, as Cmd-. does in the IDE
+    pub fn stop(&self) -> io::Result<()> {
+        self.client.send(&OscPacket::Message(OscMessage::new("/dawpl/stop",
+            Vec::new())))
+    }
+}
+
+/// A connection straight to scsynth, which plays synths node by node
+pub struct Scsynth {
+    client: OscClient,
+    next_node: i32,
+}
+
+impl Scsynth {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Scsynth> {
+        Ok(Scsynth {client: OscClient::connect(address)?,
+            next_node: FIRST_NODE})
+    }
+
+    pub fn get_client(&self) -> &OscClient {
+        &self.client
+    }
+
+    /// Returns the /s_new message that starts a synth at the tail of the
+    /// default group, with the given controls e.g. [("freq", 440.0)]
+    fn synth_message(&mut self, synth_def: &str, controls: &[(&str, f32)])
+        -> (i32, OscMessage) {
+        let node = self.next_node;
+        self.next_node += 1;
+        let mut args = vec![OscArg::Str(synth_def.into()), OscArg::Int(node),
+            OscArg::Int(1), OscArg::Int(DEFAULT_GROUP)];
+        args.extend(control_args(controls));
+        (node, OscMessage::new("/s_new", args))
+    }
+
+    /// Starts a synth at the given time, returning its node id
+    pub fn new_synth_at(&mut self, time: SystemTime, synth_def: &str,
+        controls: &[(&str, f32)]) -> io::Result<i32> {
+        let (node, message) = self.synth_message(synth_def, controls);
+        self.client.send(&OscPacket::Bundle(time_tag(time),
+            vec![OscPacket::Message(message)]))?;
+        Ok(node)
+    }
+
+    /// Starts a synth now, returning its node id
+    pub fn new_synth(&mut self, synth_def: &str, controls: &[(&str, f32)])
+        -> io::Result<i32> {
+        let (node, message) = self.synth_message(synth_def, controls);
+        self.client.send(&OscPacket::Message(message))?;
+        Ok(node)
+    }
+
+    /// Changes controls of a running synth
+    pub fn set(&self, node: i32, controls: &[(&str, f32)]) -> io::Result<()> {
+        self.client.send(&OscPacket::Message(set_message(node, controls)))
+    }
+
+    /// Changes controls of a synth at the given time
+    pub fn set_at(&self, time: SystemTime, node: i32, controls: &[(&str, f32)])
+        -> io::Result<()> {
+        self.client.send(&OscPacket::Bundle(time_tag(time),
+            vec![OscPacket::Message(set_message(node, controls))]))
+    }
+
+    pub fn free(&self, node: i32) -> io::Result<()> {
+        self.client.send(&OscPacket::Message(OscMessage::new("/n_free",
+            vec![OscArg::Int(node)])))
+    }
+
+    /// Frees every synth in the default group
+    pub fn stop(&self) -> io::Result<()> {
+        self.client.send(&OscPacket::Message(OscMessage::new("/g_freeAll",
+            vec![OscArg::Int(DEFAULT_GROUP)])))
+    }
+
+    /// Plays every note of an arrangement, starting after the given latency.
+    /// Each note is sent as a bundle time tagged with when it should start,
+    /// so the server plays them on time however early they arrive, and notes
+    /// of instruments that hold their notes are released at their end. Notes
+    /// the tuning cannot play are left out. Returns the number of notes sent.
+    pub fn play(&mut self, arrangement: &Arrangement, latency: Duration)
+        -> io::Result<usize> {
+        let start = SystemTime::now() + latency;
+        let whole_note = arrangement.get_whole_note_seconds();
+        let tuning = arrangement.get_tuning();
+        let a4_hz = arrangement.get_concert_pitch();
+        let gated: Vec<String> = arrangement.get_clips_ref().iter()
+            .filter_map(|clip| match *clip {
+                Clip::Instrument(_, ref instrument, _, _)
+                    if instrument.get_instrument().is_gated() =>
+                    Some(instrument.get_name().clone()),
+                _ => None,
+            }).collect();
+        let mut sent = 0;
+        for note in arrangement.get_timed_notes().iter() {
+            let freq = match tuning.frequency(&note.pitch, a4_hz) {
+                Some(freq) => freq,
+                None => continue,
+            };
+            let time = start + Duration::from_secs_f64(note.start.to_f64()
+                * whole_note);
+            let amp = AMPLITUDE * note.velocity as f32 /
+                DEFAULT_VELOCITY as f32;
+            let node = self.new_synth_at(time, &note.instrument,
+                &[("freq", freq as f32), ("amp", amp)])?;
+            if gated.contains(&note.instrument) {
+                self.set_at(time + Duration::from_secs_f64(
+                    note.duration.to_f64() * whole_note), node,
+                    &[("gate", 0.0)])?;
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+/// Returns the /n_set message that changes controls of a synth
+fn set_message(node: i32, controls: &[(&str, f32)]) -> OscMessage {
+    let mut args = vec![OscArg::Int(node)];
+    args.extend(control_args(controls));
+    OscMessage::new("/n_set", args)
+}
+
+/// Returns the arguments that set controls, as name and value pairs
+fn control_args(controls: &[(&str, f32)]) -> Vec<OscArg> {
+    let mut args: Vec<OscArg> = Vec::new();
+    for &(name, value) in controls.iter() {
+        args.push(OscArg::Str(name.into()));
+        args.push(OscArg::Float(value));
+    }
+    args
+}
@@ -0,0 +1,92 @@
+/// Saving and loading arrangements as project files, available with the
+/// "serialize" feature. Projects can be written as JSON or TOML; both formats
+/// hold everything an arrangement contains (tracks, clips, tuning, concert
+/// pitch, key and time signature), so a project reads back to the same
+/// arrangement it was saved from. Notes are written in scientific pitch
+/// notation e.g. "F#4", and rests are written as "rest" since TOML has no
+/// null value.
+use arrangement::*;
+use std::fs;
+use std::path::Path;
+
+/// An item of a list in which some items may be missing (rests in a melody,
+/// unmapped keys in a keyboard mapping). Missing items are written as a
+/// marker string.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListItem<T> {
+    Present(T),
+    Missing(String),
+}
+
+pub fn write_list<T: Clone>(items: &[Option<T>], marker: &str)
+    -> Vec<ListItem<T>> {
+    items.iter().map(|item| match *item {
+        Some(ref item) => ListItem::Present(item.clone()),
+        None => ListItem::Missing(marker.into()),
+    }).collect()
+}
+
+pub fn read_list<T>(items: Vec<ListItem<T>>, marker: &str)
+    -> Result<Vec<Option<T>>, String> {
+    items.into_iter().map(|item| match item {
+        ListItem::Present(item) => Ok(Some(item)),
+        ListItem::Missing(ref text) if text == marker => Ok(None),
+        ListItem::Missing(text) => Err(format!("Expected \"{}\", found \"{}\"",
+            marker, text)),
+    }).collect()
+}
+
+pub fn arrangement_to_json(arrangement: &Arrangement)
+    -> Result<String, String> {
+    serde_json::to_string_pretty(arrangement)
+        .map_err(|error| format!("Could not write JSON: {}", error))
+}
+
+pub fn arrangement_from_json(json: &str) -> Result<Arrangement, String> {
+    serde_json::from_str(json)
+        .map_err(|error| format!("Invalid JSON project: {}", error))
+}
+
+pub fn arrangement_to_toml(arrangement: &Arrangement)
+    -> Result<String, String> {
+    toml::to_string(arrangement)
+        .map_err(|error| format!("Could not write TOML: {}", error))
+}
+
+pub fn arrangement_from_toml(toml: &str) -> Result<Arrangement, String> {
+    toml::from_str(toml)
+        .map_err(|error| format!("Invalid TOML project: {}", error))
+}
+
+/// Saves an arrangement as a project file, choosing the format from the
+/// extension of the path (.json or .toml)
+pub fn save_project(arrangement: &Arrangement, path: &Path)
+    -> Result<(), String> {
+    let contents = match extension(path)? {
+        "json" => arrangement_to_json(arrangement)?,
+        _ => arrangement_to_toml(arrangement)?,
+    };
+    fs::write(path, contents)
+        .map_err(|error| format!("Could not write {}: {}", path.display(),
+            error))
+}
+
+/// Loads an arrangement from a .json or .toml project file
+pub fn load_project(path: &Path) -> Result<Arrangement, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(),
+            error))?;
+    match extension(path)? {
+        "json" => arrangement_from_json(&contents),
+        _ => arrangement_from_toml(&contents),
+    }
+}
+
+fn extension(path: &Path) -> Result<&str, String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension @ "json") | Some(extension @ "toml") => Ok(extension),
+        _ => Err(format!("Project files must end in .json or .toml: {}",
+            path.display())),
+    }
+}
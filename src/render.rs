@@ -0,0 +1,402 @@
+/// Offline renderer that plays a DAWPL arrangement into audio samples and
+/// writes them as a WAV file, for listening to an arrangement without a
+/// running SuperCollider server. Every note is played as a sine wave, at the
+/// default amplitude of a SuperCollider Pbind, shaped by the amplitude
+/// envelope of its instrument (see synth.rs): envelopes with a release node
+/// hold it for as long as the note lasts and then release, like the gated
+/// instruments of the library, and other envelopes play through. Instruments
+/// whose envelope cannot be read are shaped like the sine instrument, with a
+/// short attack and a fixed release. Audio file clips are not rendered.
+/// Tracks are mixed as in SuperCollider (see effects.rs): clips, tracks,
+/// groups, returns and the master each have their effects applied (see
+/// dsp.rs), and tracks send to returns.
+/// Automation of volume, effect parameters and the amp parameter of synths is
+/// followed; pan is not, since the samples are mono.
+use arrangement::*;
+use automation::*;
+use clip::*;
+use dsp::*;
+use duration::*;
+use effects::*;
+use instruments::*;
+use std::f64::consts::PI;
+use synth::*;
+
+pub const SAMPLE_RATE: u32 = 44100;
+/// Envelope of the sine instrument in seconds
+const ATTACK: f64 = 0.005;
+const RELEASE: f64 = 0.3;
+/// Amplitude of every note
+const AMPLITUDE: f64 = 0.1;
+/// Seconds rendered after the end of the arrangement when it has effects,
+/// for reverb and echoes to fade out
+const EFFECTS_TAIL: f64 = 2.0;
+/// Parts of a whole note that the times of samples are rounded to when
+/// automation is read at them, far finer than a sample at any usual tempo
+const SAMPLE_TIME_PARTS: u64 = 1 << 20;
+
+/// Renders an arrangement as mono samples between -1 and 1. The samples run
+/// until the end of the arrangement or until the last note has faded out,
+/// whichever is later, and on for a while longer if there are effects.
+pub fn render_arrangement(arrangement: &Arrangement, sample_rate: u32)
+    -> Vec<f32> {
+    let rate = sample_rate as f64;
+    let whole_note = arrangement.get_whole_note_seconds();
+    let notes = arrangement.get_timed_notes();
+    let mut end = notes.iter().map(|note| note.start.to_f64() * whole_note +
+        note_envelope(arrangement, note).length(note.duration.to_f64() *
+        whole_note)).fold(arrangement.get_length().to_f64() * whole_note,
+        f64::max);
+    if !arrangement.get_effect_chains().is_empty() {
+        end += EFFECTS_TAIL;
+    }
+    let length = (end * rate).ceil() as usize;
+    let mut master = vec![0.0f64; length];
+    let mut groups: Vec<Vec<f64>> = vec![vec![0.0; length];
+        arrangement.get_groups().len()];
+    let mut returns: Vec<Vec<f64>> = vec![vec![0.0; length];
+        arrangement.get_returns().len()];
+    let time = |sample: usize| Time::new((sample as f64 / rate / whole_note *
+        SAMPLE_TIME_PARTS as f64).round() as u64, SAMPLE_TIME_PARTS);
+    let effects = |target: EffectTarget, samples: &mut [f64]| {
+        for (i, effect) in arrangement.get_effects(&target).iter()
+            .enumerate() {
+            let lanes: Vec<Option<&AutomationLane>> = (0..effect
+                .get_parameters().len()).map(|parameter| arrangement
+                .get_automation(&AutomationTarget::EffectParameter(
+                    target.clone(), i, parameter))).collect();
+            if lanes.iter().all(|lane| lane.is_none()) {
+                apply_effect(effect, samples, rate);
+                continue;
+            }
+            let values = effect.get_parameters();
+            apply_automated_effect(effect, samples, rate,
+                &|parameter, sample| lanes[parameter].map_or(
+                    values[parameter], |lane| lane.value_at(time(sample))));
+        }
+    };
+    for (index, track) in arrangement.get_tracks_ref().iter().enumerate() {
+        let name = track.get_name();
+        let amp = arrangement.get_automation(&AutomationTarget::SynthParameter(
+            name.clone(), "amp".into()));
+        let mut samples = vec![0.0f64; length];
+        // clips with effects are rendered on their own first
+        let mut clips: Vec<(&String, Vec<f64>)> = Vec::new();
+        for note in notes.iter().filter(|note| note.track == index) {
+            let target = EffectTarget::Clip(note.clip.clone());
+            if arrangement.get_effects(&target).is_empty() {
+                play_note(arrangement, note, amp, &mut samples, sample_rate);
+                continue;
+            }
+            let position = match clips.iter()
+                .position(|clip| clip.0 == &note.clip) {
+                Some(position) => position,
+                None => {
+                    clips.push((&note.clip, vec![0.0; length]));
+                    clips.len() - 1
+                },
+            };
+            play_note(arrangement, note, amp, &mut clips[position].1,
+                sample_rate);
+        }
+        for (clip, mut clip_samples) in clips.into_iter() {
+            effects(EffectTarget::Clip(clip.clone()), &mut clip_samples);
+            mix_into(&mut samples, &clip_samples, 1.0);
+        }
+        effects(EffectTarget::Track(name.clone()), &mut samples);
+        if let Some(volume) = arrangement.get_automation(
+            &AutomationTarget::Volume(name.clone())) {
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample *= volume.value_at(time(i));
+            }
+        }
+        for send in arrangement.get_sends(name).iter() {
+            if let Some(position) = arrangement.get_returns().iter()
+                .position(|other| other == &send.destination) {
+                mix_into(&mut returns[position], &samples, send.level);
+            }
+        }
+        let group = arrangement.get_group_of(name).and_then(|group|
+            arrangement.get_groups().iter().position(|other| other == group));
+        match group {
+            Some(position) => mix_into(&mut groups[position], &samples, 1.0),
+            None => mix_into(&mut master, &samples, 1.0),
+        }
+    }
+    for (group, mut samples) in arrangement.get_groups().iter()
+        .zip(groups) {
+        effects(EffectTarget::Group(group.name.clone()), &mut samples);
+        mix_into(&mut master, &samples, 1.0);
+    }
+    for (name, mut samples) in arrangement.get_returns().iter()
+        .zip(returns) {
+        effects(EffectTarget::Return(name.clone()), &mut samples);
+        mix_into(&mut master, &samples, 1.0);
+    }
+    effects(EffectTarget::Master, &mut master);
+    master.iter().map(|sample| sample.clamp(-1.0, 1.0) as f32).collect()
+}
+
+/// Adds a note to samples, at the amplitude of the amp lane of its track at
+/// the start of the note if there is one, scaled by its velocity, releasing
+/// it when it ends. Notes the tuning cannot play are left out.
+fn play_note(arrangement: &Arrangement, note: &TimedNote,
+    amp: Option<&AutomationLane>, samples: &mut [f64], sample_rate: u32) {
+    let freq = match arrangement.get_tuning().frequency(&note.pitch,
+        arrangement.get_concert_pitch()) {
+        Some(freq) => freq,
+        None => return,
+    };
+    let start = (note.start.to_f64() * arrangement.get_whole_note_seconds() *
+        sample_rate as f64).round() as usize;
+    let level = amp.map_or(1.0, |amp| amp.value_at(note.start) / AMPLITUDE) *
+        note.velocity as f64 / DEFAULT_VELOCITY as f64;
+    let mut voice = Voice::new(freq, sample_rate,
+        note_envelope(arrangement, note));
+    voice.release_after(note.duration.to_f64() *
+        arrangement.get_whole_note_seconds());
+    for sample in samples.iter_mut().skip(start) {
+        if voice.is_finished() {
+            break;
+        }
+        *sample += voice.next_sample() * level;
+    }
+}
+
+/// Adds samples at a level to others of the same length
+fn mix_into(samples: &mut [f64], other: &[f64], level: f64) {
+    for (sample, other) in samples.iter_mut().zip(other.iter()) {
+        *sample += other * level;
+    }
+}
+
+/// The envelope of the instrument that plays a note: the instrument of its
+/// instrument clip or of its drum lane, or the instrument of the library it
+/// names
+fn note_envelope(arrangement: &Arrangement, note: &TimedNote) -> Envelope {
+    let instrument = match arrangement.get_clip(&note.clip) {
+        Some(Clip::Instrument(_, instrument, _, _)) => Some(instrument.clone()),
+        Some(Clip::Drums(_, pattern)) => pattern.lanes.iter()
+            .map(|lane| &lane.instrument)
+            .find(|instrument| instrument.get_name() == &note.instrument)
+            .cloned(),
+        _ => None,
+    }.or_else(|| builtin_instrument(&note.instrument));
+    instrument.map_or_else(Envelope::default, |instrument|
+        Envelope::of(instrument.get_instrument()))
+}
+
+/// An amplitude envelope as SuperCollider's Env plays it: levels, the times
+/// in seconds between them, the curvature of each segment, and the level
+/// held until the note is released, if any
+#[derive(Debug, PartialEq, Clone)]
+pub struct Envelope {
+    levels: Vec<f64>,
+    times: Vec<f64>,
+    curves: Vec<f64>,
+    release_node: Option<usize>,
+}
+
+impl Envelope {
+    /// The first envelope of an instrument's output, with its parameters at
+    /// their defaults, or the envelope of the sine instrument if there is
+    /// none or it depends on more than constants and parameters
+    pub fn of(instrument: &Instrument) -> Envelope {
+        find_envelope(instrument.get_output())
+            .and_then(|envelope| match *envelope {
+                Signal::Envelope(ref levels, ref times, ref curves,
+                    release_node) => {
+                    let parameters = instrument.get_parameters();
+                    let values = |signals: &[Signal]| signals.iter()
+                        .map(|signal| constant(signal, parameters))
+                        .collect::<Option<Vec<f64>>>();
+                    Some(Envelope {levels: values(levels)?,
+                        times: values(times)?, curves: curves.clone(),
+                        release_node})
+                },
+                _ => None,
+            })
+            .filter(|envelope| !envelope.levels.is_empty() &&
+                envelope.times.len() + 1 == envelope.levels.len() &&
+                envelope.times.iter().all(|time| *time >= 0.0) &&
+                !matches!(envelope.release_node,
+                    Some(node) if node >= envelope.levels.len()))
+            .unwrap_or_default()
+    }
+
+    /// Seconds from the start of a note held for the given number of seconds
+    /// until the envelope has ended
+    pub fn length(&self, hold: f64) -> f64 {
+        match self.release_node {
+            Some(node) => hold + self.times[node..].iter().sum::<f64>(),
+            None => self.times.iter().sum(),
+        }
+    }
+
+    /// Level of the envelope the given number of seconds into a note, which
+    /// is released at the given time, if it has been
+    fn level(&self, time: f64, release: Option<f64>) -> f64 {
+        match (self.release_node, release) {
+            (Some(node), Some(release)) if time >= release => {
+                // the release starts from wherever the envelope has got to
+                let start = self.segments(release, 0, node, self.levels[0]);
+                self.segments(time - release, node, self.times.len(), start)
+            },
+            (Some(node), _) =>
+                self.segments(time, 0, node, self.levels[0]),
+            (None, _) =>
+                self.segments(time, 0, self.times.len(), self.levels[0]),
+        }
+    }
+
+    /// Level the given number of seconds into the segments from first to
+    /// last (exclusive), starting from the given level, holding the level
+    /// the last segment ends on
+    fn segments(&self, mut time: f64, first: usize, last: usize, start: f64)
+        -> f64 {
+        let mut level = start;
+        for segment in first..last {
+            let target = self.levels[segment + 1];
+            let length = self.times[segment];
+            if time < length {
+                let curve = self.curves.get(segment).cloned().unwrap_or(0.0);
+                return level + (target - level) *
+                    curve_fraction(time / length, curve);
+            }
+            time -= length;
+            level = target;
+        }
+        level
+    }
+}
+
+/// The envelope of the sine instrument
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope {levels: vec![0.0, 1.0, 0.0], times: vec![ATTACK, RELEASE],
+            curves: vec![1.0, -1.0], release_node: None}
+    }
+}
+
+/// How far along a segment of the given curvature the level is after the
+/// given fraction of its time, as Env computes it
+fn curve_fraction(fraction: f64, curve: f64) -> f64 {
+    if curve.abs() < 1e-3 {
+        fraction
+    } else {
+        (1.0 - (curve * fraction).exp()) / (1.0 - curve.exp())
+    }
+}
+
+/// The first envelope of a signal, looking through its inputs in order
+fn find_envelope(signal: &Signal) -> Option<&Signal> {
+    match *signal {
+        Signal::Envelope(..) => Some(signal),
+        Signal::UGen(_, _, ref inputs) | Signal::Array(ref inputs) =>
+            inputs.iter().filter_map(find_envelope).next(),
+        Signal::Binary(_, ref left, ref right) =>
+            find_envelope(left).or_else(|| find_envelope(right)),
+        Signal::Unary(_, ref input) => find_envelope(input),
+        _ => None,
+    }
+}
+
+/// The value of a signal made of constants and parameters at their defaults
+fn constant(signal: &Signal, parameters: &[(String, f64)]) -> Option<f64> {
+    match *signal {
+        Signal::Constant(value) => Some(value),
+        Signal::Parameter(ref name) => parameters.iter()
+            .find(|parameter| &parameter.0 == name)
+            .map(|parameter| parameter.1),
+        Signal::Binary(operator, ref left, ref right) => {
+            let (left, right) = (constant(left, parameters)?,
+                constant(right, parameters)?);
+            Some(match operator {
+                Operator::Add => left + right,
+                Operator::Sub => left - right,
+                Operator::Mul => left * right,
+                Operator::Div => left / right,
+            })
+        },
+        _ => None,
+    }
+}
+
+/// A note played as a sine wave, one sample at a time
+pub struct Voice {
+    freq: f64,
+    sample_rate: f64,
+    /// Number of samples played so far
+    position: u64,
+    envelope: Envelope,
+    /// Seconds into the note at which it is released, once known
+    release: Option<f64>,
+}
+
+impl Voice {
+    pub fn new(freq: f64, sample_rate: u32, envelope: Envelope) -> Voice {
+        Voice {freq, sample_rate: sample_rate as f64, position: 0, envelope,
+            release: None}
+    }
+
+    /// Releases the note the given number of seconds after it started
+    pub fn release_after(&mut self, seconds: f64) {
+        self.release = Some(seconds);
+    }
+
+    /// Releases the note now
+    pub fn release(&mut self) {
+        if self.release.is_none() {
+            self.release = Some(self.time());
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        let time = self.time();
+        self.position += 1;
+        AMPLITUDE * self.envelope.level(time, self.release) *
+            (2.0 * PI * self.freq * time).sin()
+    }
+
+    /// True once the note has faded out
+    pub fn is_finished(&self) -> bool {
+        match (self.envelope.release_node, self.release) {
+            (Some(_), None) => false,
+            (_, release) => self.time() >=
+                self.envelope.length(release.unwrap_or(0.0)),
+        }
+    }
+
+    /// Seconds since the note started
+    fn time(&self) -> f64 {
+        self.position as f64 / self.sample_rate
+    }
+}
+
+/// Writes mono samples as a 16 bit PCM WAV file
+pub fn samples_to_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_length = samples.len() as u32 * 2;
+    let mut wav: Vec<u8> = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend(&(36 + data_length).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(&16u32.to_le_bytes());
+    wav.extend(&1u16.to_le_bytes()); // PCM
+    wav.extend(&1u16.to_le_bytes()); // mono
+    wav.extend(&sample_rate.to_le_bytes());
+    wav.extend(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    wav.extend(&2u16.to_le_bytes()); // bytes per sample
+    wav.extend(&16u16.to_le_bytes()); // bits per sample
+    wav.extend(b"data");
+    wav.extend(&data_length.to_le_bytes());
+    for sample in samples.iter() {
+        wav.extend(&((sample.clamp(-1.0, 1.0) * 32767.0).round() as i16)
+            .to_le_bytes());
+    }
+    wav
+}
+
+pub fn arrangement_to_wav(arrangement: &Arrangement, sample_rate: u32)
+    -> Vec<u8> {
+    samples_to_wav(&render_arrangement(arrangement, sample_rate), sample_rate)
+}
@@ -0,0 +1,172 @@
+/// Rhythm generators for algorithmic composition. A rhythm is a row of steps
+/// of equal length, each of which is an onset (a note starts) or not. Rhythms
+/// can be made from Euclidean distributions, from strings of steps, at random
+/// or from well known timelines such as the clave, and turned into the notes
+/// and durations of an instrument clip:
+///
+/// ```text
+/// let tresillo = Rhythm::euclidean(3, 8, 0, Beat::E.get_duration());
+/// let clip = Clip::Instrument("bass".into(), bass(),
+///     tresillo.melody(&play!(n!(C2), n!(G2))), tresillo.durations());
+/// ```
+use duration::*;
+use note::*;
+use random::*;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rhythm {
+    /// True for the steps on which a note starts
+    pub onsets: Vec<bool>,
+    /// Length of a step
+    pub step: Duration,
+}
+
+impl Rhythm {
+    pub fn new(onsets: Vec<bool>, step: Duration) -> Rhythm {
+        Rhythm {onsets, step}
+    }
+
+    /// The Euclidean rhythm E(hits, steps): hits spread as evenly as they can
+    /// be over the steps, as found by Bjorklund's algorithm, rotated to start
+    /// from the given step e.g. E(3, 8) is the tresillo "x..x..x."
+    pub fn euclidean(hits: usize, steps: usize, rotation: usize,
+        step: Duration) -> Rhythm {
+        let hits = hits.min(steps);
+        // groups of steps are paired with the groups left over until at most
+        // one group is left over
+        let mut groups: Vec<Vec<bool>> = vec![vec![true]; hits];
+        let mut remainders: Vec<Vec<bool>> = vec![vec![false]; steps - hits];
+        while remainders.len() > 1 && !groups.is_empty() {
+            let count = groups.len().min(remainders.len());
+            let left_over = if groups.len() > count {
+                groups[count..].to_vec()
+            } else {
+                remainders[count..].to_vec()
+            };
+            groups.truncate(count);
+            for (group, remainder) in groups.iter_mut()
+                .zip(remainders) {
+                group.extend(remainder);
+            }
+            remainders = left_over;
+        }
+        let onsets: Vec<bool> = groups.into_iter().chain(remainders)
+            .flatten().collect();
+        Rhythm::new(onsets, step).rotate(rotation)
+    }
+
+    /// Reads a rhythm from a string with a character per step: 1 or x for
+    /// an onset, 0, . or - for none. Spaces and bar lines (|) are ignored.
+    pub fn from_binary(steps: &str, step: Duration)
+        -> Result<Rhythm, String> {
+        let mut onsets: Vec<bool> = Vec::new();
+        for character in steps.chars() {
+            match character {
+                '1' | 'x' => onsets.push(true),
+                '0' | '.' | '-' => onsets.push(false),
+                '|' => (),
+                character if character.is_whitespace() => (),
+                character => return Err(format!("Unknown step '{}' in \
+                    \"{}\"", character, steps)),
+            }
+        }
+        Ok(Rhythm::new(onsets, step))
+    }
+
+    /// A rhythm whose steps are onsets with the given probability (the
+    /// density, from 0 to 1), the same for the same seed
+    pub fn random(steps: usize, density: f64, seed: u64, step: Duration)
+        -> Rhythm {
+        let mut random = Random::new(seed);
+        Rhythm::new((0..steps).map(|_| random.chance(density)).collect(),
+            step)
+    }
+
+    /// The 3-2 son clave over sixteen steps: "x..x..x...x.x..."
+    pub fn son_clave(step: Duration) -> Rhythm {
+        Rhythm::preset("x..x..x...x.x...", step)
+    }
+
+    /// The 3-2 rumba clave over sixteen steps: "x..x...x..x.x..."
+    pub fn rumba_clave(step: Duration) -> Rhythm {
+        Rhythm::preset("x..x...x..x.x...", step)
+    }
+
+    /// The tresillo over eight steps: "x..x..x."
+    pub fn tresillo(step: Duration) -> Rhythm {
+        Rhythm::preset("x..x..x.", step)
+    }
+
+    /// The cinquillo over eight steps: "x.xx.xx."
+    pub fn cinquillo(step: Duration) -> Rhythm {
+        Rhythm::preset("x.xx.xx.", step)
+    }
+
+    fn preset(steps: &str, step: Duration) -> Rhythm {
+        Rhythm::new(steps.chars().map(|character| character == 'x')
+            .collect(), step)
+    }
+
+    /// The rhythm started from the given step, with the steps before it
+    /// moved to the end
+    pub fn rotate(mut self, rotation: usize) -> Rhythm {
+        if !self.onsets.is_empty() {
+            let rotation = rotation % self.onsets.len();
+            self.onsets.rotate_left(rotation);
+        }
+        self
+    }
+
+    pub fn get_length(&self) -> Duration {
+        self.step * self.onsets.len() as u64
+    }
+
+    /// Durations of the notes of the rhythm, each lasting until the next
+    /// onset or the end of the rhythm, after a rest until the first onset if
+    /// the rhythm does not start with one. They go with the notes given by
+    /// melody().
+    pub fn durations(&self) -> Vec<Duration> {
+        let mut durations: Vec<Duration> = Vec::new();
+        for onset in self.onsets.iter() {
+            // steps without an onset lengthen the note (or the leading
+            // rest) before them
+            match durations.last_mut() {
+                Some(duration) if !onset => *duration += self.step,
+                _ => durations.push(self.step),
+            }
+        }
+        durations
+    }
+
+    /// The given notes played in turn on the onsets of the rhythm (starting
+    /// over when they run out), with a rest first if the rhythm does not
+    /// start with an onset. They go with the durations given by durations().
+    pub fn melody(&self, notes: &[Option<Vec<Note>>])
+        -> Vec<Option<Vec<Note>>> {
+        let mut melody: Vec<Option<Vec<Note>>> = Vec::new();
+        if self.onsets.first() == Some(&false) {
+            melody.push(None);
+        }
+        let count = self.onsets.iter().filter(|onset| **onset).count();
+        melody.extend(notes.iter().cycle().take(count).cloned());
+        melody
+    }
+
+    /// A duration of one step for every step of the rhythm, which go with
+    /// the notes given by step_melody()
+    pub fn step_durations(&self) -> Vec<Duration> {
+        vec![self.step; self.onsets.len()]
+    }
+
+    /// The rhythm as a rest mask: the given notes played in turn on the
+    /// onsets, each lasting a step, and rests on the other steps
+    pub fn step_melody(&self, notes: &[Option<Vec<Note>>])
+        -> Vec<Option<Vec<Note>>> {
+        let mut notes = notes.iter().cycle();
+        self.onsets.iter().map(|onset| if *onset {
+            notes.next().cloned().unwrap_or(None)
+        } else {
+            None
+        }).collect()
+    }
+}
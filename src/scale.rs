@@ -10,16 +10,25 @@ lazy_static! {
     static ref SCALE_FORMULAS: HashMap<ScaleType, Vec<i8>> = {
         [(ScaleType::Major, vec![0, 2, 4, 5, 7, 9, 11]),
          (ScaleType::Mixolydian, vec![0, 2, 4, 5, 7, 9, 10]),
-         (ScaleType::Dorian, vec![0, 2, 3, 5, 7, 9, 10])]
+         (ScaleType::Dorian, vec![0, 2, 3, 5, 7, 9, 10]),
+         (ScaleType::Minor, vec![0, 2, 3, 5, 7, 8, 10]),
+         (ScaleType::Lydian, vec![0, 2, 4, 6, 7, 9, 11]),
+         (ScaleType::Phrygian, vec![0, 1, 3, 5, 7, 8, 10]),
+         (ScaleType::Locrian, vec![0, 1, 3, 5, 6, 8, 10])]
         .iter().cloned().collect()
     }; 
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum ScaleType {
     Major,
     Mixolydian,
     Dorian,
+    Minor, // natural minor (aeolian)
+    Lydian,
+    Phrygian,
+    Locrian,
     Other // TODO: add more e.g. Blues, MajorPentatonic, MinorPentatonic
 }
 
@@ -34,6 +43,7 @@ pub enum ArabicNum {
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Scale {
     base_note: Note,
     scale_type: ScaleType
@@ -45,8 +55,17 @@ impl Scale {
         Scale {base_note, scale_type}
     }
 
+    pub fn get_base_note(&self) -> Note {
+        self.base_note
+    }
+
+    pub fn get_scale_type(&self) -> ScaleType {
+        self.scale_type
+    }
+
     /// Returns the note that is degree positions away from the base note of
-    /// the scale.
+    /// the scale. Degrees of seven-note scales are spelled with consecutive
+    /// letters, so the third degree of D major is F# rather than Gb.
     /// # Example (TODO: add examples from lib tests)
     pub fn get_degree(&self, degree: i8) -> Note {
         // TODO: add bounds checking
@@ -60,9 +79,85 @@ impl Scale {
         } else {
             scale[scale_offset as usize]
         };
-        self.base_note.add_half_steps(octave_offset * (NUM_TONES as i8) +
-            half_step_offset)
+        let note = self.base_note.add_half_steps(octave_offset *
+            (NUM_TONES as i8) + half_step_offset);
+        if scale_len != NUM_LETTERS as i8 {
+            return note;
+        }
+        let letter = self.base_note.get_letter().add_steps(degree);
+        note.respell(letter).unwrap_or(note)
+    }
+
+    /// Spells a note according to this scale. Notes that belong to the scale
+    /// take the spelling of the matching degree, other notes are written as
+    /// sharps in sharp keys and as flats otherwise.
+    pub fn spell(&self, note: &Note) -> Note {
+        let scale_len = SCALE_FORMULAS.get(&self.scale_type).unwrap().len();
+        for degree in 0..scale_len {
+            let degree_note = self.get_degree(degree as i8);
+            if degree_note.get_name() == note.get_name() {
+                return note.respell(degree_note.get_letter()).unwrap_or(*note);
+            }
+        }
+        let accidental = if self.uses_sharps() {
+            Accidental::Sharp
+        } else {
+            Accidental::Flat
+        };
+        for letter in LETTERS.iter() {
+            match note.respell(*letter) {
+                Some(spelled) if spelled.get_accidental() == accidental =>
+                    return spelled,
+                _ => {},
+            }
+        }
+        *note
+    }
+
+    /// Returns the key signature of the scale as a number of sharps (positive)
+    /// or flats (negative) e.g. 2 for D major and -1 for D minor, if the scale
+    /// is one of the modes of the major scale.
+    pub fn key_signature(&self) -> Option<i8> {
+        let mode_fifths = match self.scale_type {
+            ScaleType::Lydian => 1,
+            ScaleType::Major => 0,
+            ScaleType::Mixolydian => -1,
+            ScaleType::Dorian => -2,
+            ScaleType::Minor => -3,
+            ScaleType::Phrygian => -4,
+            ScaleType::Locrian => -5,
+            ScaleType::Other => return None,
+        };
+        // position of the natural tonic on the circle of fifths, from C
+        let letter_fifths = [0, 2, 4, -1, 1, 3, 5][
+            self.base_note.get_letter().index() as usize];
+        Some(letter_fifths + 7 * self.base_note.get_accidental().half_steps()
+            + mode_fifths)
+    }
+
+    /// Returns the scale of the given type whose key signature has the given
+    /// number of sharps (positive) or flats (negative), with its base note in
+    /// the fourth octave. Returns None if the tonic would need more than two
+    /// sharps or flats.
+    pub fn from_key_signature(fifths: i8, scale_type: ScaleType)
+        -> Option<Scale> {
+        let tonic_fifths = Scale::new(Note::new(Name::C, 4), scale_type)
+            .key_signature().map(|mode_fifths| fifths - mode_fifths)?;
+        // letters in circle of fifths order, starting from F
+        let letters = [Letter::F, Letter::C, Letter::G, Letter::D, Letter::A,
+            Letter::E, Letter::B];
+        let letter = letters[(tonic_fifths + 1).rem_euclid(7) as usize];
+        let accidental =
+            Accidental::from_half_steps((tonic_fifths + 1).div_euclid(7))?;
+        Some(Scale::new(Note::spelled(letter, accidental, 4), scale_type))
+    }
 
+    /// Returns true if any degree of the scale is spelled with a sharp
+    pub fn uses_sharps(&self) -> bool {
+        let scale_len = SCALE_FORMULAS.get(&self.scale_type).unwrap().len();
+        (0..scale_len).any(|degree| matches!(
+            self.get_degree(degree as i8).get_accidental(),
+            Accidental::Sharp | Accidental::DoubleSharp))
     }
 
     /// Returns a note with the associated arabic num, which comes after
@@ -71,15 +166,27 @@ impl Scale {
     /// octave afterward.
     pub fn get_arabic_num(&self, arabic_num: &ArabicNum, preceeding_note: &Note) 
         -> Note {
-        let mut result_note = match *arabic_num {
-            ArabicNum::Natural(num) => self.get_degree(num - 1),
-            ArabicNum::Flat(num) => self.get_degree(num - 1).flat(),
-            ArabicNum::Sharp(num) => self.get_degree(num - 1).sharp(),
+        let (num, alteration) = match *arabic_num {
+            ArabicNum::Natural(num) => (num, 0),
+            ArabicNum::Flat(num) => (num, -1),
+            ArabicNum::Sharp(num) => (num, 1),
         };
-        result_note = Note::new(result_note.get_name(),
+        // alterations keep the letter of the degree e.g. the #11 of C is F#,
+        // unless that would take more than a double sharp or flat, in which
+        // case the altered note takes the next letter in the direction of the
+        // alteration, or else its default spelling
+        let degree_note = self.get_degree(num - 1);
+        let altered_note = degree_note.add_half_steps(alteration);
+        let letter = degree_note.get_letter();
+        let spelled = altered_note.respell(letter)
+            .or_else(|| altered_note.respell(letter.add_steps(alteration)))
+            .unwrap_or(altered_note);
+        let (letter, accidental) = (spelled.get_letter(),
+            spelled.get_accidental());
+        let result_note = Note::spelled(letter, accidental,
             preceeding_note.get_octave());
         if result_note < *preceeding_note {
-            Note::new(result_note.get_name(), result_note.get_octave() + 1)
+            Note::spelled(letter, accidental, result_note.get_octave() + 1)
         } else {
             result_note
         }
@@ -0,0 +1,709 @@
+/// Parser for DAWPL scores, a text format for writing arrangements without
+/// recompiling any Rust. A score lists clip and track definitions, followed by
+/// an optional arrangement block:
+///
+/// ```text
+/// // the tune
+/// clip melody sine {
+///     notes F#4 r [D4 F#4 A4] Dmaj7
+///     durations q. e h w
+/// }
+/// clip gap empty h
+/// clip drums file "drums.wav"
+/// track lead { melody gap melody }
+/// track beat { drums }
+/// arrangement {
+///     key D major
+///     time 3/4
+///     pitch 432
+///     tempo 96
+///     tuning edo 19
+///     tracks lead beat
+/// }
+/// ```
+///
+/// Notes are written as for n!() (C4, F#4, Bb3), chords as notes in brackets
+/// or as chord symbols with an optional octave (Cmaj7, Am7, G3dom7, Bdim,
+/// Caug, Fmaj6; C7 is the note C7, so dominant sevenths are written Cdom7),
+/// and rests as r. Durations are w, h, q, e, s, t (thirty-second) and x
+/// (sixty-fourth), optionally dotted (q.), fractions of a whole note (3/8 or
+/// 1/12 for a triplet eighth), or sums of these (h+e). The arrangement
+/// block is optional and all of its settings can be left out; without a
+/// tracks setting every track is arranged in the order defined. The tempo is
+/// given in quarter notes per minute. Clip and track names start with a
+/// lowercase letter followed by letters, digits or underscores, and no clip
+/// can share its name with a track. Instruments are named as in the
+/// instrument library (see instruments.rs). Comments start with // and run to
+/// the end of the line.
+use arrangement::*;
+use chord::*;
+use clip::*;
+use duration::*;
+use instruments::*;
+use note::*;
+use scale::*;
+use synth::*;
+use track::*;
+use tuning::*;
+use std::fmt;
+
+/// Chord symbol suffixes, longest first where one ends with another
+const CHORD_SUFFIXES: [(&str, ChordType); 7] = [("maj7", ChordType::Maj7),
+    ("min7", ChordType::Min7), ("dom7", ChordType::Dom7),
+    ("m7", ChordType::Min7), ("dim", ChordType::Dim),
+    ("aug", ChordType::Aug), ("maj6", ChordType::Maj6)];
+
+/// An error in a score, located by line and column (both starting at 1)
+#[derive(PartialEq, Debug, Clone)]
+pub struct ScoreError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column,
+            self.message)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum TokenKind {
+    Word(String),
+    Text(String),
+    OpenBrace,
+    CloseBrace,
+    OpenBracket,
+    CloseBracket,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> ScoreError {
+        ScoreError {line: self.line, column: self.column, message}
+    }
+
+    fn describe(&self) -> String {
+        match self.kind {
+            TokenKind::Word(ref word) => format!("\"{}\"", word),
+            TokenKind::Text(ref text) => format!("\"\\\"{}\\\"\"", text),
+            TokenKind::OpenBrace => "\"{\"".into(),
+            TokenKind::CloseBrace => "\"}\"".into(),
+            TokenKind::OpenBracket => "\"[\"".into(),
+            TokenKind::CloseBracket => "\"]\"".into(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ScoreError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = source.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut advance = |c: char| if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        };
+        let kind = match c {
+            '{' | '}' | '[' | ']' => {
+                chars.next();
+                advance(c);
+                match c {
+                    '{' => TokenKind::OpenBrace,
+                    '}' => TokenKind::CloseBrace,
+                    '[' => TokenKind::OpenBracket,
+                    _ => TokenKind::CloseBracket,
+                }
+            },
+            '"' => {
+                chars.next();
+                advance(c);
+                let mut text: String = "".into();
+                loop {
+                    match chars.next() {
+                        Some('"') => {
+                            advance('"');
+                            break;
+                        },
+                        Some(c) => {
+                            advance(c);
+                            text.push(c);
+                        },
+                        None => return Err(ScoreError {line: start_line,
+                            column: start_column,
+                            message: "Unterminated string".into()}),
+                    }
+                }
+                TokenKind::Text(text)
+            },
+            c if c.is_whitespace() => {
+                chars.next();
+                advance(c);
+                continue;
+            },
+            _ => {
+                let mut word: String = "".into();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}[]\"".contains(c) {
+                        break;
+                    }
+                    chars.next();
+                    advance(c);
+                    word.push(c);
+                }
+                if word.starts_with("//") {
+                    // comments run to the end of the line
+                    while chars.peek().is_some_and(|c| *c != '\n') {
+                        chars.next();
+                    }
+                    continue;
+                }
+                TokenKind::Word(word)
+            },
+        };
+        tokens.push(Token {kind, line: start_line, column: start_column});
+    }
+    Ok(tokens)
+}
+
+/// Parses a duration e.g. q, h., 3/8 or h+e. Note values take at most
+/// MAX_DOTS dots.
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let mut total = Duration::zero();
+    for part in duration.split('+') {
+        let length = if part.contains('/') {
+            part.parse().ok()?
+        } else {
+            let dots = part.len() - part.trim_end_matches('.').len();
+            if dots > MAX_DOTS as usize {
+                return None;
+            }
+            let value: Beat = part[..part.len() - dots].to_ascii_uppercase()
+                .parse().ok()?;
+            value.get_duration().dotted(dots as u32)
+        };
+        if length.is_zero() {
+            return None;
+        }
+        total += length;
+    }
+    Some(total)
+}
+
+/// Parses a chord symbol e.g. Cmaj7, Am7 or G3dom7. The root is in the fourth
+/// octave unless an octave is given.
+pub fn parse_chord_symbol(symbol: &str) -> Option<Chord> {
+    if !symbol.is_ascii() {
+        return None;
+    }
+    let lower = symbol.to_lowercase();
+    let &(suffix, chord_type) = CHORD_SUFFIXES.iter()
+        .find(|&&(suffix, _)| lower.ends_with(suffix))?;
+    let root = &symbol[..symbol.len() - suffix.len()];
+    let root: Note = if root.ends_with(|c: char| c.is_ascii_digit()) {
+        root.parse().ok()?
+    } else {
+        format!("{}4", root).parse().ok()?
+    };
+    Some(Chord::new(root, chord_type))
+}
+
+/// Writes a chord as a chord symbol that parse_chord_symbol() reads back,
+/// e.g. Cmaj7 or G3dom7
+pub fn chord_symbol(chord: &Chord) -> String {
+    let root = chord.get_root();
+    let octave = if root.get_octave() == 4 {
+        "".into()
+    } else {
+        root.get_octave().to_string()
+    };
+    let &(suffix, _) = CHORD_SUFFIXES.iter()
+        .find(|&&(_, chord_type)| chord_type == chord.get_chord_type())
+        .unwrap();
+    format!("{:?}{}{}{}", root.get_letter(), root.get_accidental(), octave,
+        suffix)
+}
+
+fn parse_scale_type(mode: &str) -> Option<ScaleType> {
+    match &mode.to_lowercase()[..] {
+        "major" | "ionian" => Some(ScaleType::Major),
+        "minor" | "aeolian" => Some(ScaleType::Minor),
+        "dorian" => Some(ScaleType::Dorian),
+        "mixolydian" => Some(ScaleType::Mixolydian),
+        "lydian" => Some(ScaleType::Lydian),
+        "phrygian" => Some(ScaleType::Phrygian),
+        "locrian" => Some(ScaleType::Locrian),
+        _ => None,
+    }
+}
+
+/// Parses a score into an arrangement
+pub fn parse_score(source: &str) -> Result<Arrangement, ScoreError> {
+    ScoreParser::new(source, &[])?.parse()
+}
+
+/// Clips, tracks and arrangement settings defined by part of a score
+pub struct ScoreDefinitions {
+    pub clips: Vec<Clip>,
+    pub tracks: Vec<Track>,
+    /// Names of the tracks to arrange, in order, if they were given
+    pub track_order: Option<Vec<String>>,
+    settings: Option<Settings>,
+}
+
+impl ScoreDefinitions {
+    /// Applies the key, time signature, concert pitch, tempo and tuning
+    /// given by an arrangement block to an arrangement
+    pub fn apply_settings(&self, arrangement: &mut Arrangement) {
+        if let Some(ref settings) = self.settings {
+            settings.apply(arrangement);
+        }
+    }
+}
+
+/// Parses part of a score, for building up an arrangement a few definitions
+/// at a time. Tracks may use the clips it defines and the known clips, which
+/// were defined earlier.
+pub fn parse_definitions(source: &str, known_clips: &[String])
+    -> Result<ScoreDefinitions, ScoreError> {
+    let mut parser = ScoreParser::new(source, known_clips)?;
+    let settings = parser.parse_definitions()?;
+    let track_order = settings.as_ref().and_then(|settings|
+        settings.tracks.as_ref()).map(|names| names.iter()
+            .map(|name| word_of(name).to_string()).collect());
+    Ok(ScoreDefinitions {
+        clips: parser.clips.into_iter().map(|clip| clip.0).collect(),
+        tracks: parser.tracks.iter().map(to_track).collect(),
+        track_order, settings})
+}
+
+/// Arrangement settings and where they were given
+struct Settings {
+    key: Option<Scale>,
+    time_signature: Option<(u8, u8)>,
+    concert_pitch: Option<f64>,
+    tempo: Option<f64>,
+    tuning: Option<Tuning>,
+    tracks: Option<Vec<Token>>,
+}
+
+impl Settings {
+    fn apply(&self, arrangement: &mut Arrangement) {
+        if let Some(key) = self.key {
+            arrangement.set_key(key);
+        }
+        if let Some((beats, beat_value)) = self.time_signature {
+            arrangement.set_time_signature(beats, beat_value);
+        }
+        if let Some(concert_pitch) = self.concert_pitch {
+            arrangement.set_concert_pitch(concert_pitch);
+        }
+        if let Some(tempo) = self.tempo {
+            arrangement.set_tempo(tempo);
+        }
+        if let Some(ref tuning) = self.tuning {
+            arrangement.set_tuning(tuning.clone());
+        }
+    }
+}
+
+struct ScoreParser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Location of the end of the score, for errors about missing tokens
+    end: Token,
+    clips: Vec<(Clip, Token)>,
+    /// Tracks with the tokens of their clip names
+    tracks: Vec<(String, Token, Vec<Token>)>,
+    /// Clips defined outside of the source that tracks may use
+    known_clips: Vec<String>,
+}
+
+impl ScoreParser {
+    fn new(source: &str, known_clips: &[String])
+        -> Result<ScoreParser, ScoreError> {
+        let lines: Vec<&str> = source.split('\n').collect();
+        let end = Token {kind: TokenKind::CloseBrace, line: lines.len(),
+            column: lines[lines.len() - 1].chars().count() + 1};
+        Ok(ScoreParser {tokens: tokenize(source)?, position: 0, end,
+            clips: Vec::new(), tracks: Vec::new(),
+            known_clips: known_clips.to_vec()})
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn expect(&mut self, what: &str) -> Result<Token, ScoreError> {
+        match self.next() {
+            Some(token) => Ok(token),
+            None => Err(self.end.error(format!("Expected {}, found the end \
+                of the score", what))),
+        }
+    }
+
+    fn expect_word(&mut self, what: &str) -> Result<(String, Token),
+        ScoreError> {
+        let token = self.expect(what)?;
+        match token.kind {
+            TokenKind::Word(ref word) => Ok((word.clone(), token.clone())),
+            _ => Err(token.error(format!("Expected {}, found {}", what,
+                token.describe()))),
+        }
+    }
+
+    fn expect_open_brace(&mut self) -> Result<(), ScoreError> {
+        let token = self.expect("\"{\"")?;
+        match token.kind {
+            TokenKind::OpenBrace => Ok(()),
+            _ => Err(token.error(format!("Expected \"{{\", found {}",
+                token.describe()))),
+        }
+    }
+
+    /// True (consuming the brace) if the next token closes a block
+    fn close_brace(&mut self) -> Result<bool, ScoreError> {
+        match self.peek() {
+            Some(&Token {kind: TokenKind::CloseBrace, ..}) => {
+                self.position += 1;
+                Ok(true)
+            },
+            Some(_) => Ok(false),
+            None => Err(self.end.error("Expected \"}\", found the end of \
+                the score".into())),
+        }
+    }
+
+    /// True if the next token is a word of a block e.g. the next note
+    fn at_value(&self) -> bool {
+        match self.peek() {
+            Some(&Token {kind: TokenKind::Word(ref word), ..}) =>
+                !["notes", "durations"].contains(&&word[..]),
+            Some(&Token {kind: TokenKind::OpenBracket, ..}) => true,
+            _ => false,
+        }
+    }
+
+    /// Parses every clip, track and arrangement block, checking that tracks
+    /// only use clips that exist
+    fn parse_definitions(&mut self) -> Result<Option<Settings>, ScoreError> {
+        let mut settings: Option<Settings> = None;
+        while let Some(token) = self.next() {
+            match token.kind {
+                TokenKind::Word(ref word) if word == "clip" =>
+                    self.parse_clip()?,
+                TokenKind::Word(ref word) if word == "track" =>
+                    self.parse_track()?,
+                TokenKind::Word(ref word) if word == "arrangement" => {
+                    if settings.is_some() {
+                        return Err(token.error("A score can only have one \
+                            arrangement block".into()));
+                    }
+                    settings = Some(self.parse_arrangement()?);
+                },
+                _ => return Err(token.error(format!("Expected \"clip\", \
+                    \"track\" or \"arrangement\", found {}",
+                    token.describe()))),
+            }
+        }
+        for track in self.tracks.iter() {
+            for clip_name in track.2.iter() {
+                let name = word_of(clip_name);
+                if !self.clips.iter().any(|clip| clip.0.get_name() == name) &&
+                    !self.known_clips.iter().any(|known| known == name) {
+                    return Err(clip_name.error(format!("Unknown clip \"{}\"",
+                        name)));
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    fn parse(&mut self) -> Result<Arrangement, ScoreError> {
+        let settings = self.parse_definitions()?.unwrap_or(Settings {key: None,
+            time_signature: None, concert_pitch: None, tempo: None,
+            tuning: None, tracks: None});
+        let tracks: Vec<Track> = match settings.tracks {
+            Some(ref names) => {
+                let mut tracks = Vec::new();
+                for name in names.iter() {
+                    let track = self.tracks.iter()
+                        .find(|track| track.0 == word_of(name))
+                        .ok_or_else(|| name.error(format!("Unknown track \
+                            \"{}\"", word_of(name))))?;
+                    tracks.push(to_track(track));
+                }
+                tracks
+            },
+            None => self.tracks.iter().map(to_track).collect(),
+        };
+        let clips = self.clips.iter().map(|clip| clip.0.clone()).collect();
+        let mut arrangement = Arrangement::new(tracks, clips);
+        settings.apply(&mut arrangement);
+        Ok(arrangement)
+    }
+
+    fn parse_clip(&mut self) -> Result<(), ScoreError> {
+        let (name, name_token) = self.expect_word("a clip name")?;
+        check_name(&name, &name_token)?;
+        if self.clips.iter().any(|clip| clip.0.get_name() == name) {
+            return Err(name_token.error(format!("Clip \"{}\" is already \
+                defined", name)));
+        }
+        if self.tracks.iter().any(|track| track.0 == name) {
+            return Err(name_token.error(format!("\"{}\" is already the name \
+                of a track", name)));
+        }
+        let (kind, kind_token) = self.expect_word("an instrument, \"empty\" \
+            or \"file\"")?;
+        let clip = match &kind[..] {
+            "empty" => {
+                let (duration, token) = self.expect_word("a duration")?;
+                let duration = parse_duration(&duration).ok_or_else(||
+                    token.error(format!("Invalid duration \"{}\"", duration)))?;
+                Clip::Empty(name, duration)
+            },
+            "file" => {
+                let token = self.expect("a file path")?;
+                match token.kind {
+                    TokenKind::Text(path) => Clip::File(name, path),
+                    _ => return Err(token.error(format!("Expected a file path \
+                        in quotes, found {}", token.describe()))),
+                }
+            },
+            _ => {
+                let instrument = builtin_instrument(&kind).ok_or_else(||
+                    kind_token.error(format!("Unknown instrument \"{}\"",
+                        kind)))?;
+                self.parse_clip_body(name, instrument, kind_token)?
+            },
+        };
+        self.clips.push((clip, name_token));
+        Ok(())
+    }
+
+    /// Parses the notes and durations of an instrument clip
+    fn parse_clip_body(&mut self, name: String,
+        instrument: InstrumentHandle,
+        instrument_token: Token) -> Result<Clip, ScoreError> {
+        self.expect_open_brace()?;
+        let mut melody: Vec<Option<Vec<Note>>> = Vec::new();
+        let mut durations: Vec<Duration> = Vec::new();
+        while !self.close_brace()? {
+            let (statement, token) = self.expect_word("\"notes\" or \
+                \"durations\"")?;
+            match &statement[..] {
+                "notes" => while self.at_value() {
+                    melody.push(self.parse_event()?);
+                },
+                "durations" => while self.at_value() {
+                    let (duration, token) = self.expect_word("a duration")?;
+                    durations.push(parse_duration(&duration).ok_or_else(||
+                        token.error(format!("Invalid duration \"{}\"",
+                            duration)))?);
+                },
+                _ => return Err(token.error(format!("Expected \"notes\" or \
+                    \"durations\", found {}", token.describe()))),
+            }
+        }
+        if melody.len() != durations.len() {
+            return Err(instrument_token.error(format!("Clip \"{}\" has {} \
+                notes but {} durations", name, melody.len(), durations.len())));
+        }
+        Ok(Clip::Instrument(name, instrument, melody, durations))
+    }
+
+    /// Parses a note, chord or rest
+    fn parse_event(&mut self) -> Result<Option<Vec<Note>>, ScoreError> {
+        let token = self.expect("a note")?;
+        match token.kind {
+            TokenKind::OpenBracket => {
+                let mut notes: Vec<Note> = Vec::new();
+                loop {
+                    let note_token = self.expect("a note or \"]\"")?;
+                    match note_token.kind {
+                        TokenKind::CloseBracket => break,
+                        TokenKind::Word(ref word) => {
+                            let note: Note = word.parse().map_err(|error|
+                                note_token.error(error))?;
+                            notes.push(note);
+                        },
+                        _ => return Err(note_token.error(format!("Expected a \
+                            note or \"]\", found {}", note_token.describe()))),
+                    }
+                }
+                if notes.is_empty() {
+                    return Err(token.error("Chords need at least one note"
+                        .into()));
+                }
+                Ok(Some(notes))
+            },
+            TokenKind::Word(ref word) if word == "r" => Ok(None),
+            TokenKind::Word(ref word) => {
+                if let Ok(note) = word.parse::<Note>() {
+                    return Ok(Some(vec![note]));
+                }
+                match parse_chord_symbol(word) {
+                    Some(chord) => Ok(Some(chord.play())),
+                    None => Err(token.error(format!("Expected a note, chord or \
+                        rest, found \"{}\"", word))),
+                }
+            },
+            _ => Err(token.error(format!("Expected a note, found {}",
+                token.describe()))),
+        }
+    }
+
+    fn parse_track(&mut self) -> Result<(), ScoreError> {
+        let (name, name_token) = self.expect_word("a track name")?;
+        check_name(&name, &name_token)?;
+        if self.tracks.iter().any(|track| track.0 == name) {
+            return Err(name_token.error(format!("Track \"{}\" is already \
+                defined", name)));
+        }
+        if self.clips.iter().any(|clip| clip.0.get_name() == name) ||
+            self.known_clips.iter().any(|known| known == &name) {
+            return Err(name_token.error(format!("\"{}\" is already the name \
+                of a clip", name)));
+        }
+        self.expect_open_brace()?;
+        let mut clip_names: Vec<Token> = Vec::new();
+        while !self.close_brace()? {
+            clip_names.push(self.expect_word("a clip name")?.1);
+        }
+        self.tracks.push((name, name_token, clip_names));
+        Ok(())
+    }
+
+    fn parse_arrangement(&mut self) -> Result<Settings, ScoreError> {
+        self.expect_open_brace()?;
+        let mut settings = Settings {key: None, time_signature: None,
+            concert_pitch: None, tempo: None, tuning: None, tracks: None};
+        while !self.close_brace()? {
+            let (setting, token) = self.expect_word("an arrangement \
+                setting")?;
+            match &setting[..] {
+                "key" => {
+                    let (tonic, tonic_token) = self.expect_word("a tonic")?;
+                    let tonic: Note = format!("{}4", tonic).parse().map_err(|_|
+                        tonic_token.error(format!("Invalid tonic \"{}\"",
+                            tonic)))?;
+                    let (mode, mode_token) = self.expect_word("a mode")?;
+                    let scale_type = parse_scale_type(&mode).ok_or_else(||
+                        mode_token.error(format!("Unknown mode \"{}\"",
+                            mode)))?;
+                    settings.key = Some(Scale::new(tonic, scale_type));
+                },
+                "time" => {
+                    let (time, time_token) =
+                        self.expect_word("a time signature")?;
+                    let invalid = || time_token.error(format!("Invalid time \
+                        signature \"{}\"", time));
+                    let mut parts = time.split('/');
+                    let beats: u8 = parts.next().and_then(|beats|
+                        beats.parse().ok()).ok_or_else(invalid)?;
+                    let beat_value: u8 = parts.next().and_then(|value|
+                        value.parse().ok()).ok_or_else(invalid)?;
+                    if parts.next().is_some() || beats == 0 ||
+                        !beat_value.is_power_of_two() {
+                        return Err(invalid());
+                    }
+                    settings.time_signature = Some((beats, beat_value));
+                },
+                "pitch" => {
+                    let (pitch, pitch_token) =
+                        self.expect_word("a frequency")?;
+                    settings.concert_pitch = Some(pitch.parse().ok()
+                        .filter(|pitch: &f64| pitch.is_finite() && *pitch > 0.0)
+                        .ok_or_else(||
+                            pitch_token.error(format!("Invalid frequency \
+                                \"{}\"", pitch)))?);
+                },
+                "tempo" => {
+                    let (tempo, tempo_token) = self.expect_word("a tempo")?;
+                    settings.tempo = Some(tempo.parse().ok()
+                        .filter(|tempo: &f64| tempo.is_finite() && *tempo > 0.0)
+                        .ok_or_else(||
+                            tempo_token.error(format!("Invalid tempo \"{}\"",
+                                tempo)))?);
+                },
+                "tuning" => settings.tuning = Some(self.parse_tuning()?),
+                "tracks" => {
+                    let mut tracks: Vec<Token> = Vec::new();
+                    while let Some(&Token {kind: TokenKind::Word(_), ..}) =
+                        self.peek() {
+                        tracks.push(self.next().unwrap());
+                    }
+                    settings.tracks = Some(tracks);
+                },
+                _ => return Err(token.error(format!("Expected \"key\", \
+                    \"time\", \"pitch\", \"tempo\", \"tuning\" or \"tracks\", \
+                    found {}", token.describe()))),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Parses "edo N" or "just TONIC"
+    fn parse_tuning(&mut self) -> Result<Tuning, ScoreError> {
+        let (system, token) = self.expect_word("\"edo\" or \"just\"")?;
+        let (value, value_token) = self.expect_word("a tuning")?;
+        match &system[..] {
+            "edo" => match value.parse() {
+                Ok(steps) if steps > 0 => Ok(Tuning::Edo(steps)),
+                _ => Err(value_token.error(format!("Invalid number of steps \
+                    \"{}\"", value))),
+            },
+            "just" => {
+                let tonic: Note = format!("{}4", value).parse().map_err(|_|
+                    value_token.error(format!("Invalid tonic \"{}\"",
+                        value)))?;
+                Ok(Tuning::just(tonic.get_name()))
+            },
+            _ => Err(token.error(format!("Expected \"edo\" or \"just\", \
+                found {}", token.describe()))),
+        }
+    }
+}
+
+/// Clip and track names become SuperCollider variables, so they have to start
+/// with a lowercase letter and can only hold letters, digits and underscores
+fn check_name(name: &str, token: &Token) -> Result<(), ScoreError> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase()) &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(token.error(format!("Invalid name \"{}\" (names start with a \
+            lowercase letter followed by letters, digits or underscores)",
+            name)))
+    }
+}
+
+fn word_of(token: &Token) -> &str {
+    match token.kind {
+        TokenKind::Word(ref word) => word,
+        _ => "",
+    }
+}
+
+fn to_track(track: &(String, Token, Vec<Token>)) -> Track {
+    Track::new(track.0.clone(), track.2.iter()
+        .map(|clip_name| word_of(clip_name).to_string()).collect())
+}
@@ -0,0 +1,453 @@
+/// Real-time sequencer that plays an arrangement from Rust rather than
+/// leaving the timing to sclang. The sequencer walks the tracks and clips of
+/// an arrangement in order, turning every note into a note on and a note off
+/// event, and sends each event to every sink shortly before it is due.
+///
+/// Events are sent up to a lookahead ahead of time, together with the time
+/// they are due, so that sinks that can schedule (such as scsynth, through
+/// time tagged OSC bundles) play them exactly on time however late the
+/// sequencer wakes up. Sinks that cannot schedule play events as soon as they
+/// arrive, and are best used with a small lookahead. Positions are exact
+/// times in whole notes, turned into real times with a tempo map, which can be
+/// changed while the sequencer is running; events already sent keep the time
+/// they were sent with.
+use arrangement::*;
+use duration::Time;
+use midi::*;
+use osc::*;
+use instruments::*;
+use render::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default time between the moment events are sent and the moment they are
+/// due
+pub const DEFAULT_LOOKAHEAD: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SequencerEvent {
+    NoteOn {
+        /// Index of the note among the notes of the arrangement, which its
+        /// note off shares
+        note: usize,
+        /// Index of the track playing the note
+        track: usize,
+        /// MIDI channel of the track, as in arrangement_to_midi()
+        channel: u8,
+        instrument: String,
+        midi_value: i8,
+        /// Frequency of the note in the arrangement's tuning, if it can be
+        /// played
+        frequency: Option<f64>,
+        velocity: u8,
+    },
+    NoteOff {
+        note: usize,
+        track: usize,
+        channel: u8,
+        midi_value: i8,
+    },
+}
+
+/// An event at a position of the arrangement
+#[derive(Debug, PartialEq, Clone)]
+pub struct TimedEvent {
+    pub position: Time,
+    pub event: SequencerEvent,
+}
+
+/// Source of the time elapsed since the sequencer started
+pub trait Clock {
+    fn elapsed(&self) -> Duration;
+    /// Waits for the given time, or pretends to
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// A clock that follows real time
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {start: Instant::now()}
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when it sleeps, for running a sequencer faster
+/// than real time e.g. in tests
+#[derive(Default)]
+pub struct ManualClock {
+    elapsed: Duration,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {elapsed: Duration::from_secs(0)}
+    }
+}
+
+impl Clock for ManualClock {
+    fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.elapsed += duration;
+    }
+}
+
+/// Receives the events of a sequencer
+pub trait Sink {
+    /// Handles an event that is due at the given time, which is at most the
+    /// lookahead of the sequencer away
+    fn send(&mut self, event: &SequencerEvent, due: SystemTime)
+        -> io::Result<()>;
+    /// Silences every note
+    fn stop(&mut self) -> io::Result<()>;
+}
+
+pub struct Sequencer<C: Clock> {
+    events: Vec<TimedEvent>,
+    /// Index of the next event to send
+    next: usize,
+    /// Tempo changes as positions in whole notes and tempos in quarter notes
+    /// per minute, ordered by position, starting at position zero
+    tempo_map: Vec<(Time, f64)>,
+    clock: C,
+    /// Real time at which the sequencer started
+    start: SystemTime,
+    sinks: Vec<Box<dyn Sink>>,
+    lookahead: Duration,
+}
+
+impl<C: Clock> Sequencer<C> {
+    pub fn new(arrangement: &Arrangement, clock: C) -> Sequencer<C> {
+        let start = SystemTime::now() - clock.elapsed();
+        Sequencer {events: arrangement_events(arrangement), next: 0,
+            tempo_map: vec![(Time::zero(), arrangement.get_tempo())], clock,
+            start,
+            sinks: Vec::new(), lookahead: DEFAULT_LOOKAHEAD}
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn set_lookahead(&mut self, lookahead: Duration) {
+        self.lookahead = lookahead;
+    }
+
+    pub fn get_events(&self) -> &Vec<TimedEvent> {
+        &self.events
+    }
+
+    pub fn get_clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Changes the tempo (in quarter notes per minute) from the given
+    /// position on, replacing any later tempo changes. Tempos that are not
+    /// positive numbers give an error.
+    pub fn set_tempo_at(&mut self, position: Time, tempo: f64)
+        -> Result<(), String> {
+        if !(tempo.is_finite() && tempo > 0.0) {
+            return Err(format!("Invalid tempo {}", tempo));
+        }
+        self.tempo_map.retain(|&(change, _)| change < position);
+        if self.tempo_map.is_empty() {
+            self.tempo_map.push((Time::zero(), tempo));
+        } else {
+            self.tempo_map.push((position, tempo));
+        }
+        Ok(())
+    }
+
+    /// Changes the tempo from the current position on
+    pub fn set_tempo(&mut self, tempo: f64) -> Result<(), String> {
+        let position = self.get_position();
+        self.set_tempo_at(position, tempo)
+    }
+
+    /// Time from the start of the sequencer to a position, following the
+    /// tempo map
+    pub fn time_of(&self, position: Time) -> Duration {
+        let mut seconds = 0.0;
+        for (i, &(change, tempo)) in self.tempo_map.iter().enumerate() {
+            let end = self.tempo_map.get(i + 1)
+                .map_or(position, |&(next, _)| next.min(position));
+            if end > change {
+                seconds += (end - change).to_f64() * 240.0 / tempo;
+            }
+        }
+        Duration::from_secs_f64(seconds)
+    }
+
+    /// Position of the sequencer, to the nearest fraction a float can tell
+    pub fn get_position(&self) -> Time {
+        let mut elapsed = self.clock.elapsed().as_secs_f64();
+        let mut position = Time::zero();
+        for (i, &(change, tempo)) in self.tempo_map.iter().enumerate() {
+            let seconds_per_whole = 240.0 / tempo;
+            match self.tempo_map.get(i + 1) {
+                Some(&(next, _)) if (next - change).to_f64() *
+                    seconds_per_whole < elapsed => {
+                    elapsed -= (next - change).to_f64() * seconds_per_whole;
+                    position = next;
+                },
+                _ => return position + Time::from_f64(elapsed /
+                    seconds_per_whole).unwrap_or_else(Time::zero),
+            }
+        }
+        position
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    /// Sends every event due before the lookahead runs out, returning how
+    /// many were sent
+    pub fn tick(&mut self) -> io::Result<usize> {
+        let horizon = self.clock.elapsed() + self.lookahead;
+        let mut sent = 0;
+        while let Some(event) = self.events.get(self.next) {
+            let due = self.time_of(event.position);
+            if due > horizon {
+                break;
+            }
+            for sink in self.sinks.iter_mut() {
+                sink.send(&event.event, self.start + due)?;
+            }
+            self.next += 1;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Plays the arrangement to the end, waking up often enough that every
+    /// event is sent at least half the lookahead before it is due
+    pub fn run(&mut self) -> io::Result<()> {
+        let interval = (self.lookahead / 2).max(Duration::from_millis(1));
+        loop {
+            self.tick()?;
+            if self.is_finished() {
+                return Ok(());
+            }
+            self.clock.sleep(interval);
+        }
+    }
+
+    /// Stops every sink
+    pub fn stop(&mut self) -> io::Result<()> {
+        self.next = self.events.len();
+        for sink in self.sinks.iter_mut() {
+            sink.stop()?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the events of an arrangement in the order they are due. Note offs
+/// come before note ons at the same position, so repeated notes are not cut
+/// short, except that a note without length ends after it starts.
+pub fn arrangement_events(arrangement: &Arrangement) -> Vec<TimedEvent> {
+    let tuning = arrangement.get_tuning();
+    let a4_hz = arrangement.get_concert_pitch();
+    // (order of the event at its position, event)
+    let mut events: Vec<(u8, TimedEvent)> = Vec::new();
+    for (index, note) in arrangement.get_timed_notes().into_iter()
+        .enumerate() {
+        let channel = track_channel(arrangement, note.track);
+        events.push((if note.duration.is_zero() { 2 } else { 0 },
+            TimedEvent {position: note.start + note.duration,
+            event: SequencerEvent::NoteOff {note: index, track: note.track,
+            channel,
+            midi_value: note.pitch.get_midi_value()}}));
+        events.push((1, TimedEvent {position: note.start,
+            event: SequencerEvent::NoteOn {note: index, track: note.track,
+            channel,
+            frequency: tuning.frequency(&note.pitch, a4_hz),
+            instrument: note.instrument,
+            midi_value: note.pitch.get_midi_value(),
+            velocity: note.velocity}}));
+    }
+    // stable, so events of the same order at the same position keep the
+    // order above
+    events.sort_by_key(|&(order, ref event)| (event.position, order));
+    events.into_iter().map(|(_, event)| event).collect()
+}
+
+/// Plays events on scsynth, starting a synth for every note at the time it is
+/// due and releasing it (by setting its gate to 0) when the note ends. Synths
+/// without a gate, like the sine synth, end by themselves.
+pub struct OscSink {
+    scsynth: Scsynth,
+    /// Synths that are playing, by the index of their note
+    nodes: HashMap<usize, i32>,
+}
+
+impl OscSink {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<OscSink> {
+        Ok(OscSink {scsynth: Scsynth::connect(address)?,
+            nodes: HashMap::new()})
+    }
+}
+
+impl Sink for OscSink {
+    fn send(&mut self, event: &SequencerEvent, due: SystemTime)
+        -> io::Result<()> {
+        match *event {
+            SequencerEvent::NoteOn {note, ref instrument,
+                frequency: Some(frequency), velocity, ..} => {
+                let amp = AMPLITUDE * velocity as f32 / VELOCITY as f32;
+                let node = self.scsynth.new_synth_at(due, instrument,
+                    &[("freq", frequency as f32), ("amp", amp)])?;
+                self.nodes.insert(note, node);
+            },
+            SequencerEvent::NoteOff {note, ..} => {
+                if let Some(node) = self.nodes.remove(&note) {
+                    self.scsynth.set_at(due, node, &[("gate", 0.0)])?;
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        self.nodes.clear();
+        self.scsynth.stop()
+    }
+}
+
+/// Writes events as MIDI messages to a MIDI port, such as a raw MIDI device
+/// (e.g. /dev/snd/midiC1D0), as soon as they are sent. Every track plays on
+/// its own channel, and drum tracks on channel 10, as in Standard MIDI Files
+/// written by arrangement_to_midi().
+pub struct MidiSink<W: Write> {
+    port: W,
+    /// Keys held down, by channel
+    held: Vec<(u8, u8)>,
+}
+
+impl<W: Write> MidiSink<W> {
+    pub fn new(port: W) -> MidiSink<W> {
+        MidiSink {port, held: Vec::new()}
+    }
+
+    pub fn get_port(&self) -> &W {
+        &self.port
+    }
+}
+
+impl<W: Write> Sink for MidiSink<W> {
+    fn send(&mut self, event: &SequencerEvent, _due: SystemTime)
+        -> io::Result<()> {
+        match *event {
+            SequencerEvent::NoteOn {channel, midi_value, velocity, ..} => {
+                self.held.push((channel, midi_value as u8));
+                self.port.write_all(&[0x90 | channel, midi_value as u8,
+                    velocity])?;
+            },
+            SequencerEvent::NoteOff {channel, midi_value, ..} => {
+                let key = (channel, midi_value as u8);
+                if let Some(index) = self.held.iter().position(|held|
+                    *held == key) {
+                    self.held.remove(index);
+                }
+                self.port.write_all(&[0x80 | key.0, key.1, 0])?;
+            },
+        }
+        self.port.flush()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        for (channel, key) in self.held.drain(..) {
+            self.port.write_all(&[0x80 | channel, key, 0])?;
+        }
+        self.port.flush()
+    }
+}
+
+/// An in-process synth that plays every note as the offline renderer does,
+/// shaped by the envelope of the instrument of the library it names. The
+/// sequencer starts notes as soon as they are sent and releases them at
+/// their note offs; whatever plays the audio pulls it with render(), sharing
+/// the synth with the sequencer as an Arc<Mutex<Synth>>.
+pub struct Synth {
+    /// Notes that are sounding, by the index of their note
+    voices: Vec<(usize, Voice)>,
+    sample_rate: u32,
+}
+
+impl Synth {
+    pub fn new(sample_rate: u32) -> Synth {
+        Synth {voices: Vec::new(), sample_rate}
+    }
+
+    pub fn get_voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// Adds the next samples of every note to the given samples
+    pub fn render(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let mix: f64 = self.voices.iter_mut()
+                .map(|voice| voice.1.next_sample()).sum();
+            *sample = (*sample as f64 + mix).clamp(-1.0, 1.0) as f32;
+        }
+        self.voices.retain(|voice| !voice.1.is_finished());
+    }
+}
+
+impl Sink for Arc<Mutex<Synth>> {
+    fn send(&mut self, event: &SequencerEvent, _due: SystemTime)
+        -> io::Result<()> {
+        let mut synth = self.lock().unwrap();
+        match *event {
+            SequencerEvent::NoteOn {note, ref instrument,
+                frequency: Some(frequency), ..} => {
+                let envelope = builtin_instrument(instrument).map_or_else(
+                    Envelope::default, |instrument|
+                    Envelope::of(instrument.get_instrument()));
+                let voice = Voice::new(frequency, synth.sample_rate,
+                    envelope);
+                synth.voices.push((note, voice));
+            },
+            SequencerEvent::NoteOff {note, ..} => {
+                for voice in synth.voices.iter_mut()
+                    .filter(|voice| voice.0 == note) {
+                    voice.1.release();
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        self.lock().unwrap().voices.clear();
+        Ok(())
+    }
+}
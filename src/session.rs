@@ -0,0 +1,318 @@
+/// Interactive sessions, in which an arrangement is composed a command at a
+/// time. This is what the dawpl-repl binary runs. Clips, tracks and
+/// arrangement settings are defined in the score language (see score.rs),
+/// and defining a clip or track again replaces it, so ideas can be reworked
+/// without starting over. Any item can be inspected as SuperCollider or as
+/// MIDI values, and the whole arrangement can be exported with any backend or
+/// played live by sclang.
+use arrangement::*;
+use backend::*;
+use chord::*;
+use clip::*;
+use duration::*;
+use instruments::*;
+use note::*;
+use osc::*;
+use score::*;
+use translate::*;
+
+pub const HELP: &str = "Commands:
+  clip NAME INSTR { notes ... durations ... }  define an instrument clip
+  clip NAME empty DUR | clip NAME file \"PATH\"  define a rest or audio clip
+  track NAME { CLIPS }        define a track
+  key TONIC MODE | time N/D | pitch HZ | tempo BPM | tuning edo N
+  | tuning just TONIC | tracks NAMES           change the arrangement
+  note NOTES                  show the MIDI values and frequencies of notes
+  chord SYMBOL | chord NOTES  show the notes of a chord or name a chord
+  sc [NAME]                   show a clip, a track or everything as
+                              SuperCollider
+  midi NAME                   show the MIDI values of a clip or track
+  list                        list the clips and tracks
+  instruments                 list the instruments clips can be played with
+  remove NAME                 remove a clip or track
+  load PATH                   replace the session with a score file
+  export PATH [BACKEND]       write the arrangement to a file
+  listener                    show the code to run in sclang before
+                              connecting
+  connect [HOST:PORT]         send to sclang (default 127.0.0.1:57120),
+                              which must be running the listener
+  play                        play the arrangement in sclang
+  stop                        stop everything sclang is playing
+  clear                       start over
+  help                        show this message";
+
+/// The settings that can be written without an arrangement block
+const SETTINGS: [&str; 6] = ["key", "time", "pitch", "tempo", "tuning",
+    "tracks"];
+
+pub struct Session {
+    arrangement: Arrangement,
+    sclang: Option<Sclang>,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {arrangement: Arrangement::default(), sclang: None}
+    }
+
+    pub fn get_arrangement(&self) -> &Arrangement {
+        &self.arrangement
+    }
+
+    /// Runs a command, returning the text to show for it
+    pub fn execute(&mut self, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        let (command, rest) = match input.find(char::is_whitespace) {
+            Some(space) => (&input[..space], input[space..].trim()),
+            None => (input, ""),
+        };
+        match command {
+            "" => Ok("".into()),
+            "help" => Ok(HELP.into()),
+            "clip" | "track" | "arrangement" => self.define(input, 0),
+            _ if SETTINGS.contains(&command) => {
+                let block = "arrangement { ";
+                self.define(&format!("{}{} }}", block, input), block.len())
+            },
+            "note" => self.show_notes(rest),
+            "chord" => self.show_chord(rest),
+            "sc" => self.show_super_collider(rest),
+            "midi" => self.show_midi(rest),
+            "list" => Ok(self.list()),
+            "instruments" => Ok(INSTRUMENTS.join(" ")),
+            "listener" => Ok(SCLANG_LISTENER.into()),
+            "remove" => {
+                self.arrangement.remove(rest)?;
+                Ok(format!("Removed {}", rest))
+            },
+            "load" => {
+                let arrangement = read_arrangement(rest)?;
+                arrangement.validate()?;
+                self.arrangement = arrangement;
+                Ok(self.list())
+            },
+            "export" => {
+                let mut words = rest.split_whitespace();
+                let path = words.next().ok_or("Expected a file to write")?;
+                self.arrangement.validate()?;
+                export(&self.arrangement, path, words.next())?;
+                Ok(format!("Wrote {}", path))
+            },
+            "connect" => {
+                let address = if rest.is_empty() {
+                    format!("127.0.0.1:{}", SCLANG_PORT)
+                } else {
+                    rest.into()
+                };
+                self.sclang = Some(Sclang::connect(&address[..])
+                    .map_err(|error| format!("Could not connect to {}: {}",
+                        address, error))?);
+                Ok(format!("Sending to sclang at {}", address))
+            },
+            "play" => {
+                self.arrangement.validate()?;
+                self.ensure_connected()?;
+                if let Some(ref sclang) = self.sclang {
+                    sclang.play(&self.arrangement)
+                        .map_err(|error| error.to_string())?;
+                }
+                Ok("".into())
+            },
+            "stop" => {
+                self.ensure_connected()?;
+                if let Some(ref sclang) = self.sclang {
+                    sclang.stop().map_err(|error| error.to_string())?;
+                }
+                Ok("".into())
+            },
+            "clear" => {
+                self.arrangement = Arrangement::default();
+                Ok("".into())
+            },
+            _ => Err(format!("Unknown command \"{}\" (try \"help\")",
+                command)),
+        }
+    }
+
+    /// Adds definitions in the score language. The offset is the number of
+    /// characters added before the input on its first line, which are left
+    /// out of the columns of errors.
+    fn define(&mut self, source: &str, offset: usize)
+        -> Result<String, String> {
+        let known_clips: Vec<String> = self.arrangement.get_clips_ref().iter()
+            .map(|clip| clip.get_name()).collect();
+        let definitions = parse_definitions(source, &known_clips)
+            .map_err(|mut error| {
+                if error.line == 1 {
+                    error.column = error.column.saturating_sub(offset).max(1);
+                }
+                error.to_string()
+            })?;
+        let mut shown: Vec<String> = Vec::new();
+        for clip in definitions.clips.iter() {
+            shown.push(format!("clip {}", clip.get_name()));
+            self.arrangement.set_clip(clip.clone());
+        }
+        for track in definitions.tracks.iter() {
+            shown.push(format!("track {}", track.get_name()));
+            self.arrangement.set_track(track.clone());
+        }
+        definitions.apply_settings(&mut self.arrangement);
+        if let Some(ref names) = definitions.track_order {
+            self.arrangement.set_track_order(names)?;
+        }
+        Ok(if shown.is_empty() {
+            "Updated the arrangement".into()
+        } else {
+            format!("Defined {}", shown.join(", "))
+        })
+    }
+
+    fn show_notes(&self, notes: &str) -> Result<String, String> {
+        if notes.is_empty() {
+            return Err("Expected a note".into());
+        }
+        let tuning = self.arrangement.get_tuning();
+        let a4_hz = self.arrangement.get_concert_pitch();
+        let mut lines: Vec<String> = Vec::new();
+        for note in notes.split_whitespace() {
+            let note: Note = note.parse()?;
+            let frequency = match tuning.frequency(&note, a4_hz) {
+                Some(frequency) => format!("{:.3} Hz", frequency),
+                None => "not in the tuning".into(),
+            };
+            lines.push(format!("{}: MIDI {}, {}", note,
+                note.get_midi_value(), frequency));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn show_chord(&self, chord: &str) -> Result<String, String> {
+        let words: Vec<&str> = chord.split_whitespace().collect();
+        let chord = if words.len() == 1 {
+            parse_chord_symbol(words[0])
+                .ok_or_else(|| format!("Invalid chord \"{}\"", words[0]))?
+        } else {
+            let notes = words.iter().map(|note| note.parse::<Note>())
+                .collect::<Result<Vec<Note>, String>>()?;
+            Chord::identify(&notes)
+                .ok_or("The notes do not form a known chord")?
+        };
+        let notes: Vec<String> = chord.get_voicing(0).iter()
+            .map(|note| note.to_string()).collect();
+        let midi: Vec<i8> = chord.play().iter()
+            .map(|note| note.get_midi_value()).collect();
+        Ok(format!("{}: {} (MIDI {:?})", chord_symbol(&chord), notes.join(" "),
+            midi))
+    }
+
+    fn show_super_collider(&self, name: &str) -> Result<String, String> {
+        let arrangement = &self.arrangement;
+        if name.is_empty() {
+            return Ok(arrangement_to_super_collider(arrangement));
+        }
+        if let Some(clip) = arrangement.get_clip(name) {
+            return Ok(arranged_clip_to_super_collider(clip, arrangement));
+        }
+        match self.find_track(name) {
+            Some(index) => Ok(track_to_super_collider(
+                &arrangement.get_tracks_ref()[index])),
+            None => Err(format!("Nothing is named \"{}\"", name)),
+        }
+    }
+
+    /// Lists the start, duration and MIDI values of every step of a clip or
+    /// every note of a track, in fractions of a whole note
+    fn show_midi(&self, name: &str) -> Result<String, String> {
+        let mut lines: Vec<String> = Vec::new();
+        if let Some(clip) = self.arrangement.get_clip(name) {
+            let mut start = Time::zero();
+            match *clip {
+                Clip::Instrument(_, _, ref melody, ref durations) => {
+                    for (notes, duration) in melody.iter().zip(durations) {
+                        let notes = match *notes {
+                            Some(ref notes) => format!("{:?}", notes.iter()
+                                .map(|note| note.get_midi_value())
+                                .collect::<Vec<i8>>()),
+                            None => "rest".into(),
+                        };
+                        lines.push(format!("{:<8} {:<8} {}", start, duration,
+                            notes));
+                        start += *duration;
+                    }
+                },
+                Clip::Empty(_, duration) =>
+                    lines.push(format!("{:<8} {:<8} rest", start, duration)),
+                Clip::Drums(_, ref pattern) => {
+                    for hit in pattern.get_hits().iter() {
+                        lines.push(format!("{:<8} {:<8} {}", hit.start,
+                            pattern.step, pattern.lanes[hit.lane].key));
+                    }
+                },
+                Clip::File(_, ref path) =>
+                    return Err(format!("\"{}\" plays the audio file {}", name,
+                        path)),
+            }
+        } else {
+            let index = self.find_track(name)
+                .ok_or_else(|| format!("Nothing is named \"{}\"", name))?;
+            for note in self.arrangement.get_timed_notes().iter()
+                .filter(|note| note.track == index) {
+                lines.push(format!("{:<8} {:<8} {}", note.start,
+                    note.duration, note.pitch.get_midi_value()));
+            }
+        }
+        Ok(format!("{:<8} {:<8} midi\n{}", "start", "duration",
+            lines.join("\n")))
+    }
+
+    /// Connects to sclang on the default port unless already connected
+    fn ensure_connected(&mut self) -> Result<(), String> {
+        if self.sclang.is_none() {
+            self.execute("connect")?;
+        }
+        Ok(())
+    }
+
+    fn find_track(&self, name: &str) -> Option<usize> {
+        self.arrangement.get_tracks_ref().iter()
+            .position(|track| track.get_name() == name)
+    }
+
+    fn list(&self) -> String {
+        let clips: Vec<String> = self.arrangement.get_clips_ref().iter()
+            .map(|clip| clip.get_name()).collect();
+        let tracks: Vec<String> = self.arrangement.get_tracks_ref().iter()
+            .map(|track| format!("{} {{ {} }}", track.get_name(),
+                track.get_names_ref().join(" "))).collect();
+        format!("clips: {}\ntracks: {}", clips.join(", "), tracks.join(", "))
+    }
+}
+
+/// True if a command is finished, false while it has unclosed braces or
+/// brackets and more lines are needed
+pub fn is_complete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut in_text = false;
+    for line in input.lines() {
+        let line = match line.find("//") {
+            Some(comment) if !in_text => &line[..comment],
+            _ => line,
+        };
+        for c in line.chars() {
+            match c {
+                '"' => in_text = !in_text,
+                '{' | '[' if !in_text => depth += 1,
+                '}' | ']' if !in_text => depth -= 1,
+                _ => (),
+            }
+        }
+    }
+    depth <= 0 && !in_text
+}
@@ -1,19 +1,48 @@
 /// This file specifies a set of templates for relevant SuperCollider, to which
 /// the DAWPL code will later "compile". We must define them as macros (and not
 /// constant string references) because of the nature of the format! macro.
-macro_rules! k_instruments {
-// Synth from this video: "https://www.youtube.com/watch?v=nB_bVJ1c1Rg"
+macro_rules! k_synth_def_template {
+// Every instrument becomes a SynthDef whose output goes to its out bus
+    () => ("SynthDef.new(\\{name}, {{
+    arg {arguments};
+    Out.ar(out, {output});
+}}).add;")
+}
+
+macro_rules! k_play_buf_def {
+    () => ("SynthDef.new(\\dawplPlayBuf, {
+    arg out=0, bufnum=0, amp=1, pan=0;
+    var sig;
+    sig = PlayBuf.ar(2, bufnum, BufRateScale.kr(bufnum), doneAction:2);
+    Out.ar(out, Balance2.ar(sig[0], sig[1], pan, amp));
+}).add;")
+}
+
+// Effect chains read a stereo bus and write the result to another, and to
+// the returns they send to
+
+macro_rules! k_effects_def_template {
+    () => ("SynthDef.new(\\{name}, {{
+    arg in=0, out=0{arguments};
+    var sig = In.ar(in, 2);
+{effects}{sends}    Out.ar(out, sig);
+}}).add;")
+}
+
+// An automation lane plays its envelope into a control bus, holding its last
+// value once the envelope has ended
+
+macro_rules! k_automation_def_template {
+    () => ("SynthDef.new(\\{name}, {{
+    arg out=0;
+    Out.kr(out, EnvGen.kr({envelope}));
+}}).add;")
+}
+
+macro_rules! k_synth_defs_template {
     () => ("
 (
-SynthDef.new(\\sine, {
-    arg freq=440, atk=0.005, rel=0.3, amp=1, pan=0;
-    var sig, env;
-    sig = SinOsc.ar(freq);
-    env = EnvGen.kr(Env.new([0, 1, 0], [atk, rel], [1, -1]), doneAction:2);
-    sig = Pan2.ar(sig, pan, amp);
-    sig = sig * env;
-    Out.ar(0, sig);
-}).add;
+{synth_defs}
 )
 ")
 }
@@ -28,35 +57,148 @@ macro_rules! k_instrument_template {
 ")
 }
 
+macro_rules! k_instrument_freq_template {
+    () => ("
+{var_name} = Pbind(
+    \\instrument, \\{instrument_name},
+    \\dur, Pseq({dur}),
+    \\freq, Pseq({freqs}),
+);
+")
+}
+
+// The buffer is read as soon as the clip is declared, and the pattern is
+// only built when its turn comes in the track (Plazy), by which time the
+// length of the file is known. Durations are in beats, hence the tempo.
 macro_rules! k_audio_file_template {
     () => ("
-{var_name} = {{PlayBuf.ar(2, Buffer.read(s, {filepath}))}};
+~{var_name}Buffer = Buffer.read(s, {filepath});
+{var_name} = Plazy({{Pbind(
+    \\instrument, \\dawplPlayBuf,
+    \\bufnum, Pseq([~{var_name}Buffer]),
+    \\dur, ~{var_name}Buffer.duration * thisThread.clock.tempo,
+    \\amp, 1,
+)}});
 ")
 }
 
 macro_rules! k_empty_clip_template {
     () => ("
-{var_name} = (note:Rest(), dur:{dur});
+{var_name} = Pbind(
+    \\dur, Pseq([{dur}]),
+    \\note, Rest(),
+);
+")
+}
+
+// A transformed instrument clip gives every event the time until the next
+// (dur), how long it sounds (sustain) and its level (amp). Notes are played by
+// MIDI value or by frequency (key).
+
+macro_rules! k_transformed_instrument_template {
+    () => ("
+{var_name} = Pbind(
+    \\instrument, \\{instrument_name},
+    \\dur, Pseq({dur}),
+    \\sustain, Pseq({sustain}),
+    \\amp, Pseq({amps}),
+    \\{key}, Pseq({notes}),
+);
+")
+}
+
+// A drum clip plays a Pbind for each of its lanes at once. Steps that rest
+// have a Rest() amplitude, and steps with a probability choose between their
+// amplitude and a rest every time they play.
+
+macro_rules! k_drum_clip_template {
+    () => ("
+{var_name} = Ppar([
+{lanes}
+]);
 ")
 }
 
-// Pseq([v1, v2, .., vN]).do({arg thing; thing.play}).play (sequencing)
+macro_rules! k_drum_lane_template {
+    () => ("Pbind(
+    \\instrument, \\{instrument_name},
+    \\dur, {dur},
+    \\midinote, {midi_note},
+    \\amp, Pseq([{amps}]),
+)")
+}
+
+macro_rules! k_drum_chance_template {
+    () => ("Pwrand([{amp}, Rest()], [{probability}, {rest_probability}])")
+}
+
+// Pseq([v1, v2, .., vN]) plays each clip once its predecessor has ended
 
 macro_rules! k_track_template {
     () => ("
-{track_name} = Pseq({clips}).do({{arg currClip; currClip.play}});
+{track_name} = Pseq({clips});
 ")
 }
 
-// [v1, v2, ..., vN].do({arg thing; thing.play})
+// Automation synths run in a group before the default group, so that the
+// notes and effects that read their buses see this block's values. They start
+// together with the arrangement.
+
+macro_rules! k_automation_group_template {
+    () => ("~dawplAutomation = Group.before(s.defaultGroup);")
+}
+
+macro_rules! k_control_bus_template {
+    () => ("{bus} = Bus.control(s, 1);")
+}
+
+macro_rules! k_automation_synth_template {
+    () => ("Synth.tail(~dawplAutomation, \\{synth_def}, [\\out, {bus}]);")
+}
+
+// Effect synths run in a group after the default group, in which patterns
+// play, so that they process what the patterns play. Each is added to the
+// tail of the group in the order clips, tracks, groups, returns and master,
+// so that each stage runs after every stage that writes to its bus.
+
+macro_rules! k_effects_group_template {
+    () => ("~dawplEffects = Group.after(s.defaultGroup);")
+}
+
+macro_rules! k_bus_template {
+    () => ("{bus} = Bus.audio(s, 2);")
+}
+
+macro_rules! k_effects_synth_template {
+    () => ("Synth.tail(~dawplEffects, \\{synth_def}, [\\in, {bus}, \\out, \
+{destination}{sends}{controls}]);")
+}
+
+// Pset sends a clip to the bus of the effects it goes through first
+
+macro_rules! k_routed_clip_template {
+    () => ("Pset(\\out, {bus}, {clip_name})")
+}
+
+// Pset maps a parameter of the notes of a clip to an automation bus, which
+// they follow for as long as they play
+
+macro_rules! k_mapped_clip_template {
+    () => ("Pset(\\{parameter}, {bus}.asMap, {clip})")
+}
+
+// Ppar([t1, t2, ..., tN]) plays every track at the same time
 
 macro_rules! k_arrangement_template {
     () => ("
 {instruments}
 (
 {variable_declarations}
+TempoClock.default.tempo = {tempo};
 {clip_declarations}
+{automation}
+{effects}
 {track_declarations}
-{track_names}.do({{arg currTrack; currTrack.play}})
+Ppar({track_names}).play;
 )")
 }
\ No newline at end of file
@@ -8,10 +8,18 @@ use note::*;
 use scale::*;
 use track::*;
 
-/// This macro is used to conveniently define notes e.g. n!(C4), n!(Ds4)
+/// This macro is used to conveniently define notes e.g. n!(C4), n!(Ds4),
+/// n!(F##4), n!(Bbb3), n!(C-1), n!(G9), using the syntax accepted by
+/// Note::from_str. Notes can also be given as strings e.g. n!("E4-14c").
 macro_rules! n {
-    ($note:ident) => {{
-        let name_octave: String = String::from(stringify!($note));
+    ($note:literal) => {{
+        let result_note: Note = ($note).parse().unwrap();
+        result_note
+    }};
+    ($($note:tt)+) => {{
+        // tokens such as "C - 1" are stringified with spaces between them
+        let name_octave: String = stringify!($($note)+).chars()
+            .filter(|c| !c.is_whitespace()).collect();
         let result_note: Note = name_octave.parse().unwrap();
         result_note
     }}
@@ -29,13 +37,32 @@ macro_rules! chord {
 }
 
 /// Shorthand to express an instrument clip by denoting a name for the clip,
-/// the name of the desired instrument, a list of notes (MIDI), and their
-/// respective durations.
+/// the desired instrument, a list of notes (see play!), and their respective
+/// durations. The instrument is either the name of an instrument of the
+/// library (see instruments.rs) or an expression giving an InstrumentHandle,
+/// e.g. lead.clone().
 macro_rules! instr_clip {
     ($clip_name:ident, $instr_name:ident, $notes:expr, $durations:expr) => {{
         Clip::Instrument(String::from(stringify!($clip_name)),
-            String::from(stringify!($instr_name)),
-            $notes, $durations)
+            $crate::instruments::$instr_name(), $notes, $durations)
+    }};
+    ($clip_name:ident, $instrument:expr, $notes:expr, $durations:expr) => {{
+        Clip::Instrument(String::from(stringify!($clip_name)),
+            $instrument.clone(), $notes, $durations)
+    }}
+}
+
+/// Shorthand to express a drum clip by denoting a name for the clip, its
+/// number of steps, the length of a step and the steps of each voice (see
+/// drums.rs), e.g. a bar of sixteenth notes in 4/4:
+/// drum_clip!(beat, 16, Duration::new(1, 16), Kick => "x... x... x... x...",
+///     Snare => ".... x... .... x...", HiHat => "x.x. x.x. x.x. x.x.")
+macro_rules! drum_clip {
+    ($clip_name:ident, $steps:expr, $step:expr,
+        $($voice:ident => $lane:expr),*) => {{
+        let pattern = $crate::drums::DrumPattern::new($steps, $step)
+            $(.lane($crate::drums::DrumVoice::$voice, $lane).unwrap())*;
+        Clip::Drums(String::from(stringify!($clip_name)), pattern)
     }}
 }
 
@@ -51,31 +78,80 @@ macro_rules! track {
     }}
 }
 /// A macro that maps letters to durations, which can be element-wise multiplied
-/// to align with different tempos (might later work tempo into the macro)
+/// to align with different tempos (might later work tempo into the macro).
+/// The letters are the values of Beat (W, H, Q, E, S, T for thirty-second and
+/// X for sixty-fourth notes), and any other letter is a compile error. Values
+/// can be dotted (Q. or Q..) and tied (H + E), and n-tuplets are written as
+/// n(...) in the time of the largest power of two below n, or as n:m(...) in
+/// the time of m e.g. rhythm![Q., E, 3(E, E, E), 5:4(S, S, S, S, S), H + E].
+/// The durations are exact (see duration.rs), so that tuplets fill a bar.
+/// rhythm!(E(3, 8), E) gives the durations of the Euclidean rhythm E(3, 8) in
+/// eighth notes, and rhythm!(E(3, 8, 2), E) the same rhythm rotated by two
+/// steps (see rhythm.rs for other generators).
 macro_rules! rhythm {
-    ($( $beat:ident),*) => {{
-        let mut durations: Vec<f64> = Vec::new();
-        $( 
-            let next_dur: f64 = match stringify!($beat) {
-                "W" => 1.0,
-                "H" => 0.5,
-                "Q" => 0.25,
-                "E" => 0.125,
-                "S" => 0.0625,
-                _ => 0.0,
-            };
-            durations.push(next_dur);
-        )*
+    (E($hits:expr, $steps:expr), $beat:ident) => {{
+        rhythm!(E($hits, $steps, 0), $beat)
+    }};
+    (E($hits:expr, $steps:expr, $rotation:expr), $beat:ident) => {{
+        $crate::rhythm::Rhythm::euclidean($hits, $steps, $rotation,
+            $crate::note::Beat::$beat.get_duration()).durations()
+    }};
+    // a value, tied to the one before it if $tie is true, and scaled by $scale
+    // within tuplets
+    (@value $durations:ident [$scale:expr] $tie:tt) => {};
+    (@value $durations:ident [$scale:expr] $tie:tt
+        $n:tt : $m:tt ($($values:tt)*) $($rest:tt)*) => {
+        rhythm!(@value $durations [$scale * $crate::duration::Duration::new(
+            $m, $n)] $tie $($values)*);
+        rhythm!(@next $durations [$scale] $($rest)*);
+    };
+    (@value $durations:ident [$scale:expr] $tie:tt
+        $n:tt ($($values:tt)*) $($rest:tt)*) => {
+        rhythm!(@value $durations [$scale] $tie
+            $n : ($crate::duration::tuplet_space($n)) ($($values)*)
+            $($rest)*);
+    };
+    (@value $durations:ident [$scale:expr] $tie:tt
+        $beat:ident ... $($rest:tt)*) => {
+        rhythm!(@beat $durations [$scale] $tie $beat 3 $($rest)*);
+    };
+    (@value $durations:ident [$scale:expr] $tie:tt
+        $beat:ident .. $($rest:tt)*) => {
+        rhythm!(@beat $durations [$scale] $tie $beat 2 $($rest)*);
+    };
+    (@value $durations:ident [$scale:expr] $tie:tt
+        $beat:ident . $($rest:tt)*) => {
+        rhythm!(@beat $durations [$scale] $tie $beat 1 $($rest)*);
+    };
+    (@value $durations:ident [$scale:expr] $tie:tt
+        $beat:ident $($rest:tt)*) => {
+        rhythm!(@beat $durations [$scale] $tie $beat 0 $($rest)*);
+    };
+    (@beat $durations:ident [$scale:expr] $tie:tt $beat:ident $dots:tt
+        $($rest:tt)*) => {
+        $crate::duration::push_duration(&mut $durations, $tie,
+            $crate::note::Beat::$beat.get_duration().dotted($dots) * $scale);
+        rhythm!(@next $durations [$scale] $($rest)*);
+    };
+    // what follows a value: nothing, another value or a tied value
+    (@next $durations:ident [$scale:expr]) => {};
+    (@next $durations:ident [$scale:expr] , $($rest:tt)*) => {
+        rhythm!(@value $durations [$scale] false $($rest)*);
+    };
+    (@next $durations:ident [$scale:expr] + $($rest:tt)*) => {
+        rhythm!(@value $durations [$scale] true $($rest)*);
+    };
+    ($($values:tt)*) => {{
+        let mut durations: Vec<$crate::duration::Duration> = Vec::new();
+        rhythm!(@value durations [$crate::duration::Duration::new(1, 1)] false
+            $($values)*);
         durations
     }}
 }
-// TODO: macros for defining synths => layer other aspects on top of SuperCollider
-// TODO: add audio effects.
-
 // TODO: arrangement macro
 
 impl Playable for () { // to quiet compiler complaints
-    fn play(&self) -> Vec<i8> {
+    fn play(&self) -> Vec<Note> {
         Vec::new()
     } 
 }
@@ -84,16 +160,16 @@ impl Playable for () { // to quiet compiler complaints
 /// An empty pair of parens "()" can be used to denote rests.
 macro_rules! play {
     ($( $notes:expr),*) => {{
-        let mut midi_values: Vec<Option<Vec<i8>>> = Vec::new();
+        let mut melody: Vec<Option<Vec<Note>>> = Vec::new();
         $( 
             //println!("Notes stringified: {}", stringify!($notes));
-            let elem: Option<Vec<i8>> = match stringify!($notes) {
+            let elem: Option<Vec<Note>> = match stringify!($notes) {
                 "()" => None,
                 _ => Some($notes.play()),
             };
-            midi_values.push(elem);
+            melody.push(elem);
         )*
-        midi_values
+        melody
     }}
 
 }
@@ -0,0 +1,399 @@
+/// A small language for describing synths in Rust, which compiles to
+/// SuperCollider SynthDefs. A synth is a graph of unit generators (UGens)
+/// built from the functions below, e.g. a decaying, filtered saw wave:
+///
+/// ```text
+/// let output = pan2(rlpf(saw(param("freq")), 2000.0, 0.3), param("pan"),
+///     param("amp")) * perc(0.01, 0.5);
+/// let lead = Instrument::new("lead", &[("freq", 440.0)], output).handle();
+/// ```
+///
+/// Signals combine with +, -, * and / (also with plain numbers). UGens run
+/// at audio rate unless turned into control rate with kr(); any UGen not
+/// covered here can be made with ugen(). Instruments are referred to from
+/// clips by InstrumentHandle, and an arrangement only defines the SynthDefs
+/// its clips use.
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
+
+/// Defaults of parameters that are used by a synth without being declared,
+/// as set by SuperCollider's default event. Other undeclared parameters
+/// default to 0.
+const STANDARD_PARAMETERS: [(&str, f64); 5] = [("freq", 440.0),
+    ("amp", 0.1), ("pan", 0.0), ("gate", 1.0), ("out", 0.0)];
+
+/// Tells EnvGen to free its synth once the envelope has ended
+const FREE_SELF: f64 = 2.0;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Rate {
+    Audio,
+    Control,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Signal {
+    Constant(f64),
+    /// A parameter of the synth, set when the synth is played e.g. freq
+    Parameter(String),
+    /// A unit generator: the name of its SuperCollider class, its rate and
+    /// its inputs in the order the class takes them
+    UGen(String, Rate, Vec<Signal>),
+    Binary(Operator, Box<Signal>, Box<Signal>),
+    /// A unary operation, by the name of its SuperCollider method e.g. tanh
+    Unary(String, Box<Signal>),
+    /// Several channels e.g. [left, right]
+    Array(Vec<Signal>),
+    /// An envelope (Env) made of levels, the times between them, the
+    /// curvature of each segment (0 is linear), and the level held until
+    /// the gate closes, if any
+    Envelope(Vec<Signal>, Vec<Signal>, Vec<f64>, Option<usize>),
+}
+
+impl From<f64> for Signal {
+    fn from(value: f64) -> Signal {
+        Signal::Constant(value)
+    }
+}
+
+impl Signal {
+    /// Runs a UGen at control rate, for signals that only shape other
+    /// signals e.g. envelopes and LFOs
+    pub fn kr(self) -> Signal {
+        match self {
+            Signal::UGen(name, _, inputs) =>
+                Signal::UGen(name, Rate::Control, inputs),
+            signal => signal,
+        }
+    }
+
+    /// Applies a unary operation e.g. signal.unary("tanh") to saturate
+    pub fn unary(self, operation: &str) -> Signal {
+        Signal::Unary(operation.into(), Box::new(self))
+    }
+
+    pub fn to_super_collider(&self) -> String {
+        match *self {
+            Signal::Constant(value) => value.to_string(),
+            Signal::Parameter(ref name) => name.clone(),
+            Signal::UGen(ref name, rate, ref inputs) => format!("{}.{}({})",
+                name, if rate == Rate::Audio { "ar" } else { "kr" },
+                signal_list(inputs)),
+            Signal::Binary(operator, ref left, ref right) => {
+                let symbol = match operator {
+                    Operator::Add => "+",
+                    Operator::Sub => "-",
+                    Operator::Mul => "*",
+                    Operator::Div => "/",
+                };
+                format!("({} {} {})", left.to_super_collider(), symbol,
+                    right.to_super_collider())
+            },
+            Signal::Unary(ref operation, ref input) =>
+                format!("{}.{}", input.to_super_collider(), operation),
+            Signal::Array(ref channels) =>
+                format!("[{}]", signal_list(channels)),
+            Signal::Envelope(ref levels, ref times, ref curves,
+                release_node) => {
+                let curves: Vec<String> = curves.iter()
+                    .map(|curve| curve.to_string()).collect();
+                format!("Env.new([{}], [{}], [{}], {})", signal_list(levels),
+                    signal_list(times), curves.join(", "), release_node
+                        .map_or("nil".into(), |node| node.to_string()))
+            },
+        }
+    }
+
+    /// Adds the names of the parameters the signal uses to a list
+    fn collect_parameters(&self, parameters: &mut Vec<String>) {
+        let mut add = |signals: &[Signal]| for signal in signals.iter() {
+            signal.collect_parameters(parameters);
+        };
+        match *self {
+            Signal::Constant(_) => (),
+            Signal::Parameter(ref name) => {
+                if !parameters.contains(name) {
+                    parameters.push(name.clone());
+                }
+            },
+            Signal::UGen(_, _, ref inputs) => add(inputs),
+            Signal::Binary(_, ref left, ref right) => {
+                left.collect_parameters(parameters);
+                right.collect_parameters(parameters);
+            },
+            Signal::Unary(_, ref input) => input.collect_parameters(parameters),
+            Signal::Array(ref channels) => add(channels),
+            Signal::Envelope(ref levels, ref times, _, _) => {
+                add(levels);
+                add(times);
+            },
+        }
+    }
+}
+
+fn signal_list(signals: &[Signal]) -> String {
+    let signals: Vec<String> = signals.iter()
+        .map(|signal| signal.to_super_collider()).collect();
+    signals.join(", ")
+}
+
+macro_rules! signal_operator {
+    ($trait_name:ident, $method:ident, $operator:expr) => {
+        impl<T: Into<Signal>> $trait_name<T> for Signal {
+            type Output = Signal;
+            fn $method(self, other: T) -> Signal {
+                Signal::Binary($operator, Box::new(self),
+                    Box::new(other.into()))
+            }
+        }
+
+        impl $trait_name<Signal> for f64 {
+            type Output = Signal;
+            fn $method(self, other: Signal) -> Signal {
+                Signal::Binary($operator, Box::new(Signal::Constant(self)),
+                    Box::new(other))
+            }
+        }
+    }
+}
+
+signal_operator!(Add, add, Operator::Add);
+signal_operator!(Sub, sub, Operator::Sub);
+signal_operator!(Mul, mul, Operator::Mul);
+signal_operator!(Div, div, Operator::Div);
+
+/// A parameter of the synth e.g. param("freq")
+pub fn param(name: &str) -> Signal {
+    Signal::Parameter(name.into())
+}
+
+/// Any audio rate UGen e.g. ugen("LFNoise1", vec![4.0.into()])
+pub fn ugen(name: &str, inputs: Vec<Signal>) -> Signal {
+    Signal::UGen(name.into(), Rate::Audio, inputs)
+}
+
+// Oscillators
+
+pub fn sin_osc<F: Into<Signal>>(freq: F) -> Signal {
+    ugen("SinOsc", vec![freq.into()])
+}
+
+/// Band-limited sawtooth wave
+pub fn saw<F: Into<Signal>>(freq: F) -> Signal {
+    ugen("Saw", vec![freq.into()])
+}
+
+/// Band-limited pulse wave; a width of 0.5 gives a square wave
+pub fn pulse<F: Into<Signal>, W: Into<Signal>>(freq: F, width: W) -> Signal {
+    ugen("Pulse", vec![freq.into(), width.into()])
+}
+
+pub fn tri<F: Into<Signal>>(freq: F) -> Signal {
+    ugen("LFTri", vec![freq.into()])
+}
+
+// Filters
+
+pub fn lpf<I: Into<Signal>, F: Into<Signal>>(input: I, freq: F) -> Signal {
+    ugen("LPF", vec![input.into(), freq.into()])
+}
+
+pub fn hpf<I: Into<Signal>, F: Into<Signal>>(input: I, freq: F) -> Signal {
+    ugen("HPF", vec![input.into(), freq.into()])
+}
+
+/// Resonant low pass filter; rq is the reciprocal of the filter's Q
+pub fn rlpf<I: Into<Signal>, F: Into<Signal>, Q: Into<Signal>>(input: I,
+    freq: F, rq: Q) -> Signal {
+    ugen("RLPF", vec![input.into(), freq.into(), rq.into()])
+}
+
+pub fn bpf<I: Into<Signal>, F: Into<Signal>, Q: Into<Signal>>(input: I,
+    freq: F, rq: Q) -> Signal {
+    ugen("BPF", vec![input.into(), freq.into(), rq.into()])
+}
+
+// Envelopes
+
+/// Plays an envelope at control rate, freeing the synth when it ends. The
+/// envelope holds at its release node (if it has one) while the gate is open.
+pub fn env_gen<G: Into<Signal>>(envelope: Signal, gate: G) -> Signal {
+    // EnvGen.kr(envelope, gate, levelScale, levelBias, timeScale, doneAction)
+    ugen("EnvGen", vec![envelope, gate.into(), 1.0.into(), 0.0.into(),
+        1.0.into(), FREE_SELF.into()]).kr()
+}
+
+/// An envelope through the given levels, taking the given times between
+/// them, with a curvature for every segment
+pub fn envelope(levels: Vec<Signal>, times: Vec<Signal>, curves: Vec<f64>)
+    -> Signal {
+    Signal::Envelope(levels, times, curves, None)
+}
+
+/// A percussive envelope that rises for the attack time and falls for the
+/// release time, regardless of how long the note lasts
+pub fn perc<A: Into<Signal>, R: Into<Signal>>(attack: A, release: R)
+    -> Signal {
+    env_gen(envelope(vec![0.0.into(), 1.0.into(), 0.0.into()],
+        vec![attack.into(), release.into()], vec![-4.0, -4.0]), 1.0)
+}
+
+/// An envelope that rises for the attack time, falls to the sustain level
+/// for the decay time, holds until the gate parameter closes at the end of
+/// the note, then falls for the release time
+pub fn adsr<A, D, S, R>(attack: A, decay: D, sustain: S, release: R) -> Signal
+    where A: Into<Signal>, D: Into<Signal>, S: Into<Signal>, R: Into<Signal> {
+    let sustain = sustain.into();
+    env_gen(Signal::Envelope(vec![0.0.into(), 1.0.into(), sustain.clone(),
+        0.0.into()], vec![attack.into(), decay.into(), release.into()],
+        vec![-4.0, -4.0, -4.0], Some(2)), param("gate"))
+}
+
+/// A control rate line from start to end over the given number of seconds,
+/// which then holds at end
+pub fn line<S: Into<Signal>, E: Into<Signal>, D: Into<Signal>>(start: S,
+    end: E, duration: D) -> Signal {
+    ugen("Line", vec![start.into(), end.into(), duration.into()]).kr()
+}
+
+/// Like line() but exponential, which sounds even for pitches; start and
+/// end must not be 0
+pub fn x_line<S: Into<Signal>, E: Into<Signal>, D: Into<Signal>>(start: S,
+    end: E, duration: D) -> Signal {
+    ugen("XLine", vec![start.into(), end.into(), duration.into()]).kr()
+}
+
+// Noise
+
+pub fn white_noise() -> Signal {
+    ugen("WhiteNoise", Vec::new())
+}
+
+pub fn pink_noise() -> Signal {
+    ugen("PinkNoise", Vec::new())
+}
+
+pub fn brown_noise() -> Signal {
+    ugen("BrownNoise", Vec::new())
+}
+
+/// Random impulses, on average the given number per second
+pub fn dust<D: Into<Signal>>(density: D) -> Signal {
+    ugen("Dust", vec![density.into()])
+}
+
+// Physical models
+
+/// Karplus-Strong plucked string: a burst of the input excites a delay line
+/// of the given delay time (1 / frequency), which rings for the decay time.
+/// Coef (-1 to 1) damps the high frequencies.
+pub fn pluck<I, D, T, C>(input: I, delay: D, decay: T, coef: C) -> Signal
+    where I: Into<Signal>, D: Into<Signal>, T: Into<Signal>, C: Into<Signal> {
+    // Pluck.ar(in, trig, maxdelaytime, delaytime, decaytime, coef); a single
+    // impulse plucks the string once
+    ugen("Pluck", vec![input.into(), ugen("Impulse", vec![0.0.into()]).kr(),
+        0.05.into(), delay.into(), decay.into(), coef.into()])
+}
+
+// Panning
+
+/// Places a mono signal between the left (-1) and right (1) channels
+pub fn pan2<I: Into<Signal>, P: Into<Signal>, L: Into<Signal>>(input: I,
+    position: P, level: L) -> Signal {
+    ugen("Pan2", vec![input.into(), position.into(), level.into()])
+}
+
+/// A synth that can play the notes of clips
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Instrument {
+    name: String,
+    /// Parameters and their defaults, including every parameter the output
+    /// uses
+    parameters: Vec<(String, f64)>,
+    output: Signal,
+}
+
+impl Instrument {
+    /// Makes an instrument from its output signal. Parameters that the
+    /// output uses but that are not given are declared with the defaults of
+    /// SuperCollider's default event (freq 440, amp 0.1, pan 0, gate 1), or
+    /// 0 for other parameters.
+    pub fn new(name: &str, parameters: &[(&str, f64)], output: Signal)
+        -> Instrument {
+        let mut parameters: Vec<(String, f64)> = parameters.iter()
+            .map(|&(name, default)| (name.to_string(), default)).collect();
+        let mut used: Vec<String> = Vec::new();
+        output.collect_parameters(&mut used);
+        for name in used.into_iter() {
+            if !parameters.iter().any(|parameter| parameter.0 == name) {
+                let default = STANDARD_PARAMETERS.iter()
+                    .find(|parameter| parameter.0 == name)
+                    .map_or(0.0, |parameter| parameter.1);
+                parameters.push((name, default));
+            }
+        }
+        Instrument {name: name.into(), parameters, output}
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_parameters(&self) -> &Vec<(String, f64)> {
+        &self.parameters
+    }
+
+    pub fn get_output(&self) -> &Signal {
+        &self.output
+    }
+
+    /// True if the instrument holds its notes until its gate parameter is
+    /// set to 0
+    pub fn is_gated(&self) -> bool {
+        self.parameters.iter().any(|parameter| parameter.0 == "gate")
+    }
+
+    /// Writes the instrument as a SynthDef that sends its output to the bus
+    /// given by its out parameter
+    pub fn to_super_collider(&self) -> String {
+        let mut arguments: Vec<String> = vec!["out=0".into()];
+        for &(ref name, default) in self.parameters.iter() {
+            if name != "out" {
+                arguments.push(format!("{}={}", name, default));
+            }
+        }
+        format!(k_synth_def_template!(), name=self.name,
+            arguments=arguments.join(", "),
+            output=self.output.to_super_collider())
+    }
+
+    pub fn handle(self) -> InstrumentHandle {
+        InstrumentHandle(Arc::new(self))
+    }
+}
+
+/// Shared reference to an instrument, by which clips say what plays them
+#[derive(Debug, PartialEq, Clone)]
+pub struct InstrumentHandle(Arc<Instrument>);
+
+impl InstrumentHandle {
+    pub fn get_name(&self) -> &String {
+        self.0.get_name()
+    }
+
+    pub fn get_instrument(&self) -> &Instrument {
+        &self.0
+    }
+}
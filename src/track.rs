@@ -12,7 +12,8 @@ use clip::*;
 use note::*;
 use scale::*;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Track {
     name: String,
     clip_names: Vec<String> // Gaps can be represented as empty clips
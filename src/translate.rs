@@ -1,11 +1,21 @@
 /// Translation module that compiles DAWPL to SuperCollider
 use arrangement::*;
+use automation::*;
 use chord::*;
 use clip::*;
+use drums::*;
+use duration::*;
+use effects::*;
+use groove::*;
 use note::*;
 use scale::*;
+use synth::*;
 use track::*;
 use super_collider_templates::*;
+use tuning::*;
+
+/// Part of its duration a note sounds for, SuperCollider's default legato
+const LEGATO: f64 = 0.8;
 
 pub fn arrangement_to_super_collider(arrangement: &Arrangement) -> String {
     // Handle appropriate variable declaration
@@ -21,24 +31,334 @@ pub fn arrangement_to_super_collider(arrangement: &Arrangement) -> String {
     // add clip declarations
     let mut clip_decl: String = "".into();
     for clip in arrangement.get_clips_ref().iter() {
-        clip_decl += &(clip_to_super_collider(&clip))[..];
+        clip_decl += &(arranged_clip_to_super_collider(clip,
+            arrangement))[..];
         clip_decl += "\n";
     }
     // add track declarations
     let mut track_decl: String = "".into();
     let mut track_name_str: String = "[".into(); // list of track names
+    let stages = effect_stages(arrangement);
     for track in arrangement.get_tracks_ref().iter() {
-        track_decl += &(track_to_super_collider(&track))[..];
+        track_decl += &(routed_track_to_super_collider(track,
+            arrangement, &stages))[..];
         track_decl += "\n";
         track_name_str += &format!("{},", track.get_name());
     }
     track_name_str += "]";
-    // put declarations together to form arrangement
-    format!(k_arrangement_template!(), instruments=k_instruments!(),
+    // put declarations together to form arrangement, durations are in whole
+    // notes so the clock counts whole notes per second
+    format!(k_arrangement_template!(),
+        instruments=synth_defs_to_super_collider(arrangement),
+        tempo=1.0 / arrangement.get_whole_note_seconds(),
         variable_declarations=var_decl, clip_declarations=clip_decl,
+        automation=automation_to_super_collider(arrangement),
+        effects=effects_to_super_collider(arrangement, &stages),
         track_declarations=track_decl, track_names=track_name_str)
 }
 
+/// A stage of the mix, which gets a bus of its own and a synth that applies
+/// its effects: a clip, track or master with effects, a track with sends or
+/// automated volume or pan, a group or a return
+struct EffectStage<'a> {
+    /// Name of the SynthDef of the stage
+    synth_def: String,
+    /// Where the effects of the stage are put
+    target: EffectTarget,
+    effects: &'a [Effect],
+    /// The bus the stage reads, the clip's within a given track
+    bus: String,
+    /// The bus the stage writes to, 0 for the speakers
+    destination: String,
+    /// Buses of the returns the stage sends to, and the levels it sends at
+    sends: Vec<(String, f64)>,
+    /// The track of a clip stage
+    track: Option<String>,
+    /// The clip of a clip stage
+    clip: Option<String>,
+}
+
+/// The bus every track, group and return goes to in the end
+fn master_bus(arrangement: &Arrangement) -> String {
+    if arrangement.get_effects(&EffectTarget::Master).is_empty() {
+        "0".into()
+    } else {
+        "~dawplMasterBus".into()
+    }
+}
+
+/// The bus a track goes to once its effects and sends are applied
+fn track_output(arrangement: &Arrangement, track: &str) -> String {
+    match arrangement.get_group_of(track) {
+        Some(group) => format!("~{}GroupBus", group.name),
+        None => master_bus(arrangement),
+    }
+}
+
+/// The bus the clips of a track without effects of their own go to
+fn track_input(arrangement: &Arrangement, track: &str) -> String {
+    let faded = arrangement.get_automation_lanes().iter().any(|lane|
+        match lane.target {
+            AutomationTarget::Volume(ref name) |
+            AutomationTarget::Pan(ref name) => name == track,
+            _ => false,
+        });
+    if arrangement.get_effects(&EffectTarget::Track(track.into())).is_empty()
+        && arrangement.get_sends(track).is_empty() && !faded {
+        track_output(arrangement, track)
+    } else {
+        format!("~{}Bus", track)
+    }
+}
+
+/// Lists the stages of the mix of an arrangement in the order they must run:
+/// clips, tracks, groups, returns and then the master. A clip with effects
+/// gets a stage in every track it is used by, so that each sends its output
+/// to its track.
+fn effect_stages(arrangement: &Arrangement) -> Vec<EffectStage<'_>> {
+    let mut clip_stages: Vec<EffectStage> = Vec::new();
+    let mut track_stages: Vec<EffectStage> = Vec::new();
+    let master = master_bus(arrangement);
+    for track in arrangement.get_tracks_ref().iter() {
+        let name = track.get_name();
+        let input = track_input(arrangement, name);
+        for clip_name in track.get_names_ref().iter() {
+            let clip_effects = arrangement.get_effects(
+                &EffectTarget::Clip(clip_name.clone()));
+            let exists = clip_stages.iter().any(|stage|
+                stage.track.as_ref() == Some(name) &&
+                stage.clip.as_ref() == Some(clip_name));
+            if !clip_effects.is_empty() && !exists {
+                clip_stages.push(EffectStage {synth_def: format!(
+                    "dawplEffects_{}_{}", name, clip_name),
+                    target: EffectTarget::Clip(clip_name.clone()),
+                    effects: clip_effects,
+                    bus: format!("~{}_{}Bus", name, clip_name),
+                    destination: input.clone(), sends: Vec::new(),
+                    track: Some(name.clone()), clip: Some(clip_name.clone())});
+            }
+        }
+        let output = track_output(arrangement, name);
+        if input != output {
+            track_stages.push(EffectStage {synth_def: format!(
+                "dawplEffects_{}", name),
+                target: EffectTarget::Track(name.clone()),
+                effects: arrangement.get_effects(
+                &EffectTarget::Track(name.clone())), bus: input,
+                destination: output, sends: arrangement.get_sends(name)
+                .iter().map(|send| (format!("~{}ReturnBus", send.destination),
+                send.level)).collect(),
+                track: Some(name.clone()), clip: None});
+        }
+    }
+    clip_stages.extend(track_stages);
+    for group in arrangement.get_groups().iter() {
+        clip_stages.push(EffectStage {synth_def: format!("dawplGroup_{}",
+            group.name), target: EffectTarget::Group(group.name.clone()),
+            effects: arrangement.get_effects(
+            &EffectTarget::Group(group.name.clone())),
+            bus: format!("~{}GroupBus", group.name),
+            destination: master.clone(), sends: Vec::new(), track: None,
+            clip: None});
+    }
+    for name in arrangement.get_returns().iter() {
+        clip_stages.push(EffectStage {synth_def: format!("dawplReturn_{}",
+            name), target: EffectTarget::Return(name.clone()),
+            effects: arrangement.get_effects(
+            &EffectTarget::Return(name.clone())),
+            bus: format!("~{}ReturnBus", name), destination: master.clone(),
+            sends: Vec::new(), track: None, clip: None});
+    }
+    if master != "0" {
+        clip_stages.push(EffectStage {synth_def: "dawplEffectsMaster".into(),
+            target: EffectTarget::Master,
+            effects: arrangement.get_effects(&EffectTarget::Master),
+            bus: master, destination: "0".into(), sends: Vec::new(),
+            track: None, clip: None});
+    }
+    clip_stages
+}
+
+/// The bus an automation lane plays into, by the lane's index
+fn automation_bus(index: usize) -> String {
+    format!("~dawplAutomation{}Bus", index)
+}
+
+/// The automation lanes that set arguments of the synth of a stage, by the
+/// index of the lane, with the argument each sets
+fn stage_automation(arrangement: &Arrangement, stage: &EffectStage)
+    -> Vec<(usize, String)> {
+    let mut controls: Vec<(usize, String)> = Vec::new();
+    let is_track = |name: &String| match stage.target {
+        EffectTarget::Track(ref track) => track == name,
+        _ => false,
+    };
+    for (i, lane) in arrangement.get_automation_lanes().iter().enumerate() {
+        let argument = match lane.target {
+            AutomationTarget::EffectParameter(ref target, effect, parameter)
+                if target == &stage.target =>
+                effect_argument(effect, parameter),
+            AutomationTarget::Volume(ref track) if is_track(track) =>
+                "volume".into(),
+            AutomationTarget::Pan(ref track) if is_track(track) =>
+                "pan".into(),
+            _ => continue,
+        };
+        controls.push((i, argument));
+    }
+    controls
+}
+
+/// Allocates the buses of the automation lanes of an arrangement and starts
+/// the synths that play the lanes into them
+fn automation_to_super_collider(arrangement: &Arrangement) -> String {
+    let lanes = arrangement.get_automation_lanes();
+    if lanes.is_empty() {
+        return "".into();
+    }
+    let mut lines: Vec<String> = vec![k_automation_group_template!().into()];
+    for i in 0..lanes.len() {
+        lines.push(format!(k_control_bus_template!(), bus=automation_bus(i)));
+    }
+    for i in 0..lanes.len() {
+        lines.push(format!(k_automation_synth_template!(),
+            synth_def=format!("dawplAutomation_{}", i),
+            bus=automation_bus(i)));
+    }
+    lines.join("\n")
+}
+
+/// Allocates the buses of the stages of the mix and starts their synths,
+/// with their automated arguments mapped to the buses of their lanes
+fn effects_to_super_collider(arrangement: &Arrangement,
+    stages: &[EffectStage]) -> String {
+    if stages.is_empty() {
+        return "".into();
+    }
+    let mut lines: Vec<String> = vec![k_effects_group_template!().into()];
+    for stage in stages.iter() {
+        lines.push(format!(k_bus_template!(), bus=stage.bus));
+    }
+    for stage in stages.iter() {
+        let mut sends: String = "".into();
+        for (i, send) in stage.sends.iter().enumerate() {
+            sends += &format!(", \\send{}, {}", i, send.0);
+        }
+        let mut controls: String = "".into();
+        for (lane, argument) in stage_automation(arrangement, stage) {
+            controls += &format!(", \\{}, {}.asMap", argument,
+                automation_bus(lane));
+        }
+        lines.push(format!(k_effects_synth_template!(),
+            synth_def=stage.synth_def, bus=stage.bus,
+            destination=stage.destination, sends=sends,
+            controls=controls));
+    }
+    lines.join("\n")
+}
+
+/// Translates a track whose clips are sent to the first stage of the mix
+/// they go through: their own effects, their track's, their group or the
+/// master. Automated parameters of the track's synths are mapped to the
+/// buses of their lanes.
+fn routed_track_to_super_collider(track: &Track, arrangement: &Arrangement,
+    stages: &[EffectStage]) -> String {
+    let name = track.get_name();
+    let input = track_input(arrangement, name);
+    let mut clip_name_str: String = "[".into();
+    for clip_name in track.get_names_ref().iter() {
+        let bus = stages.iter().find(|stage|
+            stage.track.as_ref() == Some(name) &&
+            stage.clip.as_ref() == Some(clip_name))
+            .map_or(&input, |stage| &stage.bus);
+        let mut clip: String = if bus == "0" {
+            clip_name.clone()
+        } else {
+            format!(k_routed_clip_template!(), bus=bus, clip_name=clip_name)
+        };
+        for (i, lane) in arrangement.get_automation_lanes().iter()
+            .enumerate() {
+            if let AutomationTarget::SynthParameter(ref track, ref parameter)
+                = lane.target {
+                if track == name {
+                    clip = format!(k_mapped_clip_template!(),
+                        parameter=parameter, bus=automation_bus(i),
+                        clip=clip);
+                }
+            }
+        }
+        clip_name_str += &clip;
+        clip_name_str += ",";
+    }
+    clip_name_str += "]";
+    format!(k_track_template!(), track_name=name, clips=clip_name_str)
+}
+
+/// Defines the SynthDefs of the instruments the clips of an arrangement are
+/// played with, each once, the SynthDef that plays audio files if the
+/// arrangement has any file clips, and the SynthDefs of its effects and
+/// automation lanes
+pub fn synth_defs_to_super_collider(arrangement: &Arrangement) -> String {
+    let mut instruments: Vec<&InstrumentHandle> = Vec::new();
+    let mut synth_defs: Vec<String> = Vec::new();
+    for clip in arrangement.get_clips_ref().iter() {
+        match *clip {
+            Clip::Instrument(_, ref instrument, _, _) => {
+                if !instruments.contains(&instrument) {
+                    instruments.push(instrument);
+                    synth_defs.push(instrument.get_instrument()
+                        .to_super_collider());
+                }
+            },
+            Clip::File(_, _) => {
+                let play_buf = String::from(k_play_buf_def!());
+                if !synth_defs.contains(&play_buf) {
+                    synth_defs.push(play_buf);
+                }
+            },
+            Clip::Empty(_, _) => (),
+            Clip::Drums(_, ref pattern) => {
+                for lane in pattern.lanes.iter() {
+                    if !instruments.contains(&&lane.instrument) {
+                        instruments.push(&lane.instrument);
+                        synth_defs.push(lane.instrument.get_instrument()
+                            .to_super_collider());
+                    }
+                }
+            },
+        }
+    }
+    for stage in effect_stages(arrangement).iter() {
+        let levels: Vec<f64> = stage.sends.iter().map(|send| send.1)
+            .collect();
+        let mut automated: Vec<(usize, usize)> = Vec::new();
+        let mut fader = false;
+        for lane in arrangement.get_automation_lanes().iter() {
+            match lane.target {
+                AutomationTarget::EffectParameter(ref target, effect,
+                    parameter) if target == &stage.target =>
+                    automated.push((effect, parameter)),
+                AutomationTarget::Volume(ref track) |
+                AutomationTarget::Pan(ref track) =>
+                    fader |= stage.target == EffectTarget::Track(
+                        track.clone()),
+                _ => (),
+            }
+        }
+        synth_defs.push(effects_to_synth_def(&stage.synth_def,
+            stage.effects, &automated, fader, &levels));
+    }
+    for (i, lane) in arrangement.get_automation_lanes().iter().enumerate() {
+        synth_defs.push(automation_to_synth_def(&format!(
+            "dawplAutomation_{}", i), lane,
+            arrangement.get_whole_note_seconds()));
+    }
+    if synth_defs.is_empty() {
+        return "".into();
+    }
+    format!(k_synth_defs_template!(), synth_defs=synth_defs.join("\n"))
+}
+
 /// Utility function used for translating a vector of strings, which represent
 /// variable names, to SuperCollider syntax for a list of those variables.
 // pub fn translate_variable_list(var_list: &Vec<String>) -> String {
@@ -64,33 +384,180 @@ pub fn track_to_super_collider(track: &Track) -> String {
 /// This function translates a single clip to its SuperCollider equivalent.
 /// Clip is the clip object itself, var is the name of the clip.
 pub fn clip_to_super_collider(clip: &Clip) -> String {
-    match clip {
+    tuned_clip_to_super_collider(clip, &Tuning::default(), CONCERT_A)
+}
+
+/// Translates a clip whose notes sound in the given tuning and concert pitch
+/// (frequency of A4). Clips in twelve tone equal temperament at A=440 are
+/// played by MIDI value (\midinote), all other clips, and clips with detuned
+/// notes, are played by frequency (\freq).
+pub fn tuned_clip_to_super_collider(clip: &Clip, tuning: &Tuning, a4_hz: f64)
+    -> String {
+    match *clip {
         // Melody and durations must be of equivalent length
-        &Clip::Instrument(ref var, ref name, ref melody, ref durations) => {
+        Clip::Instrument(ref var, ref name, ref melody, ref durations) => {
             assert_eq!(melody.len(), durations.len());
-            let mut midi_note_str: String = "[".into();
-            for i in 0..melody.len() {
-                match melody[i] {
-                    Some(ref notes) => {
-                        let note_str = format!("{:?},", notes);
-                        midi_note_str += &note_str[..];
-                    },
-                    None => {
-                        midi_note_str += "note:Rest(),";
-                    }
-                }
+            if plays_by_frequency(melody, tuning, a4_hz) {
+                return format!(k_instrument_freq_template!(), var_name=var,
+                    instrument_name=name.get_name(),
+                    dur=duration_list(durations),
+                    freqs=frequency_list(melody, tuning, a4_hz));
             }
-            midi_note_str += "]";
             format!(k_instrument_template!(), var_name=var, 
-            instrument_name=name, dur=format!("{:?}", durations),
-            midi_notes=midi_note_str)
+            instrument_name=name.get_name(), dur=duration_list(durations),
+            midi_notes=midi_note_list(melody))
         },
-        &Clip::File(ref var, ref name) => {
-            // TODO: fill this in
-            "".into()
+        Clip::File(ref var, ref path) => {
+            format!(k_audio_file_template!(), var_name=var,
+                filepath=string_literal(path))
+        },
+        Clip::Empty(ref var, ref duration) => {
+            format!(k_empty_clip_template!(), dur=duration.to_f64(),
+                var_name=var)
+        },
+        Clip::Drums(ref var, ref pattern) => {
+            let lanes: Vec<String> = pattern.lanes.iter()
+                .map(|lane| drum_lane_to_super_collider(lane, pattern.step))
+                .collect();
+            format!(k_drum_clip_template!(), var_name=var,
+                lanes=lanes.join(",\n"))
+        },
+    }
+}
+
+/// Translates a clip of an arrangement in the arrangement's tuning, with the
+/// transforms the arrangement puts on it
+pub fn arranged_clip_to_super_collider(clip: &Clip, arrangement: &Arrangement)
+    -> String {
+    let (tuning, a4_hz) = (arrangement.get_tuning(),
+        arrangement.get_concert_pitch());
+    let transforms = arrangement.get_transforms(&clip.get_name());
+    match *clip {
+        Clip::Instrument(ref var, ref instrument, ref melody, ref durations)
+            if !transforms.is_empty() => {
+            let length: Duration = durations.iter().cloned().sum();
+            let mut events = perform_clip(durations, transforms);
+            let mut melody = melody.clone();
+            // a rest until the first event, if it is played late
+            if let Some(first) = events.first().cloned() {
+                if !first.start.is_zero() {
+                    events.insert(0, PlayedEvent {start: Time::zero(),
+                        duration: Duration::zero(), velocity: 0});
+                    melody.insert(0, None);
+                }
+            }
+            let amp = instrument_amp(instrument);
+            let (mut dur, mut sustain, mut amps) = (Vec::new(), Vec::new(),
+                Vec::new());
+            for (i, event) in events.iter().enumerate() {
+                let next = events.get(i + 1).map_or(length, |next| next.start)
+                    .max(event.start);
+                dur.push(next - event.start);
+                sustain.push(event.duration.to_f64() * LEGATO);
+                amps.push(amp * event.velocity as f64 /
+                    DEFAULT_VELOCITY as f64);
+            }
+            let (key, notes) = if plays_by_frequency(&melody, tuning, a4_hz) {
+                ("freq", frequency_list(&melody, tuning, a4_hz))
+            } else {
+                ("midinote", midi_note_list(&melody))
+            };
+            format!(k_transformed_instrument_template!(), var_name=var,
+                instrument_name=instrument.get_name(),
+                dur=duration_list(&dur), sustain=number_list(&sustain),
+                amps=number_list(&amps), key=key, notes=notes)
         },
-        &Clip::Empty(ref var, ref duration) => {
-            format!(k_empty_clip_template!(), dur=duration, var_name=var)
+        _ => tuned_clip_to_super_collider(clip, tuning, a4_hz),
+    }
+}
+
+/// The amplitude an instrument plays at, its amp parameter
+fn instrument_amp(instrument: &InstrumentHandle) -> f64 {
+    instrument.get_instrument().get_parameters().iter()
+        .find(|parameter| parameter.0 == "amp")
+        .map_or(1.0, |parameter| parameter.1)
+}
+
+/// Translates a lane of a drum pattern to a Pbind whose steps play at the
+/// amplitude of the lane's instrument scaled by their velocity (the default
+/// velocity plays at the instrument's own amplitude), and with their
+/// probability
+fn drum_lane_to_super_collider(lane: &DrumLane, step: Duration) -> String {
+    let amp = instrument_amp(&lane.instrument);
+    let steps: Vec<String> = lane.steps.iter().map(|drum_step| {
+        if drum_step.is_rest() {
+            return "Rest()".into();
+        }
+        let level = amp * drum_step.velocity as f64 / DEFAULT_VELOCITY as f64;
+        if drum_step.probability >= 1.0 {
+            level.to_string()
+        } else {
+            format!(k_drum_chance_template!(), amp=level,
+                probability=drum_step.probability,
+                rest_probability=1.0 - drum_step.probability)
+        }
+    }).collect();
+    format!(k_drum_lane_template!(), instrument_name=lane.instrument
+        .get_name(), dur=step.to_f64(), midi_note=lane.key,
+        amps=steps.join(", "))
+}
+
+/// Writes durations as a SuperCollider list of whole notes e.g. [1, 0.25]
+fn duration_list(durations: &[Duration]) -> String {
+    let numbers: Vec<String> = durations.iter()
+        .map(|duration| duration.to_f64().to_string()).collect();
+    format!("[{}]", numbers.join(", "))
+}
+
+/// Writes numbers as a SuperCollider list e.g. [0.2, 0.1]
+fn number_list(numbers: &[f64]) -> String {
+    let numbers: Vec<String> = numbers.iter()
+        .map(|number| number.to_string()).collect();
+    format!("[{}]", numbers.join(", "))
+}
+
+/// Writes a SuperCollider string literal, escaping quotes and backslashes
+fn string_literal(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// True if the notes of a clip cannot be played by MIDI value
+fn plays_by_frequency(melody: &[Option<Vec<Note>>], tuning: &Tuning,
+    a4_hz: f64) -> bool {
+    !tuning.is_equal_temperament() || a4_hz != CONCERT_A ||
+        melody.iter().flatten().flatten().any(|note| note.get_cents() != 0)
+}
+
+/// Translates the notes of a clip to a SuperCollider list of MIDI values
+fn midi_note_list(melody: &[Option<Vec<Note>>]) -> String {
+    let mut midi_note_str: String = "[".into();
+    for notes in melody.iter() {
+        match *notes {
+            Some(ref notes) => midi_note_str += &format!("{:?},", notes.iter()
+                .map(|note| note.get_midi_value()).collect::<Vec<i8>>()),
+            None => midi_note_str += "Rest(),",
+        }
+    }
+    midi_note_str += "]";
+    midi_note_str
+}
+
+/// Translates the notes of a clip to a SuperCollider list of frequencies,
+/// tuned as they are spelled. Notes the tuning cannot play are turned into
+/// rests.
+fn frequency_list(melody: &[Option<Vec<Note>>], tuning: &Tuning, a4_hz: f64)
+    -> String {
+    let mut freq_str: String = "[".into();
+    for notes in melody.iter() {
+        let freqs: Option<Vec<String>> = notes.as_ref().and_then(|notes| {
+            notes.iter().map(|note| tuning.frequency(note, a4_hz)
+                .map(|freq| format!("{:.3}", freq))).collect()
+        });
+        match freqs {
+            Some(freqs) => freq_str += &format!("[{}],", freqs.join(", ")),
+            None => freq_str += "Rest(),",
         }
     }
+    freq_str += "]";
+    freq_str
 }
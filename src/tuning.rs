@@ -0,0 +1,403 @@
+/// Defines tunings, which decide the frequency each note sounds at. DAWPL's
+/// notes are spelled in the familiar twelve-tone system, and a tuning maps
+/// them onto frequencies: twelve-tone equal temperament (the default), other
+/// equal divisions of the octave (EDO-N), just intonation ratios, or a scale
+/// and keyboard mapping loaded from Scala .scl/.kbm files.
+use note::*;
+
+/// Pitch bend range (in half steps either way) assumed for MIDI output
+pub const PITCH_BEND_RANGE: f64 = 2.0;
+/// Largest number of keys in a keyboard mapping, and largest degree it maps
+/// a key to, read from .kbm files
+const MAX_MAPPING_SIZE: i64 = 1024;
+
+// 5-limit just intonation ratios for each half step above the tonic
+const JUST_RATIOS: [(u32, u32); 12] = [(1, 1), (16, 15), (9, 8), (6, 5),
+    (5, 4), (4, 3), (45, 32), (3, 2), (8, 5), (5, 3), (9, 5), (15, 8)];
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Tuning {
+    /// Equal division of the octave into the given number of steps. Notes are
+    /// mapped onto steps through their spelling (a fifth is the step closest
+    /// to a 3/2, a whole step is two fifths less an octave), so in 19-EDO and
+    /// 31-EDO C# and Db are different pitches. A4 stays at the concert pitch.
+    Edo(u16),
+    /// Just intonation relative to a tonic pitch class, given as frequency
+    /// ratios for each of the twelve half steps above the tonic.
+    Just(Name, Vec<(u32, u32)>),
+    /// A scale and keyboard mapping loaded from Scala files
+    Scala(ScalaScale, KeyboardMapping),
+}
+
+/// A scale read from a Scala .scl file, kept as the size of each degree above
+/// the first in cents. The last degree is the period (usually the octave).
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ScalaScale {
+    description: String,
+    degrees: Vec<f64>,
+}
+
+/// A keyboard mapping read from a Scala .kbm file, which decides which MIDI
+/// key plays which scale degree and anchors the scale to a frequency.
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KeyboardMapping {
+    /// Keys between two repetitions of the mapping, 0 for a linear mapping
+    size: usize,
+    first_key: i8,
+    last_key: i8,
+    /// Key that plays the first degree of the scale
+    middle_key: i8,
+    reference_key: i8,
+    /// Frequency of the reference key, None to follow the concert pitch
+    reference_freq: Option<f64>,
+    /// Scale degree that is repeated as the formal octave of the mapping
+    octave_degree: usize,
+    /// Degree played by each key of the mapping, None for unmapped keys
+    #[cfg_attr(feature = "serialize", serde(with = "unmapped_keys"))]
+    mapping: Vec<Option<usize>>,
+}
+
+/// Unmapped keys are saved as "x", as they are written in .kbm files
+#[cfg(feature = "serialize")]
+mod unmapped_keys {
+    use project::*;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(mapping: &[Option<usize>], serializer: S)
+        -> Result<S::Ok, S::Error> {
+        write_list(mapping, "x").serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+        -> Result<Vec<Option<usize>>, D::Error> {
+        read_list(Vec::deserialize(deserializer)?, "x")
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Reads the lines of a Scala file, skipping "!" comments
+fn scala_lines(contents: &str) -> Vec<&str> {
+    contents.lines().filter(|line| !line.starts_with('!')).collect()
+}
+
+/// Parses a pitch from a .scl file. Pitches containing a period are in cents,
+/// all other pitches are ratios such as 3/2 (or whole numbers such as 2).
+fn parse_scala_pitch(pitch: &str) -> Result<f64, String> {
+    let pitch = pitch.split_whitespace().next()
+        .ok_or_else(|| "Missing pitch in scale".to_string())?;
+    let invalid = || format!("Invalid pitch in scale: {}", pitch);
+    if pitch.contains('.') {
+        return pitch.parse().map_err(|_| invalid());
+    }
+    let mut parts = pitch.splitn(2, '/');
+    let numerator: f64 = parts.next().unwrap().parse().map_err(|_| invalid())?;
+    let denominator: f64 = match parts.next() {
+        Some(denominator) => denominator.parse().map_err(|_| invalid())?,
+        None => 1.0,
+    };
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(ratio_to_cents(numerator / denominator))
+}
+
+pub fn ratio_to_cents(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
+}
+
+pub fn cents_to_ratio(cents: f64) -> f64 {
+    2.0_f64.powf(cents / 1200.0)
+}
+
+impl ScalaScale {
+    /// Parses the contents of a Scala .scl file
+    pub fn parse(contents: &str) -> Result<ScalaScale, String> {
+        let lines = scala_lines(contents);
+        if lines.len() < 2 {
+            return Err("Scale file is missing its description or size".into());
+        }
+        let description = lines[0].trim().to_string();
+        let size: usize = lines[1].trim().parse()
+            .map_err(|_| format!("Invalid scale size: {}", lines[1].trim()))?;
+        if size == 0 || lines.len() < size + 2 {
+            return Err(format!("Expected {} pitches in scale", size));
+        }
+        let degrees = lines[2..size + 2].iter()
+            .map(|line| parse_scala_pitch(line))
+            .collect::<Result<Vec<f64>, String>>()?;
+        Ok(ScalaScale {description, degrees})
+    }
+
+    pub fn get_description(&self) -> &String {
+        &self.description
+    }
+
+    /// Number of degrees per period
+    pub fn len(&self) -> usize {
+        self.degrees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.degrees.is_empty()
+    }
+
+    /// Size in cents of the given degree above the first one, which may lie
+    /// in another period
+    pub fn cents(&self, degree: i32) -> f64 {
+        let size = self.degrees.len() as i32;
+        let period = self.degrees[self.degrees.len() - 1];
+        let step = degree.rem_euclid(size);
+        let base = if step == 0 {
+            0.0
+        } else {
+            self.degrees[step as usize - 1]
+        };
+        degree.div_euclid(size) as f64 * period + base
+    }
+}
+
+impl KeyboardMapping {
+    /// The default Scala mapping: consecutive keys play consecutive degrees,
+    /// middle C plays the first degree and A4 sounds at the concert pitch.
+    pub fn linear(scale: &ScalaScale) -> KeyboardMapping {
+        KeyboardMapping {size: 0, first_key: 0, last_key: 127, middle_key: 60,
+            reference_key: A4_MIDI, reference_freq: None,
+            octave_degree: scale.len(), mapping: Vec::new()}
+    }
+
+    /// Parses the contents of a Scala .kbm file
+    pub fn parse(contents: &str) -> Result<KeyboardMapping, String> {
+        let lines = scala_lines(contents);
+        if lines.len() < 7 {
+            return Err("Keyboard mapping is missing header fields".into());
+        }
+        // a number from the first word of a line, within the given range
+        let field = |index: usize, max: i64| -> Result<i64, String> {
+            let value = lines[index].split_whitespace().next().unwrap_or("");
+            match value.parse() {
+                Ok(number) if (0..=max).contains(&number) => Ok(number),
+                _ => Err(format!("Invalid keyboard mapping field: {}",
+                    value)),
+            }
+        };
+        let key = |index: usize| field(index, MIDI_NUM as i64 - 1)
+            .map(|key| key as i8);
+        let size = field(0, MAX_MAPPING_SIZE)? as usize;
+        let reference_freq: f64 = lines[5].split_whitespace().next()
+            .unwrap_or("").parse().ok()
+            .filter(|freq: &f64| *freq > 0.0 && freq.is_finite())
+            .ok_or_else(|| "Invalid reference frequency".to_string())?;
+        let mut mapping = Vec::new();
+        for line in lines[7..].iter().take(size) {
+            let degree = line.split_whitespace().next().unwrap_or("x");
+            if degree == "x" {
+                mapping.push(None);
+            } else {
+                match degree.parse::<usize>() {
+                    Ok(degree) if degree as i64 <= MAX_MAPPING_SIZE =>
+                        mapping.push(Some(degree)),
+                    _ => return Err(format!("Invalid degree in keyboard \
+                        mapping: {}", degree)),
+                }
+            }
+        }
+        // missing entries at the end of the mapping are unmapped
+        mapping.resize(size, None);
+        Ok(KeyboardMapping {size, first_key: key(1)?, last_key: key(2)?,
+            middle_key: key(3)?, reference_key: key(4)?,
+            reference_freq: Some(reference_freq),
+            octave_degree: field(6, MAX_MAPPING_SIZE)? as usize, mapping})
+    }
+
+    /// Returns the scale degree (relative to the middle key) played by a key
+    fn degree(&self, key: i8) -> Option<i32> {
+        if key < self.first_key || key > self.last_key {
+            return None;
+        }
+        let offset = key as i32 - self.middle_key as i32;
+        if self.size == 0 {
+            return Some(offset);
+        }
+        let repetition = offset.div_euclid(self.size as i32);
+        let index = offset.rem_euclid(self.size as i32) as usize;
+        self.mapping[index].map(|degree|
+            repetition * self.octave_degree as i32 + degree as i32)
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning::Edo(NUM_TONES as u16)
+    }
+}
+
+impl Tuning {
+    /// Just intonation with 5-limit ratios above the given tonic
+    pub fn just(tonic: Name) -> Tuning {
+        Tuning::Just(tonic, JUST_RATIOS.to_vec())
+    }
+
+    /// Creates a tuning from the contents of a Scala .scl file and optionally
+    /// a .kbm keyboard mapping (the linear mapping is used otherwise)
+    pub fn from_scala(scl: &str, kbm: Option<&str>) -> Result<Tuning, String> {
+        let scale = ScalaScale::parse(scl)?;
+        let mapping = match kbm {
+            Some(kbm) => KeyboardMapping::parse(kbm)?,
+            None => KeyboardMapping::linear(&scale),
+        };
+        Ok(Tuning::Scala(scale, mapping))
+    }
+
+    /// Checks that an equal division has steps, that just intonation has a
+    /// positive ratio for each of the twelve half steps, and that a Scala
+    /// tuning has degrees and a mapping of its keys
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Tuning::Edo(0) =>
+                Err("An equal division of the octave into 0 steps".into()),
+            Tuning::Just(_, ref ratios) if ratios.len() != NUM_TONES as usize
+                || ratios.iter().any(|ratio| ratio.0 == 0 || ratio.1 == 0) =>
+                Err("Just intonation needs a positive ratio for each of the \
+                    twelve half steps".into()),
+            Tuning::Scala(ref scale, ref mapping) if scale.is_empty() ||
+                mapping.mapping.len() != mapping.size =>
+                Err(format!("Invalid Scala tuning \"{}\"",
+                    scale.get_description())),
+            _ => Ok(()),
+        }
+    }
+
+    /// True for twelve-tone equal temperament, where MIDI values alone
+    /// describe the pitch of every note
+    pub fn is_equal_temperament(&self) -> bool {
+        *self == Tuning::default()
+    }
+
+    /// Returns the frequency of a note in hertz given the frequency of A4,
+    /// taking its spelling and its detuning in cents into account. Returns
+    /// None for notes the tuning cannot play e.g. keys left unmapped by a
+    /// Scala keyboard mapping, or by tunings that do not validate. Keyboard
+    /// mappings that give their own reference frequency ignore the concert
+    /// pitch.
+    pub fn frequency(&self, note: &Note, a4_hz: f64) -> Option<f64> {
+        self.validate().ok()?;
+        let detune = cents_to_ratio(note.get_cents() as f64);
+        match *self {
+            Tuning::Edo(divisions) => {
+                let steps = edo_steps(note, divisions) -
+                    edo_steps(&Note::new(Name::A, 4), divisions);
+                Some(a4_hz * detune *
+                    cents_to_ratio(1200.0 * steps as f64 / divisions as f64))
+            },
+            Tuning::Just(tonic, ref ratios) => {
+                let tonic_note = Note::new(tonic, 4);
+                let distance = note.get_midi_value() as i32 -
+                    tonic_note.get_midi_value() as i32;
+                let (numerator, denominator) =
+                    ratios[distance.rem_euclid(NUM_TONES as i32) as usize];
+                let octaves = distance.div_euclid(NUM_TONES as i32);
+                Some(tonic_note.frequency(a4_hz)
+                    * numerator as f64 / denominator as f64
+                    * 2.0_f64.powi(octaves) * detune)
+            },
+            Tuning::Scala(ref scale, ref mapping) => {
+                let degree = mapping.degree(note.get_midi_value())?;
+                let reference = mapping.degree(mapping.reference_key)
+                    .unwrap_or(mapping.reference_key as i32
+                        - mapping.middle_key as i32);
+                let reference_freq = mapping.reference_freq.unwrap_or(a4_hz);
+                Some(reference_freq * detune * cents_to_ratio(
+                    scale.cents(degree) - scale.cents(reference)))
+            },
+        }
+    }
+
+    /// Returns the frequency of a MIDI key, such as the key of a drum hit or
+    /// a key retuned by MIDI Tuning Standard messages. The key is spelled the
+    /// default way before it is tuned.
+    pub fn key_frequency(&self, midi_value: i8, a4_hz: f64) -> Option<f64> {
+        self.frequency(&Note::from_midi(midi_value), a4_hz)
+    }
+
+    /// Returns the nearest equal-tempered MIDI key and the pitch bend (from
+    /// -8192 to 8191, assuming a range of PITCH_BEND_RANGE half steps) that
+    /// make a MIDI instrument tuned to A=440 sound the given note in this
+    /// tuning at the given concert pitch.
+    pub fn pitch_bend(&self, note: &Note, a4_hz: f64) -> Option<(i8, i16)> {
+        let freq = self.frequency(note, a4_hz)?;
+        let exact = A4_MIDI as f64 + ratio_to_cents(freq / CONCERT_A) / 100.0;
+        let key = exact.round().clamp(0.0, 127.0);
+        Some((key as i8, bend_to(exact - key)))
+    }
+
+    /// Returns MIDI Tuning Standard (real-time single note tuning change)
+    /// system exclusive messages that retune all 128 keys of a receiving
+    /// instrument to this tuning at the given concert pitch. Unmapped keys are
+    /// left unchanged.
+    pub fn mts_messages(&self, device_id: u8, program: u8, a4_hz: f64)
+        -> Vec<Vec<u8>> {
+        let mut changes: Vec<[u8; 4]> = Vec::new();
+        for key in 0..MIDI_NUM {
+            let freq = match self.key_frequency(key as i8, a4_hz) {
+                Some(freq) => freq,
+                None => continue,
+            };
+            let exact = A4_MIDI as f64
+                + ratio_to_cents(freq / CONCERT_A) / 100.0;
+            let exact = exact.clamp(0.0, 127.99);
+            let semitone = exact.floor();
+            let fraction = ((exact - semitone) * 16384.0).round().min(16383.0)
+                as u16;
+            changes.push([key, semitone as u8, (fraction >> 7) as u8,
+                (fraction & 0x7f) as u8]);
+        }
+        // each message can hold at most 127 key changes
+        changes.chunks(127).map(|chunk| {
+            let mut message = vec![0xf0, 0x7f, device_id & 0x7f, 0x08, 0x02,
+                program & 0x7f, chunk.len() as u8];
+            for change in chunk.iter() {
+                message.extend(change.iter());
+            }
+            message.push(0xf7);
+            message
+        }).collect()
+    }
+}
+
+/// The pitch bend that moves a key by the given number of half steps, within
+/// PITCH_BEND_RANGE
+pub fn bend_to(half_steps: f64) -> i16 {
+    (half_steps / PITCH_BEND_RANGE * 8192.0).round().clamp(-8192.0, 8191.0)
+        as i16
+}
+
+/// Deserializes a tuning that validates, for the tuning of an arrangement
+#[cfg(feature = "serialize")]
+pub fn deserialize_valid<'de, D: ::serde::Deserializer<'de>>(deserializer: D)
+    -> Result<Tuning, D::Error> {
+    use serde::Deserialize;
+    use serde::de::Error;
+    let tuning = Tuning::deserialize(deserializer)?;
+    tuning.validate().map_err(D::Error::custom)?;
+    Ok(tuning)
+}
+
+/// Returns the number of steps from C-1 to a note in the given EDO, following
+/// the note's spelling through the sizes of fifths and whole steps.
+fn edo_steps(note: &Note, divisions: u16) -> i32 {
+    let divisions = divisions as i32;
+    let fifth = (divisions as f64 * 1.5_f64.log2()).round() as i32;
+    let whole_step = 2 * fifth - divisions;
+    let half_step = 3 * divisions - 5 * fifth; // e.g. E to F
+    let sharp = whole_step - half_step; // e.g. F to F#
+    let letter_steps = [0, whole_step, 2 * whole_step, 2 * whole_step +
+        half_step, 3 * whole_step + half_step, 4 * whole_step + half_step,
+        5 * whole_step + half_step];
+    (note.get_octave() as i32 + 1) * divisions
+        + letter_steps[note.get_letter().index() as usize]
+        + note.get_accidental().half_steps() as i32 * sharp
+}